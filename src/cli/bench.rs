@@ -0,0 +1,106 @@
+use crate::{
+    cli::{
+        dirs::{BodiesDbPath, HeadersDbPath},
+        execution::LocalExecution,
+    },
+    sync::StateSync,
+};
+use clap::Parser;
+use reth::dirs::PlatformPath;
+use reth_primitives::{BlockNumber, ChainSpec};
+use reth_staged_sync::utils::chainspec::genesis_value_parser;
+use std::{path::PathBuf, time::Instant};
+use tracing::*;
+
+/// Measure the execution throughput over a fixed block range.
+///
+/// The headers and bodies databases must already be synced up to the end of the range and are
+/// opened read-only.
+/// The state is executed from genesis into a fresh database, so that the runs are comparable.
+#[derive(Debug, Parser)]
+pub struct Command {
+    #[arg(long, value_name = "PATH", verbatim_doc_comment, default_value_t)]
+    headers_db: PlatformPath<HeadersDbPath>,
+
+    #[arg(long, value_name = "PATH", verbatim_doc_comment, default_value_t)]
+    bodies_db: PlatformPath<BodiesDbPath>,
+
+    /// The path to the fresh state database. Defaults to a temporary directory that is removed
+    /// once the benchmark is done.
+    #[arg(long, value_name = "PATH", verbatim_doc_comment)]
+    state_db: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "CHAIN_OR_PATH",
+        verbatim_doc_comment,
+        default_value = "mainnet",
+        value_parser = genesis_value_parser
+    )]
+    chain: ChainSpec,
+
+    /// The last block of the benchmarked range.
+    #[arg(long, value_name = "BLOCK_NUMBER")]
+    to: BlockNumber,
+
+    /// The number of blocks executed before the state is committed.
+    #[arg(long, value_name = "NUM", default_value_t = 10_000)]
+    commit_threshold: u64,
+}
+
+impl Command {
+    /// Execute `bench` command
+    pub async fn execute(self) -> eyre::Result<()> {
+        let local = LocalExecution::open(
+            &self.headers_db,
+            &self.bodies_db,
+            self.state_db.as_deref(),
+            &self.chain,
+            self.to,
+        )?;
+
+        let mut state_sync = StateSync::new(
+            local.db.headers(),
+            local.db.bodies(),
+            local.db.state(),
+            self.commit_threshold,
+            self.chain.clone(),
+        );
+        let progress = state_sync.get_progress()?;
+        if progress != 0 {
+            eyre::bail!(
+                "The state database at {} is not fresh, found execution progress at block #{}",
+                local.db.state_path.display(),
+                progress,
+            )
+        }
+
+        info!(target: "reth::cli", to = self.to, commit_threshold = self.commit_threshold, "Starting benchmark");
+        let started_at = Instant::now();
+        state_sync.run(1..=self.to).await?;
+        let elapsed = started_at.elapsed().as_secs_f64();
+
+        let metrics = state_sync.metrics();
+        let summary = serde_json::json!({
+            "from": 1,
+            "to": self.to,
+            "commit_threshold": self.commit_threshold,
+            "blocks": metrics.blocks,
+            "transactions": metrics.transactions,
+            "gas": metrics.gas,
+            "elapsed_secs": elapsed,
+            "blocks_per_sec": metrics.blocks as f64 / elapsed,
+            "gas_per_sec": metrics.gas as f64 / elapsed,
+            "stages_secs": {
+                "body_read": metrics.body_read.as_secs_f64(),
+                "sender_recovery": metrics.sender_recovery.as_secs_f64(),
+                "execution": metrics.execution.as_secs_f64(),
+                "apply": metrics.apply.as_secs_f64(),
+                "commit": metrics.commit.as_secs_f64(),
+            },
+        });
+        println!("{}", serde_json::to_string_pretty(&summary)?);
+
+        Ok(())
+    }
+}
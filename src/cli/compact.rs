@@ -0,0 +1,74 @@
+use crate::{
+    cli::dirs::{BodiesDbPath, HeadersDbPath, StateDbPath},
+    database::{copy_database, DatabaseGeometry, DatabaseInitializer, Stage, MDBX_DAT, MDBX_LCK},
+};
+use clap::Parser;
+use reth::dirs::PlatformPath;
+use reth_db::mdbx::EnvKind;
+use std::{fs, io, path::Path};
+use tracing::*;
+
+/// Compact the database by copying its live entries into a new file, and swap it in.
+///
+/// Deleted entries leave free pages behind that the database file does not shrink by, which is
+/// worth reclaiming before the database is published as a snapshot.
+#[derive(Debug, Parser)]
+pub struct Command {
+    /// The stage database to compact: headers, bodies or state.
+    stage: Stage,
+
+    #[arg(long, value_name = "PATH", verbatim_doc_comment, default_value_t)]
+    headers_db: PlatformPath<HeadersDbPath>,
+
+    #[arg(long, value_name = "PATH", verbatim_doc_comment, default_value_t)]
+    bodies_db: PlatformPath<BodiesDbPath>,
+
+    #[arg(long, value_name = "PATH", verbatim_doc_comment, default_value_t)]
+    state_db: PlatformPath<StateDbPath>,
+}
+
+impl Command {
+    /// Execute `compact` command
+    pub async fn execute(self) -> eyre::Result<()> {
+        let path: &Path = match self.stage {
+            Stage::Headers => self.headers_db.as_ref(),
+            Stage::Bodies => self.bodies_db.as_ref(),
+            Stage::State => self.state_db.as_ref(),
+        };
+        let compacted_path = path.with_extension("compact");
+        if compacted_path.exists() {
+            eyre::bail!(
+                "Leftover compaction found at {}, remove it first",
+                compacted_path.display()
+            )
+        }
+
+        // Make sure no sync writes to the database while it is being copied.
+        let _lock = DatabaseInitializer::new(self.stage).with_path(path).lock()?;
+
+        let before = fs::metadata(path.join(MDBX_DAT))?.len();
+        info!(target: "reth::cli", stage = %self.stage, path = %path.display(), before, "Compacting database");
+        {
+            let source = DatabaseInitializer::new(self.stage).with_path(path).open_read_only()?;
+            // The database keeps the size limits it was created with, and any tables besides the
+            // stage ones.
+            fs::create_dir_all(&compacted_path)?;
+            let target = DatabaseGeometry::of(&source)?.open(&compacted_path, EnvKind::RW)?;
+            copy_database(&source, &target)?;
+        }
+
+        fs::rename(compacted_path.join(MDBX_DAT), path.join(MDBX_DAT))?;
+        fs::remove_dir_all(&compacted_path)?;
+        // The lock file describes the replaced database file, and is created anew on the next open.
+        match fs::remove_file(path.join(MDBX_LCK)) {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error.into()),
+            _ => {}
+        }
+
+        let after = fs::metadata(path.join(MDBX_DAT))?.len();
+        info!(target: "reth::cli", before, after, reclaimed = before.saturating_sub(after), "Database compacted");
+        println!("{}: {before} -> {after} bytes", self.stage);
+
+        Ok(())
+    }
+}
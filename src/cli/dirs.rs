@@ -1,4 +1,4 @@
-use reth::dirs::{data_dir, XdgPath};
+use reth::dirs::{config_dir, data_dir, XdgPath};
 use std::path::PathBuf;
 
 #[derive(Default, Debug, Clone)]
@@ -27,3 +27,12 @@ impl XdgPath for StateDbPath {
         data_dir().map(|root| root.join("state-db"))
     }
 }
+
+#[derive(Default, Debug, Clone)]
+pub struct LightConfigPath;
+
+impl XdgPath for LightConfigPath {
+    fn resolve() -> Option<PathBuf> {
+        config_dir().map(|root| root.join("reth-light.toml"))
+    }
+}
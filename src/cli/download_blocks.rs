@@ -0,0 +1,124 @@
+use crate::{
+    cli::{dirs::HeadersDbPath, sync::spawn_network},
+    database::{DatabaseInitializer, Stage},
+    sync::BodiesSync,
+};
+use clap::Parser;
+use eyre::Context;
+use reth::{
+    args::NetworkArgs,
+    dirs::{ConfigPath, PlatformPath},
+    runner::CliContext,
+};
+use reth_consensus::beacon::BeaconConsensus;
+use reth_db::database::Database;
+use reth_downloaders::bodies::bodies::BodiesDownloaderBuilder;
+use reth_primitives::{BlockNumber, ChainSpec, Head};
+use reth_provider::test_utils::NoopProvider;
+use reth_staged_sync::{utils::chainspec::genesis_value_parser, Config};
+use reth_stages::stages::HEADERS;
+use std::{
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tracing::*;
+
+/// Download the bodies of the listed blocks only, into a separate bodies database.
+///
+/// The headers of the blocks must already be in the local headers database. The bodies are
+/// stored without the ones in between, so the output database holds no progress and must not be
+/// used as the bodies database of the sync.
+#[derive(Debug, Parser)]
+pub struct Command {
+    #[arg(long, value_name = "FILE", verbatim_doc_comment, default_value_t)]
+    config: PlatformPath<ConfigPath>,
+
+    #[arg(long, value_name = "PATH", verbatim_doc_comment, default_value_t)]
+    headers_db: PlatformPath<HeadersDbPath>,
+
+    /// The bodies database the downloaded bodies are stored in. Created if it does not exist.
+    /// The blocks already stored in it are skipped.
+    #[arg(long, value_name = "PATH", verbatim_doc_comment)]
+    output: PathBuf,
+
+    #[arg(
+        long,
+        value_name = "CHAIN_OR_PATH",
+        verbatim_doc_comment,
+        default_value = "mainnet",
+        value_parser = genesis_value_parser
+    )]
+    chain: ChainSpec,
+
+    #[clap(flatten)]
+    network: NetworkArgs,
+
+    /// The number of seconds to wait for a peer to connect before giving up.
+    #[arg(long, value_name = "SECONDS", default_value_t = 120)]
+    peers_timeout: u64,
+
+    /// The comma-separated numbers of the blocks to download.
+    #[arg(value_name = "BLOCKS", value_delimiter = ',', required = true)]
+    blocks: Vec<BlockNumber>,
+}
+
+impl Command {
+    /// Execute `download-blocks` command
+    pub async fn execute(self, ctx: CliContext) -> eyre::Result<()> {
+        let headers = DatabaseInitializer::new(Stage::Headers)
+            .with_path(&self.headers_db)
+            .open_read_only()?;
+        let headers_progress = HEADERS.get_progress(&headers.tx()?)?.unwrap_or_default();
+        if let Some(block) = self.blocks.iter().copied().find(|block| *block > headers_progress) {
+            eyre::bail!(
+                "The headers are only synced up to block #{headers_progress}, requested #{block}"
+            )
+        }
+        let bodies =
+            DatabaseInitializer::new(Stage::Bodies).with_path(&self.output).create_local()?;
+
+        let mut config: Config =
+            confy::load_path(&self.config).wrap_err("Could not load config")?;
+        config.peers.connect_trusted_nodes_only = self.network.trusted_only;
+        config.peers.trusted_nodes.extend(self.network.trusted_peers.iter().copied());
+
+        let head = Head {
+            number: 0,
+            hash: self.chain.genesis_hash(),
+            timestamp: self.chain.genesis.timestamp,
+            difficulty: self.chain.genesis.difficulty,
+            total_difficulty: self.chain.genesis.difficulty,
+        };
+        let network_config = self
+            .network
+            .network_config(&config, self.chain.clone())
+            .with_task_executor(Box::new(ctx.task_executor.clone()))
+            .set_head(head)
+            .build(NoopProvider::default());
+        let network =
+            spawn_network(network_config, &ctx.task_executor, self.network.persistent_peers_file())
+                .await?;
+        info!(target: "reth::cli", peer_id = %network.peer_id(), "Connected to P2P network");
+
+        let started_at = Instant::now();
+        while network.num_connected_peers() == 0 {
+            if started_at.elapsed() >= Duration::from_secs(self.peers_timeout) {
+                eyre::bail!("No peers connected within {}s", self.peers_timeout)
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+
+        let (consensus, _forkchoice_state_tx) =
+            BeaconConsensus::builder().build(self.chain.clone());
+        let fetch_client = Arc::new(network.fetch_client().await?);
+        let body_downloader = BodiesDownloaderBuilder::from(config.stages.bodies)
+            .build(fetch_client, consensus, headers)
+            .into_task_with(&ctx.task_executor);
+
+        let mut bodies_sync = BodiesSync::new(bodies, body_downloader, self.chain.clone());
+        bodies_sync.download_blocks(&self.blocks).await?;
+        info!(target: "reth::cli", output = %self.output.display(), "Bodies downloaded");
+        Ok(())
+    }
+}
@@ -0,0 +1,60 @@
+use crate::database::{DatabaseInitializer, SplitDatabase, Stage};
+use reth_db::database::Database;
+use reth_primitives::{BlockNumber, ChainSpec};
+use reth_stages::stages::{BODIES, HEADERS};
+use std::path::Path;
+use tempfile::TempDir;
+
+/// The databases of an execution over the local headers and bodies, without the network.
+pub(crate) struct LocalExecution {
+    /// The read-only headers and bodies, and the state executed into.
+    pub(crate) db: SplitDatabase,
+    /// The temporary directory of the state database, removed after the database is closed.
+    _state_dir: Option<TempDir>,
+}
+
+impl LocalExecution {
+    /// Open the headers and bodies read-only and check that they are synced up to block `to`.
+    /// The state database is initialized at `state_db`, or in a temporary directory if none is
+    /// given.
+    pub(crate) fn open(
+        headers_db: impl AsRef<Path>,
+        bodies_db: impl AsRef<Path>,
+        state_db: Option<&Path>,
+        chain_spec: &ChainSpec,
+        to: BlockNumber,
+    ) -> eyre::Result<Self> {
+        let headers = DatabaseInitializer::new(Stage::Headers)
+            .with_path(headers_db.as_ref())
+            .open_read_only()?;
+        let bodies = DatabaseInitializer::new(Stage::Bodies)
+            .with_path(bodies_db.as_ref())
+            .open_read_only()?;
+
+        let headers_progress = HEADERS.get_progress(&headers.tx()?)?.unwrap_or_default();
+        let bodies_progress = BODIES.get_progress(&bodies.tx()?)?.unwrap_or_default();
+        if headers_progress < to || bodies_progress < to {
+            eyre::bail!(
+                "The execution requires headers and bodies up to block #{}, found headers at #{} \
+                 and bodies at #{}",
+                to,
+                headers_progress,
+                bodies_progress,
+            )
+        }
+
+        let (state_path, state_dir) = match state_db {
+            Some(path) => (path.to_owned(), None),
+            None => {
+                let dir = tempfile::tempdir()?;
+                (dir.path().to_owned(), Some(dir))
+            }
+        };
+        let state = DatabaseInitializer::new(Stage::State)
+            .with_path(&state_path)
+            .init_local(chain_spec.clone())?;
+
+        let db = SplitDatabase::new(headers_db, headers, bodies_db, bodies, state_path, state);
+        Ok(Self { db, _state_dir: state_dir })
+    }
+}
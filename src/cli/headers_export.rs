@@ -0,0 +1,106 @@
+use crate::{
+    cli::dirs::HeadersDbPath,
+    database::{DatabaseInitializer, Stage},
+};
+use clap::Parser;
+use reth::dirs::PlatformPath;
+use reth_db::{cursor::DbCursorRO, database::Database, tables, transaction::DbTx};
+use reth_primitives::{BlockNumber, H256};
+use reth_provider::ProviderError;
+use reth_rlp::Encodable;
+use reth_stages::stages::HEADERS;
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::PathBuf,
+};
+use tracing::*;
+
+/// Export the canonical headers over the range as a single RLP list of headers.
+///
+/// Each header is encoded with the fields of its own hardfork, so the file can span the header
+/// format changes. The exported headers are checked against the canonical hashes and to link to
+/// each other, so that another tool only needs to check that the first one links to a trusted
+/// checkpoint.
+#[derive(Debug, Parser)]
+pub struct Command {
+    #[arg(long, value_name = "PATH", verbatim_doc_comment, default_value_t)]
+    headers_db: PlatformPath<HeadersDbPath>,
+
+    /// The first exported block.
+    #[arg(long, value_name = "BLOCK_NUMBER", default_value_t = 0)]
+    from: BlockNumber,
+
+    /// The last exported block. Defaults to the headers progress.
+    #[arg(long, value_name = "BLOCK_NUMBER")]
+    to: Option<BlockNumber>,
+
+    /// The file to write the headers to.
+    #[arg(long, value_name = "FILE")]
+    output: PathBuf,
+}
+
+impl Command {
+    /// Execute `headers-export` command
+    pub async fn execute(self) -> eyre::Result<()> {
+        let db = DatabaseInitializer::new(Stage::Headers)
+            .with_path(&self.headers_db)
+            .open_read_only()?;
+        let tx = db.tx()?;
+
+        let progress = HEADERS.get_progress(&tx)?.unwrap_or_default();
+        let to = self.to.unwrap_or(progress);
+        if to > progress {
+            eyre::bail!("The headers are only synced up to block #{progress}, requested #{to}")
+        }
+        if self.from > to {
+            eyre::bail!("Invalid range: #{} is after #{to}", self.from)
+        }
+        let range = self.from..=to;
+
+        // The list header needs the total length of the encoded headers, so the headers are
+        // walked twice instead of being held in memory.
+        let mut payload_length = 0;
+        for entry in tx.cursor_read::<tables::Headers>()?.walk_range(range.clone())? {
+            payload_length += entry?.1.length();
+        }
+
+        let mut writer = BufWriter::new(File::create(&self.output)?);
+        let mut buf = Vec::new();
+        reth_rlp::Header { list: true, payload_length }.encode(&mut buf);
+        writer.write_all(&buf)?;
+
+        let mut canonical = tx.cursor_read::<tables::CanonicalHeaders>()?;
+        let mut parent_hash: Option<H256> = None;
+        let mut count = 0u64;
+        for entry in tx.cursor_read::<tables::Headers>()?.walk_range(range.clone())? {
+            let (number, header) = entry?;
+            let (_, hash) = canonical
+                .seek_exact(number)?
+                .ok_or(ProviderError::CanonicalHeader { block_number: number })?;
+            if header.hash_slow() != hash {
+                eyre::bail!("Header #{number} does not match the canonical hash {hash:?}")
+            }
+            if parent_hash.map_or(false, |parent| parent != header.parent_hash) {
+                eyre::bail!("Header #{number} does not link to the previous exported header")
+            }
+            if parent_hash.is_none() {
+                info!(target: "reth::cli", first = number, parent = ?header.parent_hash, "Exported headers link to the parent");
+            }
+
+            buf.clear();
+            header.encode(&mut buf);
+            writer.write_all(&buf)?;
+            parent_hash = Some(hash);
+            count += 1;
+        }
+        writer.flush()?;
+
+        let expected = to - self.from + 1;
+        if count != expected {
+            eyre::bail!("Expected {expected} headers in range {range:?}, found {count}")
+        }
+        info!(target: "reth::cli", count, last = to, last_hash = ?parent_hash, output = %self.output.display(), "Headers exported");
+        Ok(())
+    }
+}
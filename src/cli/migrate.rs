@@ -0,0 +1,112 @@
+use crate::{
+    cli::dirs::{BodiesDbPath, HeadersDbPath, StateDbPath},
+    database::{
+        copy_tables, DatabaseInitializer, Stage, BODIES_TABLES, HEADERS_TABLES, MDBX_DAT,
+        STATE_TABLES,
+    },
+};
+use clap::Parser;
+use reth::dirs::PlatformPath;
+use reth_db::{
+    cursor::{DbCursorRO, DbCursorRW},
+    database::Database,
+    mdbx::{Env, EnvKind, WriteMap},
+    tables,
+    transaction::{DbTx, DbTxMut},
+    Error as DatabaseError,
+};
+use reth_primitives::U256;
+use reth_stages::stages::{BODIES, EXECUTION, HEADERS, TOTAL_DIFFICULTY};
+use std::path::PathBuf;
+use tracing::*;
+
+/// Merge the split databases into a single database with the standard reth table layout.
+///
+/// The header number and total difficulty indices are derived from the headers. The other
+/// derived tables, such as the transaction senders and the hashed state, are left empty for the
+/// full node to build. Since the split state database has no changesets, the migrated state can
+/// not be unwound below the execution progress.
+#[derive(Debug, Parser)]
+pub struct Command {
+    #[arg(long, value_name = "PATH", verbatim_doc_comment, default_value_t)]
+    headers_db: PlatformPath<HeadersDbPath>,
+
+    #[arg(long, value_name = "PATH", verbatim_doc_comment, default_value_t)]
+    bodies_db: PlatformPath<BodiesDbPath>,
+
+    #[arg(long, value_name = "PATH", verbatim_doc_comment, default_value_t)]
+    state_db: PlatformPath<StateDbPath>,
+
+    /// The path of the new combined database.
+    #[arg(long, value_name = "PATH", verbatim_doc_comment)]
+    to: PathBuf,
+}
+
+impl Command {
+    /// Execute `migrate` command
+    pub async fn execute(self) -> eyre::Result<()> {
+        if self.to.join(MDBX_DAT).exists() {
+            eyre::bail!("The database at {} already exists", self.to.display())
+        }
+
+        let headers = DatabaseInitializer::new(Stage::Headers)
+            .with_path(&self.headers_db)
+            .open_read_only()?;
+        let bodies =
+            DatabaseInitializer::new(Stage::Bodies).with_path(&self.bodies_db).open_read_only()?;
+        let state =
+            DatabaseInitializer::new(Stage::State).with_path(&self.state_db).open_read_only()?;
+
+        std::fs::create_dir_all(&self.to)?;
+        let target = Env::<WriteMap>::open(&self.to, EnvKind::RW)?;
+        target.create_tables()?;
+
+        info!(target: "reth::cli", to = %self.to.display(), "Copying split databases");
+        copy_tables(&headers, &target, &HEADERS_TABLES)?;
+        copy_tables(&bodies, &target, &BODIES_TABLES)?;
+        copy_tables(&state, &target, &STATE_TABLES)?;
+
+        info!(target: "reth::cli", "Building header indices");
+        let headers_progress = HEADERS.get_progress(&headers.tx()?)?.unwrap_or_default();
+        target.update(|tx| {
+            let mut canonical_cursor = tx.cursor_read::<tables::CanonicalHeaders>()?;
+            let mut header_cursor = tx.cursor_read::<tables::Headers>()?;
+            let mut td_cursor = tx.cursor_write::<tables::HeaderTD>()?;
+
+            // The state database only holds the total difficulty at the execution progress.
+            tx.clear::<tables::HeaderTD>()?;
+
+            let mut td = U256::ZERO;
+            for entry in canonical_cursor.walk(None)? {
+                let (number, hash) = entry?;
+                let (_, header) =
+                    header_cursor.seek_exact(number)?.ok_or(DatabaseError::Read(-1))?;
+                td += header.difficulty;
+
+                tx.put::<tables::HeaderNumbers>(hash, number)?;
+                td_cursor.append(number, td.into())?;
+            }
+
+            // The total difficulty is known up to the headers progress.
+            TOTAL_DIFFICULTY.save_progress(tx, headers_progress)?;
+            Ok::<(), DatabaseError>(())
+        })??;
+
+        // Check that the stage progress was carried over from each of the split databases.
+        let tx = target.tx()?;
+        for (stage, expected) in [
+            (HEADERS, headers_progress),
+            (BODIES, BODIES.get_progress(&bodies.tx()?)?.unwrap_or_default()),
+            (EXECUTION, EXECUTION.get_progress(&state.tx()?)?.unwrap_or_default()),
+        ] {
+            let migrated = stage.get_progress(&tx)?.unwrap_or_default();
+            if migrated != expected {
+                eyre::bail!("Stage {stage} progress is #{migrated} after the migration, expected #{expected}")
+            }
+            println!("{stage}: #{migrated}");
+        }
+        println!("{TOTAL_DIFFICULTY}: #{headers_progress}");
+
+        Ok(())
+    }
+}
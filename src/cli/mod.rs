@@ -1,11 +1,26 @@
 use clap::{Parser, Subcommand};
 use reth::{
     cli::{Logs, Verbosity},
-    runner::CliRunner,
+    runner::{tokio_runtime, CliContext, CliRunner},
 };
+use reth_tasks::TaskManager;
+use std::future::Future;
 
+pub mod bench;
+pub mod compact;
 pub mod dirs;
+pub mod download_blocks;
+mod execution;
+pub mod headers_export;
+pub mod migrate;
+pub mod proof;
+pub mod remote;
+pub mod replay;
+pub mod snapshot;
+pub mod status;
 pub mod sync;
+pub mod verify_headers;
+pub mod verify_remotes;
 
 pub fn run() -> eyre::Result<()> {
     dotenv::dotenv().ok();
@@ -14,19 +29,95 @@ pub fn run() -> eyre::Result<()> {
     let (layer, _guard) = opt.logs.layer();
     reth_tracing::init(vec![layer, reth_tracing::stdout(opt.verbosity.directive())]);
 
-    let runner = CliRunner::default();
-
     match opt.command {
-        Commands::Sync(command) => runner.run_command_until_exit(|ctx| command.execute(ctx)),
+        Commands::Sync(command) => run_command_until_done(|ctx| command.execute(ctx)),
+        Commands::DownloadBlocks(command) => run_command_until_done(|ctx| command.execute(ctx)),
+        Commands::Status(command) => run_command(command.execute()),
+        Commands::Compact(command) => run_command(command.execute()),
+        Commands::Bench(command) => run_command(command.execute()),
+        Commands::Migrate(command) => run_command(command.execute()),
+        Commands::Replay(command) => run_command(command.execute()),
+        Commands::Snapshot(command) => run_command(command.execute()),
+        Commands::Proof(command) => run_command(command.execute()),
+        Commands::HeadersExport(command) => run_command(command.execute()),
+        Commands::VerifyHeaders(command) => run_command(command.execute()),
+        Commands::VerifyRemotes(command) => run_command(command.execute()),
     }
 }
 
+/// Execute the command that needs no task executor until it returns, or drop it on Ctrl-C.
+fn run_command(command: impl Future<Output = eyre::Result<()>>) -> eyre::Result<()> {
+    CliRunner::default().run_command_until_exit(|_| command)
+}
+
+/// Execute the command until it returns or any critical task panics.
+///
+/// Unlike [reth::runner::CliRunner], the command is not dropped on Ctrl-C and is expected to
+/// handle the interrupt signal itself.
+fn run_command_until_done<F>(command: impl FnOnce(CliContext) -> F) -> eyre::Result<()>
+where
+    F: Future<Output = eyre::Result<()>>,
+{
+    let tokio_runtime = tokio_runtime()?;
+    let mut task_manager = TaskManager::new(tokio_runtime.handle().clone());
+    let context = CliContext { task_executor: task_manager.executor() };
+
+    let fut = command(context);
+    tokio_runtime.block_on(async {
+        tokio::select! {
+            err = &mut task_manager => Err(err.into()),
+            res = fut => res,
+        }
+    })?;
+
+    // Fire the shutdown signal to all tasks spawned via the task executor
+    drop(task_manager);
+
+    // Drop the tokio runtime on a separate thread, since dropping it blocks until all of the
+    // blocking tasks are finished.
+    std::thread::spawn(move || drop(tokio_runtime));
+    Ok(())
+}
+
 /// Commands to be executed
 #[derive(Subcommand)]
 pub enum Commands {
     /// Start light sync
     #[command(name = "sync")]
     Sync(sync::Command),
+    /// Download the bodies of the listed blocks only
+    #[command(name = "download-blocks")]
+    DownloadBlocks(download_blocks::Command),
+    /// Show the published snapshots
+    #[command(name = "status")]
+    Status(status::Command),
+    /// Reclaim the free space of a database
+    #[command(name = "compact")]
+    Compact(compact::Command),
+    /// Measure the execution throughput over a fixed block range
+    #[command(name = "bench")]
+    Bench(bench::Command),
+    /// Merge the split databases into a single reth database
+    #[command(name = "migrate")]
+    Migrate(migrate::Command),
+    /// Re-execute the blocks from the local headers and bodies
+    #[command(name = "replay")]
+    Replay(replay::Command),
+    /// Publish snapshots of the local databases without syncing
+    #[command(name = "snapshot")]
+    Snapshot(snapshot::Command),
+    /// Print the Merkle proof of an account and its storage slots
+    #[command(name = "proof")]
+    Proof(proof::Command),
+    /// Export the canonical headers as an RLP list
+    #[command(name = "headers-export")]
+    HeadersExport(headers_export::Command),
+    /// Check the integrity of the local headers
+    #[command(name = "verify-headers")]
+    VerifyHeaders(verify_headers::Command),
+    /// Compare the snapshots published to two remote stores
+    #[command(name = "verify-remotes")]
+    VerifyRemotes(verify_remotes::Command),
 }
 
 #[derive(Parser)]
@@ -0,0 +1,103 @@
+use crate::{
+    cli::dirs::{HeadersDbPath, StateDbPath},
+    database::{account_proof, DatabaseInitializer, Stage},
+};
+use clap::Parser;
+use reth::dirs::PlatformPath;
+use reth_db::{database::Database, tables, transaction::DbTx};
+use reth_primitives::{Address, Bytes, H256, KECCAK_EMPTY, U256};
+use reth_provider::ProviderError;
+use reth_stages::stages::EXECUTION;
+use serde::Serialize;
+use tracing::*;
+
+/// Print the Merkle proof of the account and its storage slots at the latest synced block, in the
+/// form of the `eth_getProof` response.
+///
+/// The state database is opened read-only. The whole state trie is built in memory, so this is
+/// only feasible on the chains with a small state.
+#[derive(Debug, Parser)]
+pub struct Command {
+    /// The address of the account to prove.
+    address: Address,
+
+    /// The storage slot to prove. Can be passed multiple times.
+    #[arg(long = "slot", value_name = "SLOT", verbatim_doc_comment)]
+    slots: Vec<H256>,
+
+    #[arg(long, value_name = "PATH", verbatim_doc_comment, default_value_t)]
+    headers_db: PlatformPath<HeadersDbPath>,
+
+    #[arg(long, value_name = "PATH", verbatim_doc_comment, default_value_t)]
+    state_db: PlatformPath<StateDbPath>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ProofResponse {
+    block_number: u64,
+    state_root: H256,
+    address: Address,
+    balance: U256,
+    nonce: u64,
+    code_hash: H256,
+    storage_hash: H256,
+    account_proof: Vec<Bytes>,
+    storage_proof: Vec<StorageProofResponse>,
+}
+
+#[derive(Serialize)]
+struct StorageProofResponse {
+    key: H256,
+    value: U256,
+    proof: Vec<Bytes>,
+}
+
+impl Command {
+    /// Execute `proof` command
+    pub async fn execute(self) -> eyre::Result<()> {
+        let headers = DatabaseInitializer::new(Stage::Headers)
+            .with_path(&self.headers_db)
+            .open_read_only()?;
+        let state =
+            DatabaseInitializer::new(Stage::State).with_path(&self.state_db).open_read_only()?;
+
+        // Read the progress and the state from the same transaction, so that they are consistent
+        // while the sync keeps writing to the database.
+        let tx = state.tx()?;
+        let block_number = EXECUTION.get_progress(&tx)?.unwrap_or_default();
+        info!(target: "reth::cli", address = ?self.address, slots = self.slots.len(), block_number, "Building proof");
+        let proof = account_proof(&tx, self.address, &self.slots)?;
+
+        let header = headers
+            .view(|tx| tx.get::<tables::Headers>(block_number))??
+            .ok_or(ProviderError::Header { number: block_number })?;
+        if header.state_root != proof.state_root {
+            eyre::bail!(
+                "State root mismatch at block #{block_number}: the header has {:?}, the local \
+                 state has {:?}",
+                header.state_root,
+                proof.state_root,
+            )
+        }
+
+        let account = proof.account.unwrap_or_default();
+        let response = ProofResponse {
+            block_number,
+            state_root: proof.state_root,
+            address: proof.address,
+            balance: account.balance,
+            nonce: account.nonce,
+            code_hash: account.bytecode_hash.unwrap_or(KECCAK_EMPTY),
+            storage_hash: proof.storage_root,
+            account_proof: proof.proof,
+            storage_proof: proof
+                .storage_proofs
+                .into_iter()
+                .map(|p| StorageProofResponse { key: p.key, value: p.value, proof: p.proof })
+                .collect(),
+        };
+        println!("{}", serde_json::to_string_pretty(&response)?);
+        Ok(())
+    }
+}
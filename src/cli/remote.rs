@@ -0,0 +1,80 @@
+use crate::{
+    config::RemoteConfig,
+    remote::{spaces_store, RemoteBackend, RemoteStore, S3Store},
+};
+use aws_sdk_s3::Credentials;
+use clap::Args;
+use reth_primitives::Chain;
+
+/// Parameters of the remote store the snapshots are published to.
+///
+/// The parameters that are not set are taken from the light config.
+#[derive(Debug, Args)]
+#[command(next_help_heading = "Remote")]
+pub struct RemoteArgs {
+    /// The kind of host the bucket is on. Defaults to `spaces`.
+    #[arg(long = "remote.backend", value_enum)]
+    pub backend: Option<RemoteBackend>,
+
+    /// The region of the bucket.
+    #[arg(long = "remote.region", value_name = "REGION")]
+    pub region: Option<String>,
+
+    /// The bucket the snapshots are published to.
+    #[arg(long = "remote.bucket", value_name = "BUCKET")]
+    pub bucket: Option<String>,
+
+    /// The endpoint URL of the S3-compatible host. Defaults to the AWS S3 endpoint of the region
+    /// for the `s3` backend. If set for the `spaces` backend, it is used instead of the Spaces
+    /// endpoint and the region is not validated.
+    #[arg(long = "remote.endpoint", value_name = "URL", verbatim_doc_comment)]
+    pub endpoint: Option<String>,
+
+    /// The access key id. The credentials are read from the environment if not set.
+    #[arg(long = "remote.access-key-id", value_name = "KEY", requires = "secret_access_key")]
+    pub access_key_id: Option<String>,
+
+    /// The secret access key.
+    #[arg(long = "remote.secret-access-key", value_name = "SECRET", requires = "access_key_id")]
+    pub secret_access_key: Option<String>,
+}
+
+impl RemoteArgs {
+    /// Override the remote config with the parameters that are set.
+    fn apply(&self, config: RemoteConfig) -> RemoteConfig {
+        // The credentials are only overridden as a pair.
+        let (access_key_id, secret_access_key) =
+            match (&self.access_key_id, &self.secret_access_key) {
+                (Some(key), Some(secret)) => (Some(key.clone()), Some(secret.clone())),
+                _ => (config.access_key_id, config.secret_access_key),
+            };
+        RemoteConfig {
+            backend: self.backend.unwrap_or(config.backend),
+            region: self.region.clone().unwrap_or(config.region),
+            bucket: self.bucket.clone().unwrap_or(config.bucket),
+            endpoint: self.endpoint.clone().or(config.endpoint),
+            access_key_id,
+            secret_access_key,
+        }
+    }
+
+    /// Create the store for the snapshots of the chain, with the parameters that are not set
+    /// taken from the config.
+    pub async fn store(&self, chain: Chain, config: RemoteConfig) -> eyre::Result<RemoteStore> {
+        remote_store(chain, self.apply(config)).await
+    }
+}
+
+/// Create the store for the snapshots of the chain from the remote config alone.
+pub async fn remote_store(chain: Chain, config: RemoteConfig) -> eyre::Result<RemoteStore> {
+    let credentials = config
+        .access_key_id
+        .zip(config.secret_access_key)
+        .map(|(key, secret)| Credentials::from_keys(key, secret, None));
+    let (region, bucket) = (config.region, config.bucket);
+
+    match (config.backend, config.endpoint) {
+        (RemoteBackend::Spaces, None) => spaces_store(region, bucket, chain, credentials).await,
+        (_, endpoint) => S3Store::new(endpoint, region, bucket, chain, credentials).await,
+    }
+}
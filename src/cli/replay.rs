@@ -0,0 +1,98 @@
+use crate::{
+    cli::{
+        dirs::{BodiesDbPath, HeadersDbPath},
+        execution::LocalExecution,
+    },
+    sync::StateSync,
+};
+use clap::Parser;
+use reth::dirs::PlatformPath;
+use reth_primitives::{BlockNumber, ChainSpec};
+use reth_staged_sync::utils::chainspec::genesis_value_parser;
+use std::path::PathBuf;
+use tracing::*;
+
+/// Re-execute the blocks from the local headers and bodies, without connecting to the network.
+///
+/// The headers and bodies databases must already be synced up to the end of the range and are
+/// opened read-only.
+/// The state is executed into a fresh database by default, or resumed from the progress of the
+/// given one. The state root is checked against the headers along the way.
+#[derive(Debug, Parser)]
+pub struct Command {
+    #[arg(long, value_name = "PATH", verbatim_doc_comment, default_value_t)]
+    headers_db: PlatformPath<HeadersDbPath>,
+
+    #[arg(long, value_name = "PATH", verbatim_doc_comment, default_value_t)]
+    bodies_db: PlatformPath<BodiesDbPath>,
+
+    /// The path to the state database to execute into. Defaults to a temporary directory that is
+    /// removed once the replay is done.
+    #[arg(long, value_name = "PATH", verbatim_doc_comment)]
+    state_db: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "CHAIN_OR_PATH",
+        verbatim_doc_comment,
+        default_value = "mainnet",
+        value_parser = genesis_value_parser
+    )]
+    chain: ChainSpec,
+
+    /// The last block of the replayed range.
+    #[arg(long, value_name = "BLOCK_NUMBER")]
+    to: BlockNumber,
+
+    /// The number of blocks executed before the state is committed.
+    #[arg(long, value_name = "NUM", default_value_t = 10_000)]
+    commit_threshold: u64,
+
+    /// Check the state root against the header every N blocks.
+    #[arg(long, value_name = "BLOCKS", default_value_t = 10_000)]
+    verify_every: u64,
+}
+
+impl Command {
+    /// Execute `replay` command
+    pub async fn execute(self) -> eyre::Result<()> {
+        let local = LocalExecution::open(
+            &self.headers_db,
+            &self.bodies_db,
+            self.state_db.as_deref(),
+            &self.chain,
+            self.to,
+        )?;
+
+        let mut state_sync = StateSync::new(
+            local.db.headers(),
+            local.db.bodies(),
+            local.db.state(),
+            self.commit_threshold,
+            self.chain.clone(),
+        )
+        .with_state_root_verification(self.verify_every);
+        let progress = state_sync.get_progress()?;
+        if progress >= self.to {
+            eyre::bail!(
+                "The state database at {} is already at block #{}",
+                local.db.state_path.display(),
+                progress,
+            )
+        }
+
+        info!(target: "reth::cli", from = progress + 1, to = self.to, "Starting replay");
+        state_sync.run(progress + 1..=self.to).await?;
+
+        let metrics = state_sync.metrics();
+        info!(
+            target: "reth::cli",
+            blocks = metrics.blocks,
+            transactions = metrics.transactions,
+            gas = metrics.gas,
+            progress = state_sync.get_progress()?,
+            "Replay finished"
+        );
+        Ok(())
+    }
+}
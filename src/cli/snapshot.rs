@@ -0,0 +1,115 @@
+use crate::{
+    cli::{
+        dirs::{BodiesDbPath, HeadersDbPath, LightConfigPath, StateDbPath},
+        remote::RemoteArgs,
+    },
+    config::LightConfig,
+    database::{
+        ChecksumAlgorithm, DatabaseDescriptor, DatabaseInitializer, Snapshot, SnapshotContents,
+        SnapshotManifest, Stage,
+    },
+    sync::{SnapshotUploader, UPLOAD_QUEUE_CAPACITY},
+};
+use clap::Parser;
+use reth::dirs::PlatformPath;
+use reth_primitives::ChainSpec;
+use reth_staged_sync::utils::chainspec::genesis_value_parser;
+use std::{path::Path, sync::Arc, time::Duration};
+use tracing::*;
+
+/// Publish snapshots of the local databases at their current progress, without syncing.
+///
+/// The headers and bodies are published as the only snapshot of their stage, replacing the
+/// previous snapshots and the headers diffs. The state snapshot is published next to the
+/// existing ones.
+#[derive(Debug, Parser)]
+pub struct Command {
+    /// The stages to publish: headers, bodies or state. Defaults to all of them.
+    #[arg(value_delimiter = ',')]
+    stages: Vec<Stage>,
+
+    #[arg(
+        long,
+        value_name = "CHAIN_OR_PATH",
+        verbatim_doc_comment,
+        default_value = "mainnet",
+        value_parser = genesis_value_parser
+    )]
+    chain: ChainSpec,
+
+    #[arg(long, value_name = "PATH", verbatim_doc_comment, default_value_t)]
+    headers_db: PlatformPath<HeadersDbPath>,
+
+    #[arg(long, value_name = "PATH", verbatim_doc_comment, default_value_t)]
+    bodies_db: PlatformPath<BodiesDbPath>,
+
+    #[arg(long, value_name = "PATH", verbatim_doc_comment, default_value_t)]
+    state_db: PlatformPath<StateDbPath>,
+
+    /// The path to the light node config, with the remote store and the snapshot settings.
+    #[arg(long, value_name = "FILE", verbatim_doc_comment, default_value_t)]
+    light_config: PlatformPath<LightConfigPath>,
+
+    /// The hash function of the database checksums published along with the snapshots: sha256 or
+    /// blake3. Defaults to the value from the light config.
+    #[arg(long = "checksum-algo", value_name = "ALGORITHM", verbatim_doc_comment)]
+    checksum_algorithm: Option<ChecksumAlgorithm>,
+
+    #[clap(flatten)]
+    remote: RemoteArgs,
+}
+
+impl Command {
+    /// Execute `snapshot` command
+    pub async fn execute(self) -> eyre::Result<()> {
+        let stages = if self.stages.is_empty() { Stage::ALL.to_vec() } else { self.stages.clone() };
+
+        let light_config = LightConfig::load(&self.light_config)?;
+        let snapshots = light_config.snapshots;
+        let remote = self.remote.store(self.chain.chain, light_config.remote).await?;
+        let mut uploader = SnapshotUploader::spawn(
+            remote,
+            UPLOAD_QUEUE_CAPACITY,
+            snapshots.max_concurrent_uploads,
+            SnapshotManifest::new(&self.chain),
+            None,
+            snapshots.cleanup_concurrency,
+            Duration::from_secs(snapshots.cleanup_grace_period),
+        )
+        .with_checksum_algorithm(self.checksum_algorithm.unwrap_or(snapshots.checksum_algorithm))
+        .with_compression_level(snapshots.compression_level)
+        .with_compression_threads(snapshots.compression_threads)
+        .with_temp_dir(snapshots.temp_dir)
+        .with_retention(snapshots.retention);
+
+        for stage in stages {
+            let path: &Path = match stage {
+                Stage::Headers => self.headers_db.as_ref(),
+                Stage::Bodies => self.bodies_db.as_ref(),
+                Stage::State => self.state_db.as_ref(),
+            };
+            // Make sure no sync writes to the database while it is being compressed.
+            let initializer = DatabaseInitializer::new(stage).with_path(path);
+            let _lock = initializer.lock()?;
+            let db = initializer.open_read_only()?;
+
+            let Some(progress) = stage.descriptor().progress(Arc::clone(&db))? else {
+                warn!(target: "reth::cli", %stage, "Database has no progress, skipping");
+                continue
+            };
+            let contents = SnapshotContents::collect(stage, &db)?;
+            info!(target: "reth::cli", %stage, progress, %contents, "Publishing snapshot");
+            match stage {
+                Stage::Headers | Stage::Bodies => {
+                    uploader.upload_single(stage, path, progress, contents).await?
+                }
+                Stage::State => {
+                    uploader.upload(Snapshot::new(stage, progress), path, contents).await?
+                }
+            }
+        }
+
+        // Wait for the snapshots to be uploaded
+        uploader.finish().await
+    }
+}
@@ -0,0 +1,125 @@
+use crate::{
+    cli::{
+        dirs::{BodiesDbPath, HeadersDbPath, LightConfigPath, StateDbPath},
+        remote::RemoteArgs,
+    },
+    config::LightConfig,
+    database::{
+        headers_diff_chain, DatabaseDescriptor, DatabaseInitializer, Snapshot, SnapshotContents,
+        Stage, MDBX_DAT,
+    },
+    remote::{RemoteEntry, RemoteStore},
+};
+use clap::Parser;
+use reth::dirs::PlatformPath;
+use reth_primitives::ChainSpec;
+use reth_staged_sync::utils::chainspec::genesis_value_parser;
+use std::{path::Path, sync::Arc};
+
+/// Show the snapshots published to the remote store.
+#[derive(Debug, Parser)]
+pub struct Command {
+    #[arg(
+        long,
+        value_name = "CHAIN_OR_PATH",
+        verbatim_doc_comment,
+        default_value = "mainnet",
+        value_parser = genesis_value_parser
+    )]
+    chain: ChainSpec,
+
+    #[arg(long, value_name = "PATH", verbatim_doc_comment, default_value_t)]
+    headers_db: PlatformPath<HeadersDbPath>,
+
+    #[arg(long, value_name = "PATH", verbatim_doc_comment, default_value_t)]
+    bodies_db: PlatformPath<BodiesDbPath>,
+
+    #[arg(long, value_name = "PATH", verbatim_doc_comment, default_value_t)]
+    state_db: PlatformPath<StateDbPath>,
+
+    /// The path to the light node config, with the remote store settings.
+    #[arg(long, value_name = "FILE", verbatim_doc_comment, default_value_t)]
+    light_config: PlatformPath<LightConfigPath>,
+
+    #[clap(flatten)]
+    remote: RemoteArgs,
+
+    /// Also show the progress of the local databases. The databases are opened read-only.
+    #[arg(long, verbatim_doc_comment)]
+    local: bool,
+}
+
+impl Command {
+    /// Execute `status` command
+    pub async fn execute(self) -> eyre::Result<()> {
+        let light_config = LightConfig::load(&self.light_config)?;
+        let remote = self.remote.store(self.chain.chain, light_config.remote).await?;
+        let listings = list_snapshots(&remote).await?;
+
+        for (stage, entries) in Stage::ALL.into_iter().zip(listings) {
+            let keys = entries.iter().map(|e| e.key.as_str()).collect::<Vec<_>>();
+            let blocks = keys
+                .iter()
+                .filter_map(|key| Snapshot::parse_for(key, stage))
+                .map(|s| s.block)
+                .collect::<Vec<_>>();
+
+            let latest = blocks.iter().max().copied();
+            println!("{stage}: {} snapshot(s), latest at {}", blocks.len(), format_block(latest));
+
+            if let Some(block) = latest {
+                let key = Snapshot::new(stage, block).key();
+                if let Some(metadata) = remote.metadata(&key).await? {
+                    let contents = SnapshotContents::from_metadata(&metadata);
+                    if !contents.is_empty() {
+                        println!("  latest contains {contents}");
+                    }
+                }
+            }
+
+            if stage == Stage::Headers {
+                if let Some(base) = latest {
+                    let diffs = headers_diff_chain(keys, base);
+                    let progress = diffs.last().map(|d| d.to);
+                    println!("  {} diff(s) on top, up to {}", diffs.len(), format_block(progress));
+                }
+            }
+
+            if self.local {
+                let progress = self.local_progress(stage)?;
+                println!("  local progress at {}", format_block(progress));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn local_progress(&self, stage: Stage) -> eyre::Result<Option<u64>> {
+        let path: &Path = match stage {
+            Stage::Headers => self.headers_db.as_ref(),
+            Stage::Bodies => self.bodies_db.as_ref(),
+            Stage::State => self.state_db.as_ref(),
+        };
+        // The directory of a running sync might not have the database file yet.
+        if !path.join(MDBX_DAT).exists() {
+            return Ok(None)
+        }
+        let db = DatabaseInitializer::new(stage).with_path(path).open_read_only()?;
+        stage.descriptor().progress(Arc::clone(&db))
+    }
+}
+
+/// List the headers, bodies and state snapshot prefixes concurrently.
+/// The listings are returned in the order of [Stage::ALL].
+pub async fn list_snapshots(remote: &RemoteStore) -> eyre::Result<[Vec<RemoteEntry>; 3]> {
+    let (headers, bodies, state) = futures::try_join!(
+        remote.list(Some(Stage::Headers.prefix())),
+        remote.list(Some(Stage::Bodies.prefix())),
+        remote.list(Some(Stage::State.prefix())),
+    )?;
+    Ok([headers, bodies, state])
+}
+
+fn format_block(block: Option<u64>) -> String {
+    block.map_or_else(|| "-".to_owned(), |block| format!("block #{block}"))
+}
@@ -1,11 +1,19 @@
 use crate::{
-    cli::dirs::{HeadersDbPath, StateDbPath},
+    cli::{
+        dirs::{HeadersDbPath, LightConfigPath, StateDbPath},
+        remote::RemoteArgs,
+    },
+    config::LightConfig,
     database::{
-        BodiesDescriptor, DatabaseInitializer, HeadersDescriptor, SplitDatabase, StateDescriptor,
-        BODIES_PREFIX, HEADERS_PREFIX, STATE_PREFIX,
+        validate_bodies_against_headers, ChecksumAlgorithm, DatabaseGeometry, DatabaseInitializer,
+        SnapshotManifest, SplitDatabase, Stage,
+    },
+    sync::{
+        default_state_snapshot_interval, run_sync_with_snapshots, BadBlocks, BodiesSync,
+        ConsensusValidation, ExecutionTrace, Follow, HeadersSync, Interrupt, RpcTipSource,
+        SnapshotUploader, StateSync, Tip, ValidatingConsensus, DEFAULT_BODIES_COMMIT_THRESHOLD,
+        DEFAULT_MAX_REORG_DEPTH, FINALIZED, UPLOAD_QUEUE_CAPACITY,
     },
-    remote::RemoteStore,
-    sync::{run_sync_with_snapshots, BodiesSync, HeadersSync, StateSync, Tip},
 };
 use clap::{crate_version, Parser, ValueEnum};
 use eyre::Context;
@@ -19,6 +27,7 @@ use reth::{
     utils::get_single_header,
 };
 use reth_consensus::beacon::BeaconConsensus;
+use reth_db::database::Database;
 use reth_downloaders::{
     bodies::bodies::BodiesDownloaderBuilder,
     headers::reverse_headers::ReverseHeadersDownloaderBuilder,
@@ -27,21 +36,52 @@ use reth_network::{
     error::NetworkError, FetchClient, NetworkConfig, NetworkHandle, NetworkManager,
 };
 use reth_network_api::NetworkInfo;
-use reth_primitives::{BlockHashOrNumber, ChainSpec, Head, H256};
+use reth_primitives::{BlockHashOrNumber, BlockNumber, ChainSpec, Head, NodeRecord, H256};
 use reth_provider::{test_utils::NoopProvider, BlockProvider, HeaderProvider};
 use reth_staged_sync::{utils::chainspec::genesis_value_parser, Config};
+use reth_stages::stages::EXECUTION;
 use reth_tasks::TaskExecutor;
-use std::{path::PathBuf, sync::Arc};
+use std::{
+    path::{Path, PathBuf},
+    pin::Pin,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::oneshot;
 use tracing::*;
 
 use super::dirs::BodiesDbPath;
 
+/// The number of open file descriptors needed besides the peer connections.
+const FD_LIMIT_BASE: u64 = 512;
+
+/// Parse the bootnode passed with `--bootnodes`, which replaces the parser of the network
+/// arguments to explain the rejected formats.
+///
+/// Only the enode URLs are supported, since the discovery is not able to dial an ENR.
+fn parse_bootnode(value: &str) -> Result<NodeRecord, String> {
+    const EXPECTED: &str = "expected an enode URL `enode://<node id>@<ip>:<port>`";
+    if value.starts_with("enr:") {
+        return Err(format!("ENR bootnodes are not supported, {EXPECTED}"))
+    }
+    if !value.starts_with("enode://") {
+        return Err(format!("unknown bootnode format, {EXPECTED}"))
+    }
+    NodeRecord::from_str(value).map_err(|error| format!("invalid enode URL: {error}"))
+}
+
 /// Start the node
 #[derive(Debug, Parser)]
+#[command(mut_arg("bootnodes", |arg| arg.value_parser(parse_bootnode)))]
 pub struct Command {
     #[arg(long, value_name = "FILE", verbatim_doc_comment, default_value_t)]
     config: PlatformPath<ConfigPath>,
 
+    /// The path to the light node config, with the remote store and the snapshot settings.
+    #[arg(long, value_name = "FILE", verbatim_doc_comment, default_value_t)]
+    light_config: PlatformPath<LightConfigPath>,
+
     #[arg(long, value_name = "PATH", verbatim_doc_comment, default_value_t)]
     headers_db: PlatformPath<HeadersDbPath>,
 
@@ -63,25 +103,340 @@ pub struct Command {
     #[clap(flatten)]
     network: NetworkArgs,
 
-    #[arg(long = "debug.tip", help_heading = "Debug")]
-    tip: H256,
+    #[clap(flatten)]
+    remote: RemoteArgs,
+
+    #[arg(
+        long = "debug.tip",
+        help_heading = "Debug",
+        required_unless_present_any = ["tip_from_rpc", "state_only_from_snapshot"],
+        conflicts_with = "tip_from_rpc"
+    )]
+    tip: Option<H256>,
+
+    /// Only restore the state database from the newest state snapshot, and exit without
+    /// starting the network. The headers and bodies are neither restored nor synced, and the
+    /// restored state can be queried with the `proof` command.
+    #[arg(long, verbatim_doc_comment)]
+    state_only_from_snapshot: bool,
+
+    /// The Ethereum JSON-RPC endpoint the latest finalized block is queried from to be used as
+    /// the tip, instead of passing its hash with `--debug.tip`. The endpoint must serve the chain
+    /// from `--chain`.
+    #[arg(long, value_name = "URL", verbatim_doc_comment)]
+    tip_from_rpc: Option<String>,
+
+    /// Override whether the state clear EIP (EIP-161) is applied to the state changes.
+    /// Defaults to the Spurious Dragon activation of the chain.
+    #[arg(
+        long = "debug.state-clear",
+        value_name = "BOOL",
+        help_heading = "Debug",
+        verbatim_doc_comment
+    )]
+    state_clear: Option<bool>,
+
+    /// The maximum number of downloaded body responses buffered ahead of the database writes.
+    ///
+    /// Lower it if the downloader outpaces the sync and the buffered bodies use too much memory.
+    /// Defaults to the value from the config file.
+    #[arg(long = "bodies.max-buffered-responses", value_name = "NUM", verbatim_doc_comment)]
+    bodies_max_buffered_responses: Option<usize>,
+
+    /// The number of downloaded blocks written before the bodies are committed.
+    #[arg(long = "bodies.commit-threshold", value_name = "NUM", verbatim_doc_comment, default_value_t = DEFAULT_BODIES_COMMIT_THRESHOLD)]
+    bodies_commit_threshold: u64,
+
+    /// Tally the types of the downloaded transactions, and publish the counts along with the
+    /// bodies snapshots. The transactions already in the database are tallied on the first run.
+    #[arg(long = "bodies.tx-type-stats", verbatim_doc_comment)]
+    bodies_tx_type_stats: bool,
+
+    /// The maximum number of canonical headers that can be unwound when the tip is on a
+    /// competing fork.
+    #[arg(long, value_name = "DEPTH", verbatim_doc_comment, default_value_t = DEFAULT_MAX_REORG_DEPTH)]
+    max_reorg_depth: u64,
+
+    /// The memory budget in megabytes for the execution results held before a batch is committed.
+    /// A warning is logged if a batch is estimated to exceed it.
+    #[arg(
+        long = "execution.memory-budget",
+        value_name = "MB",
+        verbatim_doc_comment,
+        default_value_t = 8192
+    )]
+    execution_memory_budget: u64,
+
+    /// Upload the snapshots of the current progress when the sync is interrupted with Ctrl-C.
+    #[arg(long, verbatim_doc_comment)]
+    snapshot_on_interrupt: bool,
+
+    /// Restore the databases from the best remote snapshots, even if they are not ahead of the
+    /// local progress. Useful if the local databases are suspected to be corrupt.
+    #[arg(long, verbatim_doc_comment)]
+    resume_from_remote: bool,
+
+    /// Allow `--resume-from-remote` to rewind the local progress.
+    #[arg(long, verbatim_doc_comment, requires = "resume_from_remote")]
+    force: bool,
+
+    /// Wipe any of the local databases whose genesis does not match the chain, and sync it anew.
+    /// Useful when reusing the database directories for a different chain.
+    #[arg(long, verbatim_doc_comment)]
+    force_genesis: bool,
+
+    /// Only warn if the genesis of any of the local databases does not match the chain, and sync
+    /// on top of the existing data. Useful for devnets that regenerate their genesis. The synced
+    /// data may be inconsistent, use at your own risk.
+    #[arg(long, verbatim_doc_comment, conflicts_with = "force_genesis")]
+    no_genesis_check: bool,
+
+    /// Walk all entries of every restored snapshot before syncing on top of it, so that a
+    /// truncated download is detected and restored again on the next run. Recommended whenever
+    /// the snapshots are restored from the remote.
+    #[arg(long, verbatim_doc_comment)]
+    verify_restore: bool,
+
+    /// Check that every body has a canonical header once the databases are opened, and truncate
+    /// the bodies that do not. Useful when the headers and bodies snapshots were restored at
+    /// different heights.
+    #[arg(long, verbatim_doc_comment)]
+    validate_bodies_against_headers: bool,
+
+    /// How much of the consensus rules the downloaded headers and bodies are validated against:
+    /// full, headers or none. The headers are always linked to the tip by their hashes, but
+    /// `headers` accepts the bodies that do not match their headers, and `none` also accepts the
+    /// malformed headers. Only lower it to sync faster from trusted peers.
+    #[arg(long, value_name = "LEVEL", verbatim_doc_comment, default_value_t)]
+    consensus_validation: ConsensusValidation,
+
+    /// Write the state changes of every executed transaction to the file as JSON lines.
+    #[arg(long, value_name = "FILE", verbatim_doc_comment)]
+    trace_execution: Option<PathBuf>,
+
+    /// Keep a local copy of every uploaded snapshot file in the directory, named by its key.
+    #[arg(long, value_name = "DIR", verbatim_doc_comment)]
+    log_snapshots_to: Option<PathBuf>,
+
+    /// Verify the state root against the canonical header every N blocks, and halt on the first
+    /// mismatch. The state trie is rebuilt in memory from the plain state on every check, so this
+    /// is only practical on the chains with a small state.
+    #[arg(long, value_name = "BLOCKS", verbatim_doc_comment)]
+    reexecute_verify: Option<u64>,
+
+    /// Skip the blocks that fail to execute instead of halting the sync, and log them. The state
+    /// diverges from the chain past the first skipped block, so the state snapshots are not
+    /// uploaded. Use a separate `--state-db` to keep the regular state intact.
+    #[arg(long, verbatim_doc_comment)]
+    skip_bad_blocks: bool,
+
+    /// Write the skipped bad blocks to the file as JSON lines.
+    #[arg(long, value_name = "FILE", verbatim_doc_comment, requires = "skip_bad_blocks")]
+    bad_blocks_log: Option<PathBuf>,
+
+    /// Record the gas used and the execution time of every block, and log the totals along with
+    /// the slowest blocks at the end of the execution range.
+    #[arg(long, verbatim_doc_comment)]
+    profile_evm: bool,
+
+    /// Publish the state snapshots as diffs of the changes since the previous snapshot, with a
+    /// full base snapshot every 1M blocks. The changes are kept in memory between the snapshots.
+    #[arg(long = "state-diffs", verbatim_doc_comment)]
+    state_diffs: bool,
+
+    /// The maximum number of snapshots of all stages uploaded at the same time. The compressed
+    /// snapshots beyond it wait on disk. Lower it if the uploads saturate the bandwidth or trip
+    /// the rate limits of the remote store.
+    /// Defaults to the value from the light config.
+    #[arg(long, value_name = "NUM", verbatim_doc_comment)]
+    max_concurrent_uploads: Option<usize>,
+
+    /// The number of the newest state snapshots kept in the remote store. The older ones are
+    /// deleted once a new state snapshot is uploaded, downloaded again and verified against its
+    /// checksum, along with the state diffs on top of them.
+    /// Defaults to the value from the light config, or to keeping all of them.
+    #[arg(long, value_name = "NUM", verbatim_doc_comment)]
+    state_retention: Option<usize>,
+
+    /// The maximum number of previous snapshot entries deleted concurrently after a new one is
+    /// uploaded. Lower it if the remote store rate limits the requests.
+    /// Defaults to the value from the light config.
+    #[arg(long = "upload.cleanup-concurrency", value_name = "NUM", verbatim_doc_comment)]
+    upload_cleanup_concurrency: Option<usize>,
+
+    /// The number of seconds a previous snapshot entry is kept for after it was uploaded, so that
+    /// the processes restoring from it can finish the download.
+    /// Defaults to the value from the light config.
+    #[arg(long = "upload.cleanup-grace-period", value_name = "SECONDS", verbatim_doc_comment)]
+    upload_cleanup_grace_period: Option<u64>,
+
+    /// The directory the snapshots are compressed into before the upload. Must have room for the
+    /// compressed state database. Defaults to the value from the light config, or to the parent
+    /// directory of each database.
+    #[arg(long = "upload.tmp-dir", value_name = "DIR", verbatim_doc_comment)]
+    upload_tmp_dir: Option<PathBuf>,
+
+    /// The number of threads the restored snapshots are decompressed with, if they were published
+    /// in the parallel compression format. Defaults to the number of available cores.
+    #[arg(long, value_name = "NUM", verbatim_doc_comment)]
+    decompress_threads: Option<usize>,
+
+    /// The hash function of the database checksums published along with the snapshots, which
+    /// the restore verifies the downloaded databases against: sha256 or blake3.
+    /// Defaults to the value from the light config.
+    #[arg(long = "checksum-algo", value_name = "ALGORITHM", verbatim_doc_comment)]
+    checksum_algorithm: Option<ChecksumAlgorithm>,
+
+    /// The number of blocks between the state snapshots.
+    /// Defaults to the value from the light config, or to the interval for the chain.
+    #[arg(long, value_name = "BLOCKS", verbatim_doc_comment)]
+    snapshot_interval: Option<u64>,
+
+    /// The maximum size of each database file, in gigabytes. Must not be lower than the size of
+    /// the existing files. Defaults to the size for the chain.
+    #[arg(long = "db.max-size", value_name = "GB", verbatim_doc_comment)]
+    db_max_size: Option<usize>,
+
+    /// Sync the bodies and the state only up to the block, even if the tip is higher. A state
+    /// snapshot is uploaded at the block once it is reached.
+    #[arg(long, value_name = "NUMBER", verbatim_doc_comment)]
+    max_block: Option<BlockNumber>,
+
+    /// Sync the bodies and the state exactly up to the block, upload their final snapshots at it
+    /// and exit. The block must not be past the tip. The headers are still synced up to the tip,
+    /// since they are downloaded in reverse from it.
+    #[arg(
+        long,
+        value_name = "NUMBER",
+        verbatim_doc_comment,
+        conflicts_with_all = ["max_block", "follow", "skip_bad_blocks"]
+    )]
+    halt_at: Option<BlockNumber>,
+
+    /// Keep the network running once the tip is reached, and extend the sync to every new
+    /// finalized block queried from `--follow.rpc`. The state snapshots are uploaded at the
+    /// snapshot interval as the sync advances.
+    #[arg(long, verbatim_doc_comment, requires = "follow_rpc", conflicts_with = "max_block")]
+    follow: bool,
+
+    /// The Ethereum JSON-RPC endpoint the finalized block is queried from in the `--follow` mode.
+    #[arg(long = "follow.rpc", value_name = "URL", verbatim_doc_comment)]
+    follow_rpc: Option<String>,
+
+    /// The number of seconds between the finalized block queries in the `--follow` mode.
+    #[arg(
+        long = "follow.interval",
+        value_name = "SECONDS",
+        verbatim_doc_comment,
+        default_value_t = 60
+    )]
+    follow_interval: u64,
+
+    /// The number of seconds to wait for the peers to connect before aborting the sync.
+    #[arg(long, value_name = "SECONDS", verbatim_doc_comment, default_value_t = 120)]
+    peers_timeout: u64,
+
+    /// The minimum number of connected peers the downloads are started with. The downloaders
+    /// stall silently with too few peers.
+    #[arg(long, value_name = "NUM", verbatim_doc_comment, default_value_t = 1)]
+    peer_count: usize,
+
+    /// The interval between the connected peer count logs, in seconds.
+    #[arg(long, value_name = "SECONDS", verbatim_doc_comment, default_value_t = 60)]
+    peer_log_interval: u64,
+
+    /// The minimum number of open file descriptors required to run the sync. Defaults to the
+    /// descriptors needed for the databases and the maximum number of peers from the config.
+    #[arg(long, value_name = "NUM", verbatim_doc_comment)]
+    min_fd_limit: Option<u64>,
 }
 
 impl Command {
-    /// Execute `node` command
+    /// Execute `sync` command
+    ///
+    /// The interrupt signal is handled here rather than by the runner, so that the snapshots of
+    /// the current progress can be uploaded before exiting.
     pub async fn execute(self, ctx: CliContext) -> eyre::Result<()> {
+        if self.state_only_from_snapshot {
+            return tokio::select! {
+                res = self.restore_state() => res,
+                _ = tokio::signal::ctrl_c() => Ok(()),
+            }
+        }
+
+        let interrupt = Interrupt::default();
+
+        let mut sync = tokio::select! {
+            sync = self.start_sync(&ctx, interrupt.clone()) => sync?,
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+        };
+
+        tokio::select! {
+            res = &mut sync => res??,
+            _ = tokio::signal::ctrl_c() => {
+                if !self.snapshot_on_interrupt {
+                    return Ok(())
+                }
+
+                info!(target: "reth::cli", "Interrupted, uploading snapshots of the current progress. Press Ctrl-C again to exit immediately.");
+                interrupt.trigger();
+                tokio::select! {
+                    res = sync => res??,
+                    _ = tokio::signal::ctrl_c() => return Ok(()),
+                }
+            }
+        }
+
+        info!(target: "reth::cli", "State sync has finished.");
+
+        Ok(())
+    }
+
+    /// Restore the state database from the newest state snapshot, if it is ahead of the local one.
+    async fn restore_state(&self) -> eyre::Result<()> {
+        let light_config = LightConfig::load(&self.light_config)?;
+        let remote = self.remote.store(self.chain.chain, light_config.remote).await?;
+
+        info!(target: "reth::cli", state_db = %self.state_db, "Restoring state database");
+        let state = self.database_initializer(Stage::State, &self.state_db);
+        let _lock = state.lock()?;
+        let db = state.init(&remote, self.chain.clone()).await?;
+        let progress = EXECUTION.get_progress(&db.tx()?)?.unwrap_or_default();
+        info!(target: "reth::cli", progress, "State database restored");
+        Ok(())
+    }
+
+    /// Initialize the network and databases, and spawn the sync task.
+    /// Returns the receiver for the result of the sync.
+    async fn start_sync(
+        &self,
+        ctx: &CliContext,
+        interrupt: Interrupt,
+    ) -> eyre::Result<oneshot::Receiver<eyre::Result<()>>> {
         info!(target: "reth::cli", "reth {} starting", crate_version!());
 
         // Raise the fd limit of the process. Does not do anything on windows.
-        raise_fd_limit();
+        let fd_limit = raise_fd_limit();
 
         let mut config: Config = self.load_config()?;
         info!(target: "reth::cli", path = %self.config, "Configuration loaded");
+        self.check_fd_limit(fd_limit, &config);
 
-        let remote = RemoteStore::new("fra1".to_owned(), "reth-state-snapshots".to_owned()).await;
+        if let Some(max_buffered) = self.bodies_max_buffered_responses {
+            config.stages.bodies.downloader_max_buffered_responses = max_buffered;
+        }
+
+        let light_config = LightConfig::load(&self.light_config)?;
+        info!(target: "reth::cli", path = %self.light_config, "Light configuration loaded");
+        let snapshots = light_config.snapshots;
+        let remote = self.remote.store(self.chain.chain, light_config.remote).await?;
 
         let (consensus, _forkchoice_state_tx) =
             BeaconConsensus::builder().build(self.chain.clone());
+        let consensus = Arc::new(ValidatingConsensus::new(consensus, self.consensus_validation));
+        if self.consensus_validation != ConsensusValidation::Full {
+            warn!(target: "reth::cli", validation = %self.consensus_validation, "Consensus validation is lowered, the downloaded data is trusted");
+        }
         info!(target: "reth::cli", "Consensus engine initialized");
 
         self.init_trusted_nodes(&mut config);
@@ -96,35 +451,42 @@ impl Command {
             network.event_listener().map(Into::into),
         ));
 
+        self.wait_for_peers(&network).await?;
+        ctx.task_executor.spawn(log_peers(
+            network.clone(),
+            self.peer_count,
+            Duration::from_secs(self.peer_log_interval.max(1)),
+        ));
+
         let fetch_client = network.fetch_client().await?;
-        let tip = Tip::new(self.tip, self.fetch_tip(fetch_client.clone(), self.tip).await?);
+        let tip = self.resolve_tip(fetch_client.clone()).await?;
 
         info!(target: "reth::cli", headers_db = %self.headers_db, "Opening split database");
-        let headers = DatabaseInitializer::default()
-            .with_path(&self.headers_db)
-            .with_prefix(HEADERS_PREFIX)
-            .init(&remote, self.chain.clone(), HeadersDescriptor)
-            .await?;
-        let bodies = DatabaseInitializer::default()
-            .with_path(&self.bodies_db)
-            .with_prefix(BODIES_PREFIX)
-            .init(&remote, self.chain.clone(), BodiesDescriptor)
-            .await?;
-        let state = DatabaseInitializer::default()
-            .with_path(&self.state_db)
-            .with_prefix(STATE_PREFIX)
-            .init(&remote, self.chain.clone(), StateDescriptor)
-            .await?;
+        // The snapshots are independent files, so they are restored concurrently.
+        let headers = self.database_initializer(Stage::Headers, &self.headers_db);
+        let bodies = self.database_initializer(Stage::Bodies, &self.bodies_db);
+        let state = self.database_initializer(Stage::State, &self.state_db);
+        let locks = [headers.lock()?, bodies.lock()?, state.lock()?];
+        let (headers, bodies, state) = futures::try_join!(
+            headers.init(&remote, self.chain.clone()),
+            bodies.init(&remote, self.chain.clone()),
+            state.init(&remote, self.chain.clone()),
+        )?;
         let db = SplitDatabase::new(
             &self.headers_db,
             headers,
-            self.bodies_db,
+            &self.bodies_db,
             bodies,
             &self.state_db,
             state,
-        );
+        )
+        .with_locks(locks);
         info!(target: "reth::cli", "Split database opened");
 
+        if self.validate_bodies_against_headers {
+            validate_bodies_against_headers(&db.headers(), &db.bodies())?;
+        }
+
         let fetch_client = Arc::new(fetch_client);
         let header_downloader = ReverseHeadersDownloaderBuilder::from(config.stages.headers)
             .build(fetch_client.clone(), consensus.clone())
@@ -133,30 +495,117 @@ impl Command {
             .build(fetch_client.clone(), consensus.clone(), db.headers())
             .into_task_with(&ctx.task_executor);
 
-        let headers_sync = HeadersSync::new(db.headers(), header_downloader);
-        let bodies_sync = BodiesSync::new(db.bodies(), body_downloader);
-        let state_sync = StateSync::new(
+        let headers_sync = HeadersSync::new(db.headers(), header_downloader, self.chain.clone())
+            .with_max_reorg_depth(self.max_reorg_depth)
+            .with_consensus_validation(self.consensus_validation);
+        let bodies_sync = BodiesSync::new(db.bodies(), body_downloader, self.chain.clone())
+            .with_commit_threshold(self.bodies_commit_threshold)
+            .with_tx_type_stats(self.bodies_tx_type_stats)
+            .with_consensus_validation(self.consensus_validation);
+        let mut state_sync = StateSync::new(
             db.headers(),
             db.bodies(),
             db.state(),
             config.stages.execution.commit_threshold,
             self.chain.clone(),
-        );
+        )
+        .with_memory_budget(self.execution_memory_budget * 1024 * 1024)
+        .with_state_clear_override(self.state_clear)
+        .with_interrupt(interrupt.clone());
+        if let Some(path) = &self.trace_execution {
+            state_sync = state_sync.with_execution_trace(ExecutionTrace::create(path)?);
+        }
+        if let Some(interval) = self.reexecute_verify {
+            state_sync = state_sync.with_state_root_verification(interval);
+        }
+        if self.profile_evm {
+            state_sync = state_sync.with_evm_profile();
+        }
+        if self.state_diffs {
+            state_sync = state_sync.with_state_diffs();
+        }
+        if self.skip_bad_blocks {
+            let mut bad_blocks = BadBlocks::default();
+            if let Some(path) = &self.bad_blocks_log {
+                bad_blocks = bad_blocks.with_log(path)?;
+            }
+            state_sync = state_sync.with_skip_bad_blocks(bad_blocks);
+        }
 
         // Run sync
-        let (rx, tx) = tokio::sync::oneshot::channel();
+        let uploader = SnapshotUploader::spawn(
+            remote,
+            UPLOAD_QUEUE_CAPACITY,
+            self.max_concurrent_uploads.unwrap_or(snapshots.max_concurrent_uploads),
+            SnapshotManifest::new(&self.chain),
+            self.log_snapshots_to.clone(),
+            self.upload_cleanup_concurrency.unwrap_or(snapshots.cleanup_concurrency),
+            Duration::from_secs(
+                self.upload_cleanup_grace_period.unwrap_or(snapshots.cleanup_grace_period),
+            ),
+        )
+        .with_checksum_algorithm(self.checksum_algorithm.unwrap_or(snapshots.checksum_algorithm))
+        .with_compression_level(snapshots.compression_level)
+        .with_compression_threads(snapshots.compression_threads)
+        .with_temp_dir(self.upload_tmp_dir.clone().or(snapshots.temp_dir))
+        .with_retention(snapshots.retention.with_keep(self.state_retention));
+        let max_block = self.max_block;
+        let halt_at = self.halt_at;
+        let snapshot_interval = self
+            .snapshot_interval
+            .or(snapshots.state_interval)
+            .unwrap_or_else(|| default_state_snapshot_interval(self.chain.chain))
+            .max(1);
+        let follow = self.follow_rpc.as_ref().filter(|_| self.follow).map(|url| Follow {
+            source: RpcTipSource::new(url.clone()),
+            poll_interval: Duration::from_secs(self.follow_interval.max(1)),
+        });
+        let (tx, rx) = oneshot::channel();
         info!(target: "reth::cli", "Starting state sync");
         ctx.task_executor.spawn_critical_blocking("state sync task", async move {
-            let res =
-                run_sync_with_snapshots(headers_sync, bodies_sync, state_sync, tip, remote, db)
-                    .await;
-            let _ = rx.send(res);
+            let res = run_sync_with_snapshots(
+                headers_sync,
+                bodies_sync,
+                state_sync,
+                tip,
+                max_block,
+                halt_at,
+                snapshot_interval,
+                uploader,
+                db,
+                interrupt,
+                follow,
+            )
+            .await;
+            let _ = tx.send(res);
         });
 
-        tx.await??;
-
-        info!(target: "reth::cli", "State sync has finished.");
+        Ok(rx)
+    }
 
+    /// Wait until at least `--peer-count` peers are connected, or fail once the peers timeout
+    /// elapses.
+    async fn wait_for_peers(&self, network: &NetworkHandle) -> eyre::Result<()> {
+        let required = self.peer_count.max(1);
+        let timeout = Duration::from_secs(self.peers_timeout);
+        let started_at = Instant::now();
+        let mut last_logged = started_at;
+        while network.num_connected_peers() < required {
+            if started_at.elapsed() >= timeout {
+                eyre::bail!(
+                    "{} of {required} peers connected within {}s, check the bootnodes and the \
+                     network arguments",
+                    network.num_connected_peers(),
+                    self.peers_timeout,
+                )
+            }
+            if last_logged.elapsed() >= Duration::from_secs(10) {
+                last_logged = Instant::now();
+                info!(target: "reth::cli", peers = network.num_connected_peers(), required, "Waiting for peers");
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+        info!(target: "reth::cli", peers = network.num_connected_peers(), "Connected to peers");
         Ok(())
     }
 
@@ -186,18 +635,47 @@ impl Command {
     where
         C: BlockProvider + HeaderProvider + Clone + Unpin + 'static,
     {
-        let client = config.client.clone();
-        let (handle, network, _txpool, eth) =
-            NetworkManager::builder(config).await?.request_handler(client).split_with_handle();
+        spawn_network(config, task_executor, self.network.persistent_peers_file()).await
+    }
 
-        let known_peers_file = self.network.persistent_peers_file();
-        task_executor.spawn_critical_with_signal("p2p network task", |shutdown| async move {
-            run_network_until_shutdown(shutdown, network, known_peers_file).await
-        });
+    /// Warn if the open file limit could not be raised above the limit the sync needs, which
+    /// would otherwise surface as "too many open files" errors from the databases or the network.
+    fn check_fd_limit(&self, fd_limit: Option<u64>, config: &Config) {
+        let Some(fd_limit) = fd_limit else {
+            debug!(target: "reth::cli", "Open file limit is unknown on this platform");
+            return
+        };
 
-        task_executor.spawn_critical("p2p eth request handler", async move { eth.await });
+        let connections = &config.peers.connection_info;
+        let required = self
+            .min_fd_limit
+            .unwrap_or(FD_LIMIT_BASE + (connections.max_inbound + connections.max_outbound) as u64);
+        if fd_limit < required {
+            warn!(
+                target: "reth::cli",
+                fd_limit,
+                required,
+                "The open file limit is too low and could not be raised, raise it with `ulimit -n {required}`"
+            );
+        } else {
+            debug!(target: "reth::cli", fd_limit, required, "Open file limit raised");
+        }
+    }
 
-        Ok(handle)
+    fn database_initializer(&self, stage: Stage, path: impl AsRef<Path>) -> DatabaseInitializer {
+        DatabaseInitializer::new(stage)
+            .with_path(path)
+            .with_resume_from_remote(self.resume_from_remote)
+            .with_force(self.force)
+            .with_force_genesis(self.force_genesis)
+            .with_no_genesis_check(self.no_genesis_check)
+            .with_verify_restore(self.verify_restore)
+            .with_decompress_threads(self.decompress_threads.unwrap_or_else(|| {
+                std::thread::available_parallelism().map_or(1, |threads| threads.get())
+            }))
+            .with_geometry(
+                DatabaseGeometry::for_chain(self.chain.chain).with_max_size_gb(self.db_max_size),
+            )
     }
 
     fn load_network_config(
@@ -212,6 +690,10 @@ impl Command {
             difficulty: self.chain.genesis.difficulty,
             total_difficulty: self.chain.genesis.difficulty,
         };
+        // The bootnodes passed with `--bootnodes` replace the ones of the chain.
+        if let Some(bootnodes) = &self.network.bootnodes {
+            info!(target: "reth::cli", count = bootnodes.len(), "Using the bootnodes from the command line");
+        }
         self.network
             .network_config(config, self.chain.clone())
             .with_task_executor(Box::new(executor))
@@ -219,6 +701,27 @@ impl Command {
             .build(NoopProvider::default())
     }
 
+    /// Query the finalized block from `--tip-from-rpc`, or fetch the number of the `--debug.tip`
+    /// block from the network.
+    async fn resolve_tip(&self, fetch_client: FetchClient) -> eyre::Result<Tip> {
+        let Some(url) = &self.tip_from_rpc else {
+            let hash = self.tip.expect("required without --tip-from-rpc");
+            return Ok(Tip::new(hash, self.fetch_tip(fetch_client, hash).await?))
+        };
+
+        let source = RpcTipSource::new(url.clone());
+        let chain_id = source.chain_id().await?;
+        if chain_id != self.chain.chain.id() {
+            eyre::bail!(
+                "The tip RPC endpoint serves chain {chain_id}, but --chain {} was requested",
+                self.chain.chain
+            )
+        }
+        let tip = source.tip(FINALIZED).await?;
+        info!(target: "reth::cli", ?tip, "Fetched finalized tip from the RPC endpoint");
+        Ok(tip)
+    }
+
     async fn fetch_tip(
         &self,
         fetch_client: FetchClient,
@@ -239,8 +742,47 @@ impl Command {
     }
 }
 
+/// Log the number of connected peers at the interval, warning while it is below the minimum the
+/// sync was started with.
+async fn log_peers(network: NetworkHandle, min_peers: usize, interval: Duration) {
+    let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+    loop {
+        interval.tick().await;
+        let peers = network.num_connected_peers();
+        if peers < min_peers {
+            warn!(target: "reth::cli", peers, min_peers, "Too few peers connected, the downloads may stall");
+        } else {
+            info!(target: "reth::cli", peers, "Connected peers");
+        }
+    }
+}
+
+/// Spawn the network and its request handler, writing the known peers to the
+/// `persistent_peers_file` on shutdown.
+pub(crate) async fn spawn_network<C>(
+    config: NetworkConfig<C>,
+    task_executor: &TaskExecutor,
+    persistent_peers_file: Option<PathBuf>,
+) -> Result<NetworkHandle, NetworkError>
+where
+    C: BlockProvider + HeaderProvider + Clone + Unpin + 'static,
+{
+    let client = config.client.clone();
+    let (handle, network, _txpool, eth) =
+        NetworkManager::builder(config).await?.request_handler(client).split_with_handle();
+
+    task_executor.spawn_critical_with_signal("p2p network task", |shutdown| async move {
+        run_network_until_shutdown(shutdown, network, persistent_peers_file).await
+    });
+
+    task_executor.spawn_critical("p2p eth request handler", async move { eth.await });
+
+    Ok(handle)
+}
+
 /// Drives the [NetworkManager] future until a [Shutdown](reth_tasks::shutdown::Shutdown) signal is
-/// received. If configured, this writes known peers to `persistent_peers_file` afterwards.
+/// received. If configured, this writes known peers to `persistent_peers_file` afterwards, or when
+/// the network task panics.
 async fn run_network_until_shutdown<C>(
     shutdown: reth_tasks::shutdown::Shutdown,
     network: NetworkManager<C>,
@@ -248,25 +790,78 @@ async fn run_network_until_shutdown<C>(
 ) where
     C: BlockProvider + HeaderProvider + Clone + Unpin + 'static,
 {
-    pin_mut!(network, shutdown);
+    let mut network = PersistPeersOnDrop { network: Box::pin(network), persistent_peers_file };
+    pin_mut!(shutdown);
 
     tokio::select! {
-        _ = &mut network => {},
+        _ = &mut network.network => {},
         _ = shutdown => {},
     }
+}
 
-    if let Some(file_path) = persistent_peers_file {
-        let known_peers = network.all_peers().collect::<Vec<_>>();
-        if let Ok(known_peers) = serde_json::to_string_pretty(&known_peers) {
-            trace!(target : "reth::cli", peers_file =?file_path, num_peers=%known_peers.len(), "Saving current peers");
-            match std::fs::write(&file_path, known_peers) {
-                Ok(_) => {
-                    info!(target: "reth::cli", peers_file=?file_path, "Wrote network peers to file");
-                }
-                Err(err) => {
-                    warn!(target: "reth::cli", ?err, peers_file=?file_path, "Failed to write network peers to file");
+/// Owns the [NetworkManager] and writes its known peers to the peers file when dropped, so that
+/// the peers are retained on both the clean shutdown and the unwind of the network task.
+struct PersistPeersOnDrop<C: BlockProvider + HeaderProvider + Clone + Unpin + 'static> {
+    network: Pin<Box<NetworkManager<C>>>,
+    persistent_peers_file: Option<PathBuf>,
+}
+
+impl<C: BlockProvider + HeaderProvider + Clone + Unpin + 'static> Drop for PersistPeersOnDrop<C> {
+    fn drop(&mut self) {
+        if let Some(file_path) = &self.persistent_peers_file {
+            let known_peers = self.network.all_peers().collect::<Vec<_>>();
+            if let Ok(known_peers) = serde_json::to_string_pretty(&known_peers) {
+                trace!(target : "reth::cli", peers_file =?file_path, num_peers=%known_peers.len(), "Saving current peers");
+                match std::fs::write(file_path, known_peers) {
+                    Ok(_) => {
+                        info!(target: "reth::cli", peers_file=?file_path, "Wrote network peers to file");
+                    }
+                    Err(err) => {
+                        warn!(target: "reth::cli", ?err, peers_file=?file_path, "Failed to write network peers to file");
+                    }
                 }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOOTNODE: &str = "enode://d860a01f9722d78051619d1e2351aba3f43f943f6f00718d1b9baa4101932a1f5011f16bb2b1bb35db20d6fe28fa0bf09636d26a87d31de9ec6203eeedb1f666@18.138.108.67:30303";
+
+    #[test]
+    fn parse_bootnodes() {
+        let tip = format!("{:?}", H256::zero());
+        let command = Command::try_parse_from([
+            "sync",
+            "--debug.tip",
+            tip.as_str(),
+            "--bootnodes",
+            format!("{BOOTNODE},{BOOTNODE}").as_str(),
+        ])
+        .unwrap();
+        let expected = NodeRecord::from_str(BOOTNODE).unwrap();
+        assert_eq!(command.network.bootnodes, Some(vec![expected, expected]));
+
+        for (bootnode, error) in [
+            (
+                "enr:-Iu4QLm7bZGdAt9NSeJG0cEnJohWcQTQaI9wFLu3Q8eZtFCF0fbFus",
+                "ENR bootnodes are not supported",
+            ),
+            ("18.138.108.67:30303", "unknown bootnode format"),
+            ("enode://d860a01f@18.138.108.67:30303", "invalid enode URL"),
+        ] {
+            let result = Command::try_parse_from([
+                "sync",
+                "--debug.tip",
+                tip.as_str(),
+                "--bootnodes",
+                bootnode,
+            ]);
+            let message = result.unwrap_err().to_string();
+            assert!(message.contains(error), "{bootnode}: {message}");
+        }
+    }
+}
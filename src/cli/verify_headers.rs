@@ -0,0 +1,99 @@
+use crate::{
+    cli::dirs::HeadersDbPath,
+    database::{DatabaseInitializer, Stage},
+};
+use clap::Parser;
+use reth::dirs::PlatformPath;
+use reth_db::{cursor::DbCursorRO, database::Database, tables, transaction::DbTx};
+use reth_primitives::{BlockNumber, H256, U256};
+use reth_stages::stages::HEADERS;
+use tracing::*;
+
+/// The number of blocks between the progress logs.
+const LOG_INTERVAL: BlockNumber = 1_000_000;
+
+/// Check the integrity of the local headers without modifying them.
+///
+/// Every header up to the headers progress must have a canonical hash that matches it, follow
+/// the previous block number and link to the previous header. The total difficulty must
+/// strictly increase up to the merge, and stay constant after it. Fails with the first
+/// inconsistency found.
+#[derive(Debug, Parser)]
+pub struct Command {
+    #[arg(long, value_name = "PATH", verbatim_doc_comment, default_value_t)]
+    headers_db: PlatformPath<HeadersDbPath>,
+}
+
+impl Command {
+    /// Execute `verify-headers` command
+    pub async fn execute(self) -> eyre::Result<()> {
+        let db = DatabaseInitializer::new(Stage::Headers)
+            .with_path(&self.headers_db)
+            .open_read_only()?;
+        let tx = db.tx()?;
+
+        let progress = HEADERS.get_progress(&tx)?.unwrap_or_default();
+        info!(target: "reth::cli", progress, "Verifying headers");
+
+        let mut canonical = tx.cursor_read::<tables::CanonicalHeaders>()?;
+        let mut parent: Option<(BlockNumber, H256)> = None;
+        let mut td = U256::ZERO;
+        let mut merged = false;
+        for entry in tx.cursor_read::<tables::Headers>()?.walk_range(0..=progress)? {
+            let (number, header) = entry?;
+            if header.number != number {
+                eyre::bail!("Header #{number} is stored with the number #{}", header.number)
+            }
+            let expected = parent.map_or(0, |(parent, _)| parent + 1);
+            if number != expected {
+                eyre::bail!("Header #{expected} is missing, the next one is #{number}")
+            }
+
+            let hash = header.hash_slow();
+            match canonical.seek_exact(number)? {
+                Some((_, canonical_hash)) if canonical_hash == hash => {}
+                Some((_, canonical_hash)) => {
+                    eyre::bail!(
+                        "Header #{number} does not match the canonical hash {canonical_hash:?}"
+                    )
+                }
+                None => eyre::bail!("Header #{number} has no canonical hash"),
+            }
+            if let Some((_, parent_hash)) = parent {
+                if header.parent_hash != parent_hash {
+                    eyre::bail!(
+                        "Header #{number} links to {:?} instead of the previous header {parent_hash:?}",
+                        header.parent_hash
+                    )
+                }
+            }
+
+            // The difficulty is zero from the merge onwards. The genesis difficulty is not checked,
+            // since the dev chains may start with a zero one.
+            if header.difficulty == U256::ZERO && number > 0 {
+                if !merged {
+                    info!(target: "reth::cli", block = number, %td, "Total difficulty stops increasing at the merge");
+                }
+                merged = true;
+            } else if merged {
+                eyre::bail!("Header #{number} has a difficulty after the merge, the total difficulty increases")
+            }
+            td += header.difficulty;
+
+            if number % LOG_INTERVAL == 0 && number > 0 {
+                info!(target: "reth::cli", block = number, %td, "Headers verified");
+            }
+            parent = Some((number, hash));
+        }
+
+        match parent {
+            Some((last, _)) if last == progress => {}
+            Some((last, _)) => {
+                eyre::bail!("Headers are synced up to block #{progress}, found only up to #{last}")
+            }
+            None => eyre::bail!("The headers database is empty"),
+        }
+        info!(target: "reth::cli", progress, %td, "Headers are consistent");
+        Ok(())
+    }
+}
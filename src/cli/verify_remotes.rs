@@ -0,0 +1,125 @@
+use crate::{
+    cli::{
+        dirs::LightConfigPath,
+        remote::{remote_store, RemoteArgs},
+        status::list_snapshots,
+    },
+    config::LightConfig,
+    database::{Checksum, Stage},
+    remote::{RemoteEntry, RemoteStore},
+};
+use clap::Parser;
+use reth::dirs::PlatformPath;
+use reth_primitives::ChainSpec;
+use reth_staged_sync::utils::chainspec::genesis_value_parser;
+use std::{collections::BTreeMap, path::PathBuf};
+
+/// Compare the snapshots published to two remote stores, for the setups that publish to a mirror.
+///
+/// The snapshots are compared by their database checksums if both copies have one, and by their
+/// compressed size otherwise. Fails if any snapshot is missing from either store or differs.
+#[derive(Debug, Parser)]
+pub struct Command {
+    #[arg(
+        long,
+        value_name = "CHAIN_OR_PATH",
+        verbatim_doc_comment,
+        default_value = "mainnet",
+        value_parser = genesis_value_parser
+    )]
+    chain: ChainSpec,
+
+    /// The path to the light node config, with the primary remote store settings.
+    #[arg(long, value_name = "FILE", verbatim_doc_comment, default_value_t)]
+    light_config: PlatformPath<LightConfigPath>,
+
+    /// The path to the light node config of the mirror, whose remote store settings are compared
+    /// against the primary ones.
+    #[arg(long, value_name = "FILE", verbatim_doc_comment)]
+    mirror_config: PathBuf,
+
+    #[clap(flatten)]
+    remote: RemoteArgs,
+}
+
+impl Command {
+    /// Execute `verify-remotes` command
+    pub async fn execute(self) -> eyre::Result<()> {
+        let light_config = LightConfig::load(&self.light_config)?;
+        let primary = self.remote.store(self.chain.chain, light_config.remote).await?;
+        // The config is not created with the defaults, since they point to the primary store.
+        if !self.mirror_config.exists() {
+            eyre::bail!("No mirror config found at {}", self.mirror_config.display())
+        }
+        let mirror_config = LightConfig::load(&self.mirror_config)?;
+        let mirror = remote_store(self.chain.chain, mirror_config.remote).await?;
+
+        let (primary_listings, mirror_listings) =
+            futures::try_join!(list_snapshots(&primary), list_snapshots(&mirror))?;
+
+        let mut diverged = 0;
+        for ((stage, primary_entries), mirror_entries) in
+            Stage::ALL.into_iter().zip(primary_listings).zip(mirror_listings)
+        {
+            let mut entries = BTreeMap::<String, (Option<RemoteEntry>, Option<RemoteEntry>)>::new();
+            for entry in primary_entries {
+                entries.entry(entry.key.clone()).or_default().0 = Some(entry);
+            }
+            for entry in mirror_entries {
+                entries.entry(entry.key.clone()).or_default().1 = Some(entry);
+            }
+
+            println!("{stage}: {} snapshot file(s)", entries.len());
+            for (key, pair) in entries {
+                let verdict = match pair {
+                    (Some(primary_entry), Some(mirror_entry)) => {
+                        compare(&key, &primary, &primary_entry, &mirror, &mirror_entry).await?
+                    }
+                    (Some(_), None) => Some("missing from the mirror".to_owned()),
+                    (None, Some(_)) => Some("missing from the primary".to_owned()),
+                    (None, None) => unreachable!(),
+                };
+                if let Some(verdict) = verdict {
+                    diverged += 1;
+                    println!("  {key}: {verdict}");
+                }
+            }
+        }
+
+        if diverged > 0 {
+            eyre::bail!("{diverged} snapshot file(s) differ between the stores")
+        }
+        println!("The stores have the same snapshots");
+        Ok(())
+    }
+}
+
+/// Compare the copies of the snapshot in both stores.
+/// Returns the description of the difference, or [None] if they match.
+async fn compare(
+    key: &str,
+    primary: &RemoteStore,
+    primary_entry: &RemoteEntry,
+    mirror: &RemoteStore,
+    mirror_entry: &RemoteEntry,
+) -> eyre::Result<Option<String>> {
+    let (primary_metadata, mirror_metadata) =
+        futures::try_join!(primary.metadata(key), mirror.metadata(key))?;
+    let primary_checksum = primary_metadata.as_ref().and_then(Checksum::from_metadata);
+    let mirror_checksum = mirror_metadata.as_ref().and_then(Checksum::from_metadata);
+
+    if let (Some(primary_checksum), Some(mirror_checksum)) = (primary_checksum, mirror_checksum) {
+        if primary_checksum.algorithm == mirror_checksum.algorithm {
+            return Ok((primary_checksum != mirror_checksum).then(|| {
+                format!(
+                    "checksum {} in the primary, {} in the mirror",
+                    primary_checksum.digest, mirror_checksum.digest
+                )
+            }))
+        }
+    }
+
+    Ok((primary_entry.size != mirror_entry.size).then(|| {
+        format!("{} bytes in the primary, {} in the mirror", primary_entry.size, mirror_entry.size)
+    }))
+}
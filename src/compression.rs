@@ -1,22 +1,188 @@
 use flate2::{write::GzEncoder, Compression};
+use gzp::{
+    deflate::Mgzip,
+    par::{compress::ParCompressBuilder, decompress::ParDecompressBuilder},
+    ZWriter,
+};
 use std::{
+    collections::HashMap,
     fs::File,
-    io::{copy, BufReader},
-    path::Path,
+    io::{self, copy, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
     time::Instant,
 };
 use tempfile::NamedTempFile;
+use tokio::task::JoinHandle;
+
+const COMPRESSION_FORMAT_KEY: &str = "compression";
+
+/// The magic bytes every gzip member starts with.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Returns `true` if the contents start with a gzip member.
+pub fn is_gzip(contents: &[u8]) -> bool {
+    contents.starts_with(&GZIP_MAGIC)
+}
+
+/// The format of the compressed snapshot files.
+/// Stored as the metadata of the remote snapshot object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionFormat {
+    /// A single gzip member, which can only be decompressed sequentially.
+    Gzip,
+    /// A series of independently compressed gzip members, which can be decompressed in parallel.
+    /// Sequential gzip decoders that read all the members decompress it as well.
+    Mgzip,
+}
+
+impl CompressionFormat {
+    /// Returns `true` if the format can be decompressed by multiple threads.
+    pub fn is_parallel(&self) -> bool {
+        matches!(self, CompressionFormat::Mgzip)
+    }
+
+    /// Encode the format as the object metadata.
+    pub fn to_metadata(&self) -> HashMap<String, String> {
+        let format = match self {
+            CompressionFormat::Gzip => "gzip",
+            CompressionFormat::Mgzip => "mgzip",
+        };
+        HashMap::from([(COMPRESSION_FORMAT_KEY.to_owned(), format.to_owned())])
+    }
+
+    /// Decode the format from the object metadata.
+    /// The snapshots published without the format are single gzip members.
+    pub fn from_metadata(metadata: &HashMap<String, String>) -> Self {
+        match metadata.get(COMPRESSION_FORMAT_KEY).map(String::as_str) {
+            Some("mgzip") => CompressionFormat::Mgzip,
+            _ => CompressionFormat::Gzip,
+        }
+    }
+}
+
+/// The file being compressed on a blocking thread by [spawn_compress_file].
+///
+/// The compression is cancelled once the handle is dropped, so that an abandoned upload does not
+/// keep the blocking thread, and the runtime shutdown, waiting for it.
+pub struct CompressionHandle {
+    handle: JoinHandle<eyre::Result<(NamedTempFile, CompressionFormat)>>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CompressionHandle {
+    /// Wait for the compression to complete.
+    pub async fn join(mut self) -> eyre::Result<(NamedTempFile, CompressionFormat)> {
+        (&mut self.handle).await?
+    }
 
-pub fn compress_file(path: &Path) -> eyre::Result<NamedTempFile> {
-    tracing::trace!(target: "compression", path = %path.display(), "Compressing file");
-    let mut input = BufReader::new(File::open(path)?);
-    let output = NamedTempFile::new()?;
-    let mut encoder = GzEncoder::new(output, Compression::default());
+    /// Stop the compression at the next read of the input file.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+}
+
+impl Drop for CompressionHandle {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+/// Compress the file into a temporary file in `temp_dir` on a blocking thread.
+/// See [compress_file] for the format of the compressed file.
+pub fn spawn_compress_file(
+    path: PathBuf,
+    temp_dir: PathBuf,
+    level: Compression,
+    threads: usize,
+) -> CompressionHandle {
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let input_cancelled = Arc::clone(&cancelled);
+    let handle = tokio::task::spawn_blocking(move || {
+        compress_file(
+            &path,
+            &temp_dir,
+            level,
+            threads,
+            CancellableReader { inner: File::open(&path)?, cancelled: input_cancelled },
+        )
+    });
+    CompressionHandle { handle, cancelled }
+}
+
+/// Compress the file read from the input into a temporary file in `temp_dir`.
+///
+/// The compressed database files can take several gigabytes, so the temporary directory should
+/// not be on a small tmpfs.
+///
+/// With more than one thread, the file is compressed in parallel into the
+/// [CompressionFormat::Mgzip] format. Otherwise, it is compressed into a single gzip member.
+fn compress_file<R: Read>(
+    path: &Path,
+    temp_dir: &Path,
+    level: Compression,
+    threads: usize,
+    input: R,
+) -> eyre::Result<(NamedTempFile, CompressionFormat)> {
+    tracing::trace!(target: "compression", path = %path.display(), level = level.level(), threads, "Compressing file");
+    let mut input = BufReader::new(input);
     let start = Instant::now();
-    copy(&mut input, &mut encoder)?;
-    let output = encoder.finish()?;
-    let source_len = input.get_ref().metadata()?.len();
+    let (output, format) = if threads > 1 {
+        let output = NamedTempFile::new_in(temp_dir)?;
+        // The encoder takes the ownership of the writer, so that it can be written from the
+        // compression threads.
+        let mut encoder = ParCompressBuilder::<Mgzip>::new()
+            .num_threads(threads)?
+            .compression_level(level)
+            .from_writer(output.reopen()?);
+        copy(&mut input, &mut encoder)?;
+        encoder.finish()?;
+        (output, CompressionFormat::Mgzip)
+    } else {
+        let mut encoder = GzEncoder::new(NamedTempFile::new_in(temp_dir)?, level);
+        copy(&mut input, &mut encoder)?;
+        (encoder.finish()?, CompressionFormat::Gzip)
+    };
+    let source_len = path.metadata()?.len();
     let target_len = output.as_file().metadata()?.len();
-    tracing::trace!(target: "compression", elapsed = start.elapsed().as_secs(), source_len, target_len, "Finished compressing");
-    Ok(output)
+    tracing::trace!(target: "compression", elapsed = start.elapsed().as_secs(), source_len, target_len, ?format, "Finished compressing");
+    Ok((output, format))
+}
+
+/// Fails the reads once the compression is cancelled.
+struct CancellableReader<R> {
+    inner: R,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl<R: Read> Read for CancellableReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.cancelled.load(Ordering::SeqCst) {
+            return Err(io::Error::new(io::ErrorKind::Other, "compression cancelled"))
+        }
+        self.inner.read(buf)
+    }
+}
+
+/// Decompress the [CompressionFormat::Mgzip] file into the `destination` with multiple threads.
+/// The destination file is synced to disk once the decompression completes.
+pub fn decompress_file_parallel(
+    source: File,
+    destination: &Path,
+    threads: usize,
+) -> eyre::Result<()> {
+    tracing::trace!(target: "compression", destination = %destination.display(), threads, "Decompressing file");
+    let start = Instant::now();
+    let mut decoder =
+        ParDecompressBuilder::<Mgzip>::new().num_threads(threads)?.from_reader(source);
+    let mut output = BufWriter::new(File::create(destination)?);
+    copy(&mut decoder, &mut output)?;
+    decoder.finish()?;
+    output.flush()?;
+    output.get_ref().sync_all()?;
+    tracing::trace!(target: "compression", elapsed = start.elapsed().as_secs(), "Finished decompressing");
+    Ok(())
 }
@@ -0,0 +1,108 @@
+use crate::{
+    database::ChecksumAlgorithm,
+    remote::{RemoteBackend, DEFAULT_BUCKET, DEFAULT_REGION},
+    sync::{
+        RetentionPolicy, DEFAULT_CLEANUP_CONCURRENCY, DEFAULT_CLEANUP_GRACE_PERIOD,
+        DEFAULT_COMPRESSION_LEVEL, DEFAULT_MAX_CONCURRENT_UPLOADS,
+    },
+};
+use eyre::WrapErr;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// The light node settings on top of the reth config, loaded from their own TOML file.
+///
+/// The command line flags take precedence over the values from the file.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LightConfig {
+    /// The remote store the snapshots are published to.
+    pub remote: RemoteConfig,
+    /// The snapshot publishing settings.
+    pub snapshots: SnapshotsConfig,
+}
+
+impl LightConfig {
+    /// Load the config from the file, creating it with the defaults if it does not exist.
+    pub fn load(path: impl AsRef<Path>) -> eyre::Result<Self> {
+        confy::load_path(path).wrap_err("Could not load light config")
+    }
+}
+
+/// The remote store settings.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RemoteConfig {
+    /// The kind of host the bucket is on.
+    pub backend: RemoteBackend,
+    /// The region of the bucket.
+    pub region: String,
+    /// The bucket the snapshots are published to.
+    pub bucket: String,
+    /// The endpoint URL of the S3-compatible host.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<String>,
+    /// The access key id. The credentials are read from the environment if not set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub access_key_id: Option<String>,
+    /// The secret access key.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret_access_key: Option<String>,
+}
+
+impl Default for RemoteConfig {
+    fn default() -> Self {
+        Self {
+            backend: RemoteBackend::Spaces,
+            region: DEFAULT_REGION.to_owned(),
+            bucket: DEFAULT_BUCKET.to_owned(),
+            endpoint: None,
+            access_key_id: None,
+            secret_access_key: None,
+        }
+    }
+}
+
+/// The snapshot publishing settings.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SnapshotsConfig {
+    /// The number of blocks between the state snapshots. Defaults to the interval for the chain.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state_interval: Option<u64>,
+    /// The gzip compression level of the database snapshots, from 0 to 9.
+    pub compression_level: u32,
+    /// The number of threads the database snapshots are compressed with. With more than one, the
+    /// snapshots can be decompressed in parallel, but not restored by the previous releases.
+    pub compression_threads: usize,
+    /// The maximum number of snapshots of all stages uploaded at the same time.
+    pub max_concurrent_uploads: usize,
+    /// The maximum number of previous snapshot entries deleted concurrently after an upload.
+    pub cleanup_concurrency: usize,
+    /// The number of seconds a previous snapshot entry is kept for after it was uploaded.
+    pub cleanup_grace_period: u64,
+    /// The hash function of the database checksums published along with the snapshots.
+    pub checksum_algorithm: ChecksumAlgorithm,
+    /// The directory the database snapshots are compressed into before the upload. Defaults to
+    /// the parent directory of each database.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temp_dir: Option<PathBuf>,
+    /// How many of the state snapshots are kept in the remote store.
+    pub retention: RetentionPolicy,
+}
+
+impl Default for SnapshotsConfig {
+    fn default() -> Self {
+        Self {
+            state_interval: None,
+            compression_level: DEFAULT_COMPRESSION_LEVEL,
+            compression_threads: 1,
+            max_concurrent_uploads: DEFAULT_MAX_CONCURRENT_UPLOADS,
+            cleanup_concurrency: DEFAULT_CLEANUP_CONCURRENCY,
+            cleanup_grace_period: DEFAULT_CLEANUP_GRACE_PERIOD.as_secs(),
+            checksum_algorithm: ChecksumAlgorithm::default(),
+            temp_dir: None,
+            retention: RetentionPolicy::default(),
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, fmt, fs::File, io, path::Path, str::FromStr};
+
+const CHECKSUM_KEY: &str = "checksum";
+const CHECKSUM_ALGORITHM_KEY: &str = "checksum-algo";
+
+/// The hash function the snapshot checksums are computed with.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChecksumAlgorithm {
+    /// SHA-256, supported by the common tooling.
+    #[default]
+    Sha256,
+    /// BLAKE3, much faster to compute for the large snapshots.
+    Blake3,
+}
+
+impl ChecksumAlgorithm {
+    /// All supported algorithms.
+    pub const ALL: [ChecksumAlgorithm; 2] = [ChecksumAlgorithm::Sha256, ChecksumAlgorithm::Blake3];
+}
+
+impl fmt::Display for ChecksumAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChecksumAlgorithm::Sha256 => f.write_str("sha256"),
+            ChecksumAlgorithm::Blake3 => f.write_str("blake3"),
+        }
+    }
+}
+
+impl FromStr for ChecksumAlgorithm {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ChecksumAlgorithm::ALL
+            .into_iter()
+            .find(|algorithm| algorithm.to_string() == s)
+            .ok_or_else(|| eyre::eyre!("unknown checksum algorithm {s}, expected sha256 or blake3"))
+    }
+}
+
+/// The checksum of the decompressed snapshot database file.
+/// Stored as the metadata of the remote snapshot object along with the manifest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checksum {
+    /// The hash function of the checksum.
+    pub algorithm: ChecksumAlgorithm,
+    /// The hex digest of the file.
+    pub digest: String,
+}
+
+impl Checksum {
+    /// Compute the checksum of the file at `path`.
+    pub fn compute(algorithm: ChecksumAlgorithm, path: &Path) -> eyre::Result<Self> {
+        let mut file = File::open(path)?;
+        let digest = match algorithm {
+            ChecksumAlgorithm::Sha256 => {
+                let mut hasher = Sha256::new();
+                io::copy(&mut file, &mut hasher)?;
+                format!("{:x}", hasher.finalize())
+            }
+            ChecksumAlgorithm::Blake3 => {
+                let mut hasher = blake3::Hasher::new();
+                io::copy(&mut file, &mut hasher)?;
+                hasher.finalize().to_hex().to_string()
+            }
+        };
+        Ok(Self { algorithm, digest })
+    }
+
+    /// Check that the file at `path` matches the checksum of the snapshot under `key`.
+    pub fn verify(&self, key: &str, path: &Path) -> eyre::Result<()> {
+        let actual = Self::compute(self.algorithm, path)?;
+        if actual.digest != self.digest {
+            eyre::bail!(
+                "Snapshot {key} failed the {} checksum verification: expected {}, got {}",
+                self.algorithm,
+                self.digest,
+                actual.digest,
+            )
+        }
+        Ok(())
+    }
+
+    /// Encode the checksum as the object metadata.
+    pub fn to_metadata(&self) -> HashMap<String, String> {
+        HashMap::from([
+            (CHECKSUM_KEY.to_owned(), self.digest.clone()),
+            (CHECKSUM_ALGORITHM_KEY.to_owned(), self.algorithm.to_string()),
+        ])
+    }
+
+    /// Decode the checksum from the object metadata.
+    /// Returns [None] if the snapshot was published without the checksum.
+    pub fn from_metadata(metadata: &HashMap<String, String>) -> Option<Self> {
+        let algorithm = metadata.get(CHECKSUM_ALGORITHM_KEY)?.parse().ok()?;
+        let digest = metadata.get(CHECKSUM_KEY)?.clone();
+        Some(Self { algorithm, digest })
+    }
+}
@@ -0,0 +1,216 @@
+use super::TxTypeCounts;
+use reth_db::{
+    cursor::{DbCursorRO, DbCursorRW},
+    database::Database,
+    mdbx::{Env, WriteMap},
+    table::Table,
+    tables,
+    transaction::{DbTx, DbTxMut},
+};
+use reth_primitives::BlockNumber;
+use reth_stages::stages::{BODIES, EXECUTION, HEADERS};
+
+/// Reconcile the progress of the stages before the sync starts from them.
+///
+/// The snapshots of the stages are taken at different times, so each database might be restored at
+/// a different height. The bodies ahead of the headers are truncated, so that the bodies sync
+/// resumes right after the headers, and the state is checked to have the bodies it resumes from.
+pub fn reconcile_progress(
+    headers: &Env<WriteMap>,
+    bodies: &Env<WriteMap>,
+    state: &Env<WriteMap>,
+) -> eyre::Result<()> {
+    let headers_progress = HEADERS.get_progress(&headers.tx()?)?.unwrap_or_default();
+    let mut bodies_progress = BODIES.get_progress(&bodies.tx()?)?.unwrap_or_default();
+    let state_progress = EXECUTION.get_progress(&state.tx()?)?.unwrap_or_default();
+    tracing::info!(
+        target: "database::consistency",
+        headers_progress,
+        bodies_progress,
+        state_progress,
+        "Reconciling the stage progress"
+    );
+
+    if bodies_progress > headers_progress {
+        tracing::warn!(
+            target: "database::consistency",
+            headers_progress,
+            bodies_progress,
+            "Bodies are ahead of the headers, resuming them from the headers"
+        );
+        truncate_bodies(bodies, headers_progress + 1)?;
+        bodies_progress = headers_progress;
+    }
+
+    if state_progress < bodies_progress {
+        // The execution reads the bodies from the block after the state onwards.
+        let next = state_progress + 1;
+        if bodies.tx()?.cursor_read::<tables::BlockBodies>()?.seek_exact(next)?.is_none() {
+            eyre::bail!(
+                "The state resumes from block #{next}, but there is no body for it while the bodies are synced up to #{bodies_progress}"
+            );
+        }
+    } else if state_progress > bodies_progress {
+        tracing::info!(
+            target: "database::consistency",
+            state_progress,
+            bodies_progress,
+            "State is ahead of the bodies, the execution resumes once they catch up"
+        );
+    }
+    Ok(())
+}
+
+/// Check that every local body has a canonical header, and truncate the bodies database to the
+/// first block that does not.
+///
+/// The headers and bodies snapshots are restored independently, so the bodies might end up ahead of
+/// the headers. This would fail the execution once it reaches a block without a header. The
+/// truncated bodies are downloaded again by the bodies sync.
+pub fn validate_bodies_against_headers(
+    headers: &Env<WriteMap>,
+    bodies: &Env<WriteMap>,
+) -> eyre::Result<()> {
+    let headers_tx = headers.tx()?;
+    let headers_progress = HEADERS.get_progress(&headers_tx)?.unwrap_or_default();
+    let bodies_progress = BODIES.get_progress(&bodies.tx()?)?.unwrap_or_default();
+    tracing::info!(
+        target: "database::consistency",
+        headers_progress,
+        bodies_progress,
+        "Validating bodies against headers"
+    );
+
+    let first_invalid = if bodies_progress > headers_progress {
+        Some(headers_progress + 1)
+    } else {
+        // The bodies are expected to be contiguous with the headers up to the bodies progress.
+        let mut canonical_cursor = headers_tx.cursor_read::<tables::CanonicalHeaders>()?;
+        let mut first_invalid = None;
+        for entry in bodies.tx()?.cursor_read::<tables::BlockBodies>()?.walk(None)? {
+            let (number, _) = entry?;
+            if canonical_cursor.seek_exact(number)?.is_none() {
+                first_invalid = Some(number);
+                break
+            }
+        }
+        first_invalid
+    };
+
+    let Some(from) = first_invalid else {
+        tracing::info!(target: "database::consistency", "Bodies are consistent with the headers");
+        return Ok(())
+    };
+    if from == 0 {
+        eyre::bail!("The genesis body has no canonical header, the headers database is empty");
+    }
+
+    tracing::warn!(
+        target: "database::consistency",
+        from,
+        bodies_progress,
+        headers_progress,
+        "Bodies without canonical headers found, truncating"
+    );
+    truncate_bodies(bodies, from)
+}
+
+/// Remove the bodies from the block onwards along with their transactions, and rewind the bodies
+/// progress to the preceding block.
+pub fn truncate_bodies(bodies: &Env<WriteMap>, from: BlockNumber) -> eyre::Result<()> {
+    if from == 0 {
+        eyre::bail!("The genesis body cannot be truncated")
+    }
+    // The transaction is aborted on any error, so that the bodies are not truncated partially.
+    let tx = bodies.tx_mut()?;
+    if let Some((_, body)) = tx.cursor_read::<tables::BlockBodies>()?.seek(from)? {
+        delete_from::<tables::Transactions>(&tx, body.start_tx_id)?;
+    }
+    delete_from::<tables::BlockBodies>(&tx, from)?;
+    delete_from::<tables::BlockOmmers>(&tx, from)?;
+    delete_from::<tables::BlockWithdrawals>(&tx, from)?;
+    // The transaction types are tallied anew if the stats are enabled.
+    TxTypeCounts::clear(&tx)?;
+    BODIES.save_progress(&tx, from - 1)?;
+    tx.commit()?;
+    tracing::info!(target: "database::consistency", progress = from - 1, "Bodies truncated");
+    Ok(())
+}
+
+/// Delete all entries of the table starting from the key.
+fn delete_from<'tx, T: Table<Key = u64>>(tx: &impl DbTxMut<'tx>, from: u64) -> eyre::Result<()> {
+    let mut cursor = tx.cursor_write::<T>()?;
+    while cursor.seek(from)?.is_some() {
+        cursor.delete_current()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::{test_utils::create_test_db, Stage},
+        sync::test_utils::{insert_empty_blocks, test_genesis, test_headers},
+    };
+
+    /// The numbers of the stored bodies.
+    fn body_numbers(bodies: &Env<WriteMap>) -> Vec<BlockNumber> {
+        let tx = bodies.tx().unwrap();
+        let mut numbers = Vec::new();
+        for entry in tx.cursor_read::<tables::BlockBodies>().unwrap().walk(None).unwrap() {
+            numbers.push(entry.unwrap().0);
+        }
+        numbers
+    }
+
+    #[test]
+    fn validate_truncates_bodies_ahead_of_headers() {
+        let (_headers_dir, headers) = create_test_db(Stage::Headers);
+        let (_bodies_dir, bodies) = create_test_db(Stage::Bodies);
+        insert_empty_blocks(&headers, &bodies, &test_headers(&test_genesis(), 4)).unwrap();
+        // The headers are restored from an older snapshot than the bodies.
+        headers
+            .update(|tx| {
+                for number in 3..=4 {
+                    tx.delete::<tables::CanonicalHeaders>(number, None)?;
+                    tx.delete::<tables::Headers>(number, None)?;
+                }
+                HEADERS.save_progress(tx, 2)
+            })
+            .unwrap()
+            .unwrap();
+
+        validate_bodies_against_headers(&headers, &bodies).unwrap();
+        assert_eq!(BODIES.get_progress(&bodies.tx().unwrap()).unwrap(), Some(2));
+        assert_eq!(body_numbers(&bodies), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn validate_truncates_bodies_from_gap() {
+        let (_headers_dir, headers) = create_test_db(Stage::Headers);
+        let (_bodies_dir, bodies) = create_test_db(Stage::Bodies);
+        insert_empty_blocks(&headers, &bodies, &test_headers(&test_genesis(), 4)).unwrap();
+        // The bodies past the missing canonical header are truncated along with its own.
+        headers.update(|tx| tx.delete::<tables::CanonicalHeaders>(2, None)).unwrap().unwrap();
+
+        validate_bodies_against_headers(&headers, &bodies).unwrap();
+        assert_eq!(BODIES.get_progress(&bodies.tx().unwrap()).unwrap(), Some(1));
+        assert_eq!(body_numbers(&bodies), vec![0, 1]);
+    }
+
+    #[test]
+    fn truncate_bodies_keeps_genesis() {
+        let (_headers_dir, headers) = create_test_db(Stage::Headers);
+        let (_bodies_dir, bodies) = create_test_db(Stage::Bodies);
+        insert_empty_blocks(&headers, &bodies, &test_headers(&test_genesis(), 2)).unwrap();
+
+        let error = truncate_bodies(&bodies, 0).unwrap_err();
+        assert!(error.to_string().contains("genesis body"), "{error}");
+        assert_eq!(BODIES.get_progress(&bodies.tx().unwrap()).unwrap(), Some(2));
+        assert_eq!(body_numbers(&bodies), vec![0, 1, 2]);
+        // The consistent bodies are left as is.
+        validate_bodies_against_headers(&headers, &bodies).unwrap();
+        assert_eq!(body_numbers(&bodies), vec![0, 1, 2]);
+    }
+}
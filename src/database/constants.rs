@@ -1,9 +1,11 @@
 use reth_db::{tables, TableType};
 
 pub const MDBX_DAT: &str = "mdbx.dat";
+pub const MDBX_LCK: &str = "mdbx.lck";
 pub const DAT_GZ_EXT: &str = ".dat.gz";
 
 pub const HEADERS_PREFIX: &str = "headers-";
+pub const HEADERS_DIFF_PREFIX: &str = "headers-diff-";
 pub const HEADERS_TABLES: [(TableType, &str); 3] = [
     (TableType::Table, tables::SyncStage::const_name()),
     (TableType::Table, tables::Headers::const_name()),
@@ -20,9 +22,12 @@ pub const BODIES_TABLES: [(TableType, &str); 5] = [
 ];
 
 pub const STATE_PREFIX: &str = "state-snapshots/state-";
-pub const STATE_TABLES: [(TableType, &str); 4] = [
+pub const STATE_DIFF_PREFIX: &str = "state-snapshots/state-diff-";
+pub const STATE_TABLES: [(TableType, &str); 5] = [
     (TableType::Table, tables::SyncStage::const_name()),
     (TableType::Table, tables::PlainAccountState::const_name()),
     (TableType::DupSort, tables::PlainStorageState::const_name()),
     (TableType::Table, tables::Bytecodes::const_name()),
+    // Holds the total difficulty at the execution progress only.
+    (TableType::Table, tables::HeaderTD::const_name()),
 ];
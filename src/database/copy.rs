@@ -0,0 +1,137 @@
+use reth_db::{
+    mdbx::{DatabaseFlags, Env, WriteFlags, WriteMap},
+    TableType,
+};
+use std::borrow::Cow;
+
+/// The number of entries copied before the target transaction is committed.
+const COPY_COMMIT_THRESHOLD: usize = 100_000;
+
+/// Create every table of one database in another with the same flags, and copy their entries.
+///
+/// The tables are listed from the main table of the source database, which holds an entry for
+/// each named table, so that the tables outside of the stage ones are copied too.
+pub fn copy_database(from: &Env<WriteMap>, to: &Env<WriteMap>) -> eyre::Result<()> {
+    let mut tables = Vec::new();
+    {
+        let source_tx = from.inner.begin_ro_txn()?;
+        let main_db = source_tx.open_db(None)?;
+        let mut cursor = source_tx.cursor(&main_db)?;
+        for entry in cursor.iter_start::<Cow<'_, [u8]>, Cow<'_, [u8]>>() {
+            let (name, _) = entry?;
+            let name = String::from_utf8(name.into_owned())?;
+            let flags = source_tx.db_flags(&source_tx.open_db(Some(name.as_str()))?)?;
+            tables.push((name, flags));
+        }
+    }
+
+    let target_tx = to.inner.begin_rw_txn()?;
+    for (name, flags) in &tables {
+        target_tx.create_db(Some(name.as_str()), *flags)?;
+    }
+    target_tx.commit()?;
+
+    let tables = tables
+        .iter()
+        .map(|(name, flags)| {
+            let table_type = if flags.contains(DatabaseFlags::DUP_SORT) {
+                TableType::DupSort
+            } else {
+                TableType::Table
+            };
+            (table_type, name.as_str())
+        })
+        .collect::<Vec<_>>();
+    copy_tables(from, to, &tables)
+}
+
+/// Copy all entries of the tables from one database into another, in key order.
+///
+/// The tables must already exist in the target database. Since only the live entries are
+/// copied, the target file does not contain the free pages left over by the deleted entries.
+pub fn copy_tables(
+    from: &Env<WriteMap>,
+    to: &Env<WriteMap>,
+    tables: &[(TableType, &str)],
+) -> eyre::Result<()> {
+    let source_tx = from.inner.begin_ro_txn()?;
+    for (_, table) in tables {
+        let source_db = source_tx.open_db(Some(*table))?;
+        let mut cursor = source_tx.cursor(&source_db)?;
+
+        let mut entries = cursor.iter_start::<Cow<'_, [u8]>, Cow<'_, [u8]>>();
+        let mut copied = 0;
+        loop {
+            let target_tx = to.inner.begin_rw_txn()?;
+            let target_db = target_tx.open_db(Some(*table))?;
+            let mut chunk = 0;
+            for entry in entries.by_ref().take(COPY_COMMIT_THRESHOLD) {
+                let (key, value) = entry?;
+                target_tx.put(&target_db, key, value, WriteFlags::empty())?;
+                chunk += 1;
+            }
+            target_tx.commit()?;
+
+            copied += chunk;
+            if chunk < COPY_COMMIT_THRESHOLD {
+                break
+            }
+        }
+        tracing::trace!(target: "database::copy", table, copied, "Copied table");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{test_utils::create_test_db, DatabaseGeometry, Stage};
+    use reth_db::{
+        cursor::DbDupCursorRO,
+        database::Database,
+        mdbx::EnvKind,
+        tables,
+        transaction::{DbTx, DbTxMut},
+    };
+    use reth_primitives::{Address, StorageEntry, H256, U256};
+
+    #[test]
+    fn copy_database_with_all_tables() {
+        let (_source_dir, source) = create_test_db(Stage::State);
+        let address = Address::from_low_u64_be(0x10);
+        source
+            .update(|tx| {
+                for n in 1..=2 {
+                    let entry =
+                        StorageEntry { key: H256::from_low_u64_be(n), value: U256::from(n) };
+                    tx.put::<tables::PlainStorageState>(address, entry)?;
+                }
+                Ok::<_, reth_db::Error>(())
+            })
+            .unwrap()
+            .unwrap();
+        // A table outside of the stage ones.
+        let tx = source.inner.begin_rw_txn().unwrap();
+        let extra = tx.create_db(Some("Extra"), DatabaseFlags::default()).unwrap();
+        tx.put(&extra, b"key", b"value", WriteFlags::empty()).unwrap();
+        tx.commit().unwrap();
+
+        let target_dir = tempfile::tempdir().unwrap();
+        let geometry = DatabaseGeometry::of(&source).unwrap();
+        let target = geometry.open(target_dir.path(), EnvKind::RW).unwrap();
+        copy_database(&source, &target).unwrap();
+        assert_eq!(DatabaseGeometry::of(&target).unwrap(), geometry);
+
+        // The storage table is still dup-sorted, with both slots of the account.
+        let tx = target.tx().unwrap();
+        let mut cursor = tx.cursor_dup_read::<tables::PlainStorageState>().unwrap();
+        let second = StorageEntry { key: H256::from_low_u64_be(2), value: U256::from(2) };
+        assert_eq!(cursor.seek_by_key_subkey(address, second.key).unwrap(), Some(second));
+        drop((cursor, tx));
+
+        let tx = target.inner.begin_ro_txn().unwrap();
+        let extra = tx.open_db(Some("Extra")).unwrap();
+        let value = tx.get::<Cow<'_, [u8]>>(&extra, b"key").unwrap();
+        assert_eq!(value.as_deref(), Some(&b"value"[..]));
+    }
+}
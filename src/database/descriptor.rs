@@ -18,6 +18,20 @@ pub trait DatabaseDescriptor<DB: Database> {
     fn ensure_genesis(&self, db: DB, chain_spec: ChainSpec) -> eyre::Result<()>;
 }
 
+impl<DB: Database, T: DatabaseDescriptor<DB> + ?Sized> DatabaseDescriptor<DB> for &T {
+    fn default_tables(&self) -> &[(TableType, &str)] {
+        (**self).default_tables()
+    }
+
+    fn progress(&self, db: DB) -> eyre::Result<Option<BlockNumber>> {
+        (**self).progress(db)
+    }
+
+    fn ensure_genesis(&self, db: DB, chain_spec: ChainSpec) -> eyre::Result<()> {
+        (**self).ensure_genesis(db, chain_spec)
+    }
+}
+
 pub struct HeadersDescriptor;
 impl<DB: Database> DatabaseDescriptor<DB> for HeadersDescriptor {
     fn default_tables(&self) -> &[(TableType, &str)] {
@@ -68,8 +82,22 @@ impl<DB: Database> DatabaseDescriptor<DB> for BodiesDescriptor {
     fn ensure_genesis(&self, db: DB, _chain_spec: ChainSpec) -> eyre::Result<()> {
         let progress =
             db.view(|tx| tx.get::<tables::SyncStage>(BODIES.0.as_bytes().to_vec()))??;
-        if progress.is_none() {
-            db.update(|tx| tx.put::<tables::BlockBodies>(0, Default::default()))??;
+        if progress.is_some() {
+            return Ok(())
+        }
+
+        // A restored snapshot may have the bodies without the progress, which is then
+        // reconstructed from the last body instead of overwriting the genesis one.
+        let last = db.view(|tx| tx.cursor_read::<tables::BlockBodies>()?.last())??;
+        match last {
+            Some((number, _)) => {
+                tracing::warn!(target: "database::init", progress = number, "Bodies progress is missing, reconstructing it from the last body");
+                db.update(|tx| BODIES.save_progress(tx, number))??;
+            }
+            None => {
+                tracing::debug!("Writing genesis body.");
+                db.update(|tx| tx.put::<tables::BlockBodies>(0, Default::default()))??;
+            }
         }
         Ok(())
     }
@@ -106,3 +134,38 @@ impl<DB: Database> DatabaseDescriptor<DB> for StateDescriptor {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{
+        test_utils::{create_test_db, test_chain_spec},
+        Stage,
+    };
+    use reth_db::models::StoredBlockBody;
+    use std::sync::Arc;
+
+    #[test]
+    fn bodies_ensure_genesis_reconstructs_progress() {
+        let (_dir, db) = create_test_db(Stage::Bodies);
+        // The bodies of a restored snapshot without the progress row.
+        let genesis_body = StoredBlockBody { start_tx_id: 0, tx_count: 1 };
+        db.update(|tx| {
+            tx.delete::<tables::SyncStage>(BODIES.0.as_bytes().to_vec(), None)?;
+            tx.put::<tables::BlockBodies>(0, genesis_body.clone())?;
+            for number in 1..=3 {
+                let body = StoredBlockBody { start_tx_id: number, tx_count: 0 };
+                tx.put::<tables::BlockBodies>(number, body)?;
+            }
+            Ok::<_, reth_db::Error>(())
+        })
+        .unwrap()
+        .unwrap();
+
+        BodiesDescriptor.ensure_genesis(Arc::clone(&db), test_chain_spec()).unwrap();
+
+        assert_eq!(BodiesDescriptor.progress(Arc::clone(&db)).unwrap(), Some(3));
+        let tx = db.tx().unwrap();
+        assert_eq!(tx.get::<tables::BlockBodies>(0).unwrap(), Some(genesis_body));
+    }
+}
@@ -0,0 +1,182 @@
+use super::{DAT_GZ_EXT, HEADERS_DIFF_PREFIX};
+use flate2::{write::GzEncoder, Compression};
+use reth_db::{
+    cursor::{DbCursorRO, DbCursorRW},
+    database::Database,
+    tables,
+    transaction::{DbTx, DbTxMut},
+    Error as DatabaseError,
+};
+use reth_primitives::{BlockNumber, Header};
+use reth_rlp::Decodable;
+use reth_stages::stages::HEADERS;
+use std::{fmt, io::Write, ops::RangeInclusive, path::Path};
+use tempfile::NamedTempFile;
+
+/// The key of a remote headers diff in the form of `{HEADERS_DIFF_PREFIX}{from}-{to}{DAT_GZ_EXT}`.
+///
+/// A diff contains the RLP encoded list of canonical headers in the `from..=to` range and is
+/// applied on top of a headers database synced up to `from - 1`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeadersDiffKey {
+    /// The first block in the diff.
+    pub from: BlockNumber,
+    /// The last block in the diff.
+    pub to: BlockNumber,
+}
+
+impl HeadersDiffKey {
+    /// Parse the headers diff key.
+    /// Returns [None] if the key does not belong to a headers diff.
+    pub fn parse(key: &str) -> Option<Self> {
+        let range = key.strip_prefix(HEADERS_DIFF_PREFIX)?.strip_suffix(DAT_GZ_EXT)?;
+        let (from, to) = range.split_once('-')?;
+        let is_number = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+        if !is_number(from) || !is_number(to) {
+            return None
+        }
+        let key = Self { from: from.parse().ok()?, to: to.parse().ok()? };
+        (key.from <= key.to).then_some(key)
+    }
+}
+
+impl fmt::Display for HeadersDiffKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{HEADERS_DIFF_PREFIX}{}-{}{DAT_GZ_EXT}", self.from, self.to)
+    }
+}
+
+/// Resolve the chain of diffs that extends the headers synced up to `progress` as far as possible.
+///
+/// Diffs that partially overlap with the already synced headers are included, since the overlap
+/// is skipped when the diff is applied.
+pub fn headers_diff_chain<'a>(
+    keys: impl IntoIterator<Item = &'a str>,
+    mut progress: BlockNumber,
+) -> Vec<HeadersDiffKey> {
+    let diffs = keys.into_iter().filter_map(HeadersDiffKey::parse).collect::<Vec<_>>();
+
+    let mut chain = Vec::new();
+    while let Some(next) =
+        diffs.iter().filter(|d| d.from <= progress + 1 && d.to > progress).max_by_key(|d| d.to)
+    {
+        progress = next.to;
+        chain.push(*next);
+    }
+    chain
+}
+
+/// Write the canonical headers in the range into a compressed diff file in `temp_dir`.
+pub fn write_headers_diff<DB: Database>(
+    db: &DB,
+    range: RangeInclusive<BlockNumber>,
+    temp_dir: &Path,
+) -> eyre::Result<NamedTempFile> {
+    let headers = db.view(|tx| {
+        tx.cursor_read::<tables::Headers>()?
+            .walk_range(range.clone())?
+            .map(|entry| entry.map(|(_, header)| header))
+            .collect::<Result<Vec<_>, _>>()
+    })??;
+
+    let expected = range.end() - range.start() + 1;
+    if headers.len() as u64 != expected {
+        eyre::bail!("Expected {expected} headers in range {range:?}, found {}", headers.len())
+    }
+
+    let mut encoded = Vec::new();
+    reth_rlp::encode_list(&headers, &mut encoded);
+
+    let mut encoder = GzEncoder::new(NamedTempFile::new_in(temp_dir)?, Compression::default());
+    encoder.write_all(&encoded)?;
+    Ok(encoder.finish()?)
+}
+
+/// Apply the decompressed diff contents on top of the headers database.
+///
+/// The headers at or below the current progress are skipped. The headers are applied until the
+/// first one that does not extend the local canonical chain. Returns the new progress and whether
+/// the whole diff was applied.
+pub fn apply_headers_diff<DB: Database>(
+    db: &DB,
+    contents: &[u8],
+) -> eyre::Result<(BlockNumber, bool)> {
+    let headers = Vec::<Header>::decode(&mut &contents[..])?;
+
+    let applied = db.update(|tx| {
+        let mut progress = HEADERS.get_progress(tx)?.unwrap_or_default();
+        let mut cursor_header = tx.cursor_write::<tables::Headers>()?;
+        let mut cursor_canonical = tx.cursor_write::<tables::CanonicalHeaders>()?;
+
+        let mut complete = true;
+        for header in headers.into_iter().skip_while(|h| h.number <= progress) {
+            let parent = cursor_canonical.seek_exact(progress)?.map(|(_, hash)| hash);
+            if header.number != progress + 1 || parent != Some(header.parent_hash) {
+                complete = false;
+                break
+            }
+
+            let sealed = header.seal_slow();
+            progress = sealed.number;
+            cursor_canonical.append(progress, sealed.hash())?;
+            cursor_header.append(progress, sealed.unseal())?;
+        }
+
+        HEADERS.save_progress(tx, progress)?;
+        Ok::<_, DatabaseError>((progress, complete))
+    })??;
+
+    Ok(applied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_headers_diff_keys() {
+        let key = HeadersDiffKey { from: 11, to: 20 };
+        assert_eq!(key.to_string(), "headers-diff-11-20.dat.gz");
+        assert_eq!(HeadersDiffKey::parse(&key.to_string()), Some(key));
+        assert_eq!(
+            HeadersDiffKey::parse("headers-diff-5-5.dat.gz"),
+            Some(HeadersDiffKey { from: 5, to: 5 })
+        );
+
+        for key in [
+            "headers-diff-20-11.dat.gz",
+            "headers-diff-11.dat.gz",
+            "headers-diff-11-.dat.gz",
+            "headers-diff--20.dat.gz",
+            "headers-diff-11-20-30.dat.gz",
+            "headers-diff-a-b.dat.gz",
+            "headers-diff-11-20.dat",
+            "headers-20.dat.gz",
+            "state-snapshots/state-diff-11-20.dat.gz",
+            "README.md",
+        ] {
+            assert_eq!(HeadersDiffKey::parse(key), None, "{key}");
+        }
+    }
+
+    #[test]
+    fn headers_diff_chain_from_progress() {
+        let keys = [
+            "headers-diff-1-10.dat.gz",
+            "headers-diff-11-20.dat.gz",
+            "headers-diff-15-30.dat.gz",
+            "headers-diff-32-40.dat.gz",
+            "headers-10.dat.gz",
+            "README.md",
+        ];
+        let chain = headers_diff_chain(keys, 10);
+        assert_eq!(
+            chain,
+            vec![HeadersDiffKey { from: 11, to: 20 }, HeadersDiffKey { from: 15, to: 30 }]
+        );
+
+        // The diffs that do not extend the progress are not in the chain.
+        assert!(headers_diff_chain(keys, 30).is_empty());
+        assert!(headers_diff_chain(keys, 40).is_empty());
+    }
+}
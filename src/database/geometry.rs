@@ -0,0 +1,99 @@
+use reth_db::{
+    mdbx::{
+        Env, EnvKind, Environment, EnvironmentFlags, Geometry, Mode, PageSize, SyncMode, WriteMap,
+    },
+    tables::TABLES,
+    utils::default_page_size,
+};
+use reth_primitives::Chain;
+use std::path::Path;
+
+const GIGABYTE: usize = 1024 * 1024 * 1024;
+const MEGABYTE: isize = 1024 * 1024;
+
+/// The size limits of the database file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DatabaseGeometry {
+    /// The maximum size of the database file in bytes.
+    pub max_size: usize,
+    /// The number of bytes the file grows by once it is full.
+    pub growth_step: isize,
+}
+
+impl Default for DatabaseGeometry {
+    /// The geometry reth opens its databases with, which fits the mainnet.
+    fn default() -> Self {
+        Self { max_size: 4 * 1024 * GIGABYTE, growth_step: 256 * MEGABYTE }
+    }
+}
+
+impl DatabaseGeometry {
+    /// The geometry sized for the chain. The mainnet gets the full reth geometry, the public
+    /// testnets a smaller one, and any other chain is assumed to be a small dev chain.
+    pub fn for_chain(chain: Chain) -> Self {
+        if chain == Chain::mainnet() {
+            Self::default()
+        } else if chain == Chain::goerli() || chain == Chain::sepolia() {
+            Self { max_size: 1024 * GIGABYTE, growth_step: 128 * MEGABYTE }
+        } else {
+            Self { max_size: 64 * GIGABYTE, growth_step: 16 * MEGABYTE }
+        }
+    }
+
+    /// Override the maximum size of the database file, in gigabytes.
+    /// It must not be lower than the size of the existing file.
+    pub fn with_max_size_gb(mut self, max_size_gb: Option<usize>) -> Self {
+        if let Some(max_size_gb) = max_size_gb {
+            self.max_size = max_size_gb * GIGABYTE;
+        }
+        self
+    }
+
+    /// The geometry the existing database was created with, as stored in its file.
+    pub fn of(db: &Env<WriteMap>) -> eyre::Result<Self> {
+        let geometry = db.inner.info()?.geometry();
+        Ok(Self { max_size: geometry.max() as usize, growth_step: geometry.grow() as isize })
+    }
+
+    /// Open the database environment with this geometry, with the same flags as [Env::open].
+    pub fn open(&self, path: &Path, kind: EnvKind) -> eyre::Result<Env<WriteMap>> {
+        let mode = match kind {
+            EnvKind::RO => Mode::ReadOnly,
+            EnvKind::RW => Mode::ReadWrite { sync_mode: SyncMode::Durable },
+        };
+        let inner = Environment::new()
+            .set_max_dbs(TABLES.len())
+            .set_geometry(Geometry {
+                size: Some(0..self.max_size),
+                growth_step: Some(self.growth_step),
+                shrink_threshold: None,
+                page_size: Some(PageSize::Set(default_page_size())),
+            })
+            .set_flags(EnvironmentFlags {
+                mode,
+                no_rdahead: true,
+                coalesce: true,
+                ..Default::default()
+            })
+            .open(path)?;
+        Ok(Env { inner })
+    }
+}
+
+/// Open the existing database environment read-only, with the same flags as [Env::open].
+///
+/// The geometry is not set, so that it is taken from the database file as set by the process
+/// that writes to it. A second process can then read the database while it is being synced into.
+/// Its read transactions see the last committed state.
+pub(crate) fn open_read_only(path: &Path) -> eyre::Result<Env<WriteMap>> {
+    let inner = Environment::new()
+        .set_max_dbs(TABLES.len())
+        .set_flags(EnvironmentFlags {
+            mode: Mode::ReadOnly,
+            no_rdahead: true,
+            coalesce: true,
+            ..Default::default()
+        })
+        .open(path)?;
+    Ok(Env { inner })
+}
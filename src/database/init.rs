@@ -1,27 +1,54 @@
-use super::{descriptor::DatabaseDescriptor, DAT_GZ_EXT, MDBX_DAT};
-use crate::remote::RemoteStore;
+use super::{
+    apply_headers_diff, apply_state_diff, descriptor::DatabaseDescriptor, geometry,
+    headers_diff_chain, state_diff_chain, Checksum, DatabaseGeometry, DatabaseLock, Snapshot,
+    SnapshotManifest, Stage, HEADERS_DIFF_PREFIX, MDBX_DAT, MDBX_LCK, STATE_DIFF_PREFIX,
+};
+use crate::{compression::CompressionFormat, remote::RemoteStore};
 use itertools::Itertools;
 use reth_db::{
     mdbx::{DatabaseFlags, Env, EnvKind, WriteMap},
     TableType,
 };
-use reth_primitives::ChainSpec;
+use reth_primitives::{Chain, ChainSpec};
+use reth_staged_sync::utils::init::InitDatabaseError;
 use std::{
-    fs,
+    borrow::Cow,
     path::{Path, PathBuf},
     sync::Arc,
+    time::Duration,
 };
 
-#[derive(Default)]
+/// The number of attempts to open the restored database.
+const OPEN_ATTEMPTS: u32 = 5;
+
+/// The delay before retrying to open the restored database, doubled after each attempt.
+const OPEN_RETRY_DELAY: Duration = Duration::from_millis(200);
+
 pub struct DatabaseInitializer {
-    prefix: String,
+    stage: Stage,
     path: PathBuf,
+    resume_from_remote: bool,
+    force: bool,
+    force_genesis: bool,
+    no_genesis_check: bool,
+    decompress_threads: usize,
+    geometry: Option<DatabaseGeometry>,
+    verify_restore: bool,
 }
 
 impl DatabaseInitializer {
-    pub fn with_prefix(mut self, prefix: &str) -> Self {
-        self.prefix = prefix.to_owned();
-        self
+    pub fn new(stage: Stage) -> Self {
+        Self {
+            stage,
+            path: PathBuf::default(),
+            resume_from_remote: false,
+            force: false,
+            force_genesis: false,
+            no_genesis_check: false,
+            decompress_threads: 1,
+            geometry: None,
+            verify_restore: false,
+        }
     }
 
     pub fn with_path<P: AsRef<Path>>(mut self, path: P) -> Self {
@@ -29,76 +56,398 @@ impl DatabaseInitializer {
         self
     }
 
+    /// Always restore the best remote snapshot, even if it is not ahead of the local progress.
+    pub fn with_resume_from_remote(mut self, resume_from_remote: bool) -> Self {
+        self.resume_from_remote = resume_from_remote;
+        self
+    }
+
+    /// Allow the restore to rewind the local progress with [Self::with_resume_from_remote].
+    pub fn with_force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// Wipe the local database and create it anew if its genesis does not match the chain spec.
+    pub fn with_force_genesis(mut self, force_genesis: bool) -> Self {
+        self.force_genesis = force_genesis;
+        self
+    }
+
+    /// Only warn if the genesis of the local database does not match the chain spec, and proceed on
+    /// the existing data.
+    pub fn with_no_genesis_check(mut self, no_genesis_check: bool) -> Self {
+        self.no_genesis_check = no_genesis_check;
+        self
+    }
+
+    /// Decompress the restored snapshots with multiple threads if their format allows it.
+    pub fn with_decompress_threads(mut self, decompress_threads: usize) -> Self {
+        self.decompress_threads = decompress_threads.max(1);
+        self
+    }
+
+    /// Open the database with the geometry instead of the default one for the chain.
+    pub fn with_geometry(mut self, geometry: DatabaseGeometry) -> Self {
+        self.geometry = Some(geometry);
+        self
+    }
+
+    /// Walk all entries of the stage tables once a snapshot is restored, to detect a structurally
+    /// truncated database file before syncing on top of it.
+    pub fn with_verify_restore(mut self, verify_restore: bool) -> Self {
+        self.verify_restore = verify_restore;
+        self
+    }
+
+    /// Acquire the lock on the database directory, so that no other process syncs into it.
+    pub fn lock(&self) -> eyre::Result<DatabaseLock> {
+        DatabaseLock::acquire(&self.path)
+    }
+
     pub async fn init(
         &self,
         remote: &RemoteStore,
         chain_spec: ChainSpec,
-        descriptor: impl DatabaseDescriptor<Arc<Env<WriteMap>>>,
     ) -> eyre::Result<Arc<Env<WriteMap>>> {
+        let descriptor = self.stage.descriptor();
         // Initialize local database (create if does not exist).
-        let local = self.initialize_database(descriptor.default_tables())?;
+        let local = self.initialize_for_chain(&chain_spec)?;
         // Get database progress.
         let progress = descriptor.progress(Arc::clone(&local))?.unwrap_or_default();
         // Restore database if remote has more data.
-        let db = self.restore_database(local, remote, progress).await?;
-        descriptor.ensure_genesis(Arc::clone(&db), chain_spec)?;
+        let db = self.restore_database(local, remote, &chain_spec, progress).await?;
+        // Headers are also published as diffs on top of the base snapshots.
+        if self.stage == Stage::Headers {
+            let progress = descriptor.progress(Arc::clone(&db))?.unwrap_or_default();
+            self.apply_headers_diffs(&db, remote, progress).await?;
+        }
+        // So is the state, if published with the state diffs.
+        if self.stage == Stage::State {
+            let progress = descriptor.progress(Arc::clone(&db))?.unwrap_or_default();
+            self.apply_state_diffs(&db, remote, progress).await?;
+        }
+        self.ensure_genesis(&db, chain_spec)?;
+        Ok(db)
+    }
+
+    /// Initialize the local database without restoring it from the remote.
+    pub fn init_local(&self, chain_spec: ChainSpec) -> eyre::Result<Arc<Env<WriteMap>>> {
+        let db = self.initialize_for_chain(&chain_spec)?;
+        self.ensure_genesis(&db, chain_spec)?;
         Ok(db)
     }
 
+    /// Create the local database with the stage tables, without checking the genesis.
+    pub fn create_local(&self) -> eyre::Result<Arc<Env<WriteMap>>> {
+        let geometry = self.geometry.unwrap_or_default();
+        self.initialize_database(self.stage.descriptor().default_tables(), geometry)
+    }
+
+    /// Open the existing local database read-only.
+    /// The tables are not created and the genesis is not checked, so that the database can be
+    /// inspected while it is being written to by another process. The read transactions should
+    /// be short-lived, since the writer cannot reuse the pages they still see.
+    pub fn open_read_only(&self) -> eyre::Result<Arc<Env<WriteMap>>> {
+        if !self.path.join(MDBX_DAT).exists() {
+            eyre::bail!("No {} database found at {}", self.stage, self.path.display())
+        }
+        Ok(Arc::new(geometry::open_read_only(&self.path)?))
+    }
+
     async fn restore_database(
         &self,
         local: Arc<Env<WriteMap>>,
         remote: &RemoteStore,
+        chain_spec: &ChainSpec,
         progress: u64,
     ) -> eyre::Result<Arc<Env<WriteMap>>> {
-        let snapshots = remote.list(Some(&self.prefix)).await?;
+        let snapshots = remote.list(Some(self.stage.prefix())).await?;
 
-        // Sort snapshots by key
+        // Sort snapshots by key, skipping any unrelated entries
         let snapshots = snapshots
             .into_iter()
-            .map(|s| {
-                let key = s.key().unwrap();
-                (key.to_owned(), self.get_snapshot_progress(key))
+            .filter_map(|entry| {
+                let snapshot = Snapshot::parse_for(&entry.key, self.stage)?;
+                Some((entry.key, snapshot.block))
             })
             .sorted_by_key(|s| s.1);
         // Filter snapshot by local progress
-        let best_snapshot = snapshots.rev().next().filter(|s| s.1 > progress);
+        let best_snapshot =
+            snapshots.rev().next().filter(|s| self.resume_from_remote || s.1 > progress);
+
+        if let Some((key, block)) = best_snapshot.as_ref().filter(|s| s.1 < progress) {
+            if !self.force {
+                eyre::bail!(
+                    "Restoring {key} would rewind the local progress from block #{progress} to \
+                     #{block}, pass `--force` to restore anyway"
+                )
+            }
+            tracing::warn!(target: "database::init", %key, progress, block, "Rewinding the local progress to the remote snapshot");
+        }
 
         if let Some((key, _)) = best_snapshot {
+            // Check the chain before downloading the whole snapshot
+            let metadata = remote.metadata(&key).await?.unwrap_or_default();
+            match SnapshotManifest::from_metadata(&metadata) {
+                Some(manifest) => manifest.ensure_matches(&key, chain_spec)?,
+                None => {
+                    tracing::warn!(target: "database::init", %key, "Snapshot has no manifest, skipping the chain check")
+                }
+            }
+
             drop(local);
-            let contents = remote.retrieve(&key).await?.unwrap();
-            fs::write(self.path.join(MDBX_DAT), contents)?;
-            let db = Arc::new(Env::<WriteMap>::open(&self.path, EnvKind::RW)?);
-            Ok(db)
+            let format = CompressionFormat::from_metadata(&metadata);
+            let threads = if format.is_parallel() { self.decompress_threads } else { 1 };
+            tracing::trace!(target: "database::init", %key, ?format, threads, "Restoring snapshot");
+            // The contents are synced to disk before the environment maps the file.
+            if !remote.retrieve_to(&key, &self.path.join(MDBX_DAT), threads).await? {
+                eyre::bail!("Snapshot {key} was removed before it could be downloaded")
+            }
+            match Checksum::from_metadata(&metadata) {
+                Some(checksum) => {
+                    // The database is hashed off the runtime threads, so that the concurrent
+                    // restores are not held up.
+                    let (owned_key, database) = (key.clone(), self.path.join(MDBX_DAT));
+                    let algorithm = checksum.algorithm;
+                    let verified =
+                        tokio::task::spawn_blocking(move || checksum.verify(&owned_key, &database))
+                            .await?;
+                    if let Err(error) = verified {
+                        // Do not leave the corrupt database behind for the next run to open.
+                        let _ = std::fs::remove_file(self.path.join(MDBX_DAT));
+                        return Err(error)
+                    }
+                    tracing::trace!(target: "database::init", %key, %algorithm, "Verified snapshot checksum");
+                }
+                None => {
+                    tracing::warn!(target: "database::init", %key, "Snapshot has no checksum, skipping the verification")
+                }
+            }
+
+            let db = self.open_with_retry(self.geometry(chain_spec.chain)).await?;
+            // The snapshot might predate some of the stage tables.
+            create_tables(&db, self.stage.descriptor().default_tables())?;
+            if self.verify_restore {
+                if let Err(error) = verify_tables(&db, self.stage.descriptor().default_tables()) {
+                    drop(db);
+                    let _ = std::fs::remove_file(self.path.join(MDBX_DAT));
+                    eyre::bail!(
+                        "Restored snapshot {key} is corrupt and was removed, restart to download \
+                         it again: {error}"
+                    )
+                }
+                tracing::info!(target: "database::init", %key, "Verified restored database");
+            }
+            Ok(Arc::new(db))
         } else {
             Ok(local)
         }
     }
 
+    async fn apply_headers_diffs(
+        &self,
+        db: &Arc<Env<WriteMap>>,
+        remote: &RemoteStore,
+        progress: u64,
+    ) -> eyre::Result<()> {
+        let entries = remote.list(Some(HEADERS_DIFF_PREFIX)).await?;
+        for diff in headers_diff_chain(entries.iter().map(|e| e.key.as_str()), progress) {
+            let key = diff.to_string();
+            let Some(contents) = remote.retrieve(&key).await? else {
+                tracing::warn!(target: "database::init", %key, "Headers diff was removed, skipping the rest");
+                break
+            };
+            let (progress, complete) = apply_headers_diff(db, &contents)?;
+            if !complete {
+                tracing::warn!(target: "database::init", %key, progress, "Headers diff does not extend the local chain, skipping the rest");
+                break
+            }
+            tracing::trace!(target: "database::init", %key, progress, "Applied headers diff");
+        }
+        Ok(())
+    }
+
+    async fn apply_state_diffs(
+        &self,
+        db: &Arc<Env<WriteMap>>,
+        remote: &RemoteStore,
+        progress: u64,
+    ) -> eyre::Result<()> {
+        let entries = remote.list(Some(STATE_DIFF_PREFIX)).await?;
+        for diff in state_diff_chain(entries.iter().map(|e| e.key.as_str()), progress) {
+            let key = diff.to_string();
+            let Some(contents) = remote.retrieve(&key).await? else {
+                tracing::warn!(target: "database::init", %key, "State diff was removed, skipping the rest");
+                break
+            };
+            // A diff that fails is not applied at all, and the rest of the state is executed.
+            match apply_state_diff(db, &contents) {
+                Ok(progress) => {
+                    tracing::trace!(target: "database::init", %key, progress, "Applied state diff")
+                }
+                Err(error) => {
+                    tracing::warn!(target: "database::init", %key, %error, "Failed to apply state diff, skipping the rest");
+                    break
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Open the restored database, retrying on the transient errors.
+    async fn open_with_retry(&self, geometry: DatabaseGeometry) -> eyre::Result<Env<WriteMap>> {
+        let mut delay = OPEN_RETRY_DELAY;
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            match geometry.open(&self.path, EnvKind::RW) {
+                Ok(db) => return Ok(db),
+                Err(error) if attempt < OPEN_ATTEMPTS => {
+                    tracing::warn!(target: "database::init", path = %self.path.display(), %error, attempt, "Failed to open the restored database, retrying");
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                }
+                Err(error) => {
+                    let lock = self.path.join(MDBX_LCK);
+                    if lock.exists() {
+                        eyre::bail!(
+                            "Failed to open the restored database at {}: {error}. The lock file {} \
+                             may be stale, make sure no other process uses the database and \
+                             remove it.",
+                            self.path.display(),
+                            lock.display(),
+                        )
+                    }
+                    return Err(error.into())
+                }
+            }
+        }
+    }
+
+    /// Ensure the genesis of the database matches the chain spec. With
+    /// [Self::with_no_genesis_check], a mismatch is only logged.
+    fn ensure_genesis(&self, db: &Arc<Env<WriteMap>>, chain_spec: ChainSpec) -> eyre::Result<()> {
+        match self.stage.descriptor().ensure_genesis(Arc::clone(db), chain_spec) {
+            Err(error) if self.no_genesis_check && is_genesis_mismatch(&error) => {
+                tracing::warn!(target: "database::init", stage = %self.stage, path = %self.path.display(), %error, "Genesis mismatch, proceeding on the existing data");
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    /// Initialize the local database. With [Self::with_force_genesis], the database is wiped and
+    /// created anew if its genesis does not match the chain spec.
+    fn initialize_for_chain(&self, chain_spec: &ChainSpec) -> eyre::Result<Arc<Env<WriteMap>>> {
+        let descriptor = self.stage.descriptor();
+        let geometry = self.geometry(chain_spec.chain);
+        let db = self.initialize_database(descriptor.default_tables(), geometry)?;
+        if !self.force_genesis {
+            return Ok(db)
+        }
+
+        match descriptor.ensure_genesis(Arc::clone(&db), chain_spec.clone()) {
+            Err(error) if is_genesis_mismatch(&error) => {
+                tracing::warn!(target: "database::init", stage = %self.stage, path = %self.path.display(), %error, "Genesis mismatch, wiping the database");
+                drop(db);
+                // The directory itself is kept along with the lock file.
+                std::fs::remove_file(self.path.join(MDBX_DAT))?;
+                let _ = std::fs::remove_file(self.path.join(MDBX_LCK));
+                self.initialize_database(descriptor.default_tables(), geometry)
+            }
+            result => result.map(|_| db),
+        }
+    }
+
+    /// The geometry set with [Self::with_geometry], or the default one for the chain.
+    fn geometry(&self, chain: Chain) -> DatabaseGeometry {
+        self.geometry.unwrap_or_else(|| DatabaseGeometry::for_chain(chain))
+    }
+
     fn initialize_database(
         &self,
         tables: &[(TableType, &str)],
+        geometry: DatabaseGeometry,
     ) -> eyre::Result<Arc<Env<WriteMap>>> {
         std::fs::create_dir_all(&self.path)?;
-        let db = Env::<WriteMap>::open(&self.path, EnvKind::RW)?;
+        let db = geometry.open(&self.path, EnvKind::RW)?;
+        create_tables(&db, tables)?;
+        Ok(Arc::new(db))
+    }
+}
 
-        let tx = db.inner.begin_rw_txn()?;
-        for (table_type, table) in tables {
-            let flags = match table_type {
-                TableType::Table => DatabaseFlags::default(),
-                TableType::DupSort => DatabaseFlags::DUP_SORT,
-            };
-            tx.create_db(Some(table), flags)?;
+fn is_genesis_mismatch(error: &eyre::Report) -> bool {
+    matches!(
+        error.downcast_ref::<InitDatabaseError>(),
+        Some(InitDatabaseError::GenesisHashMismatch { .. })
+    )
+}
+
+/// Walk all entries of the tables, failing on the first one that cannot be read.
+fn verify_tables(db: &Env<WriteMap>, tables: &[(TableType, &str)]) -> eyre::Result<()> {
+    let tx = db.inner.begin_ro_txn()?;
+    for (_, table) in tables {
+        let table_db = tx.open_db(Some(*table))?;
+        let mut cursor = tx.cursor(&table_db)?;
+        let mut entries = 0u64;
+        for entry in cursor.iter_start::<Cow<'_, [u8]>, Cow<'_, [u8]>>() {
+            entry.map_err(|error| eyre::eyre!("table {table}, entry #{entries}: {error}"))?;
+            entries += 1;
         }
-        tx.commit()?;
+        tracing::trace!(target: "database::init", table, entries, "Verified table");
+    }
+    Ok(())
+}
 
-        Ok(Arc::new(db))
+/// Create the tables that do not exist in the database yet.
+fn create_tables(db: &Env<WriteMap>, tables: &[(TableType, &str)]) -> eyre::Result<()> {
+    let tx = db.inner.begin_rw_txn()?;
+    for (table_type, table) in tables {
+        let flags = match table_type {
+            TableType::Table => DatabaseFlags::default(),
+            TableType::DupSort => DatabaseFlags::DUP_SORT,
+        };
+        tx.create_db(Some(table), flags)?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::test_utils::create_test_db;
+    use reth_db::{database::Database, transaction::DbTxMut};
+    use reth_stages::stages::BODIES;
+
+    #[test]
+    fn open_read_only_reads_progress_during_write() {
+        let (dir, db) = create_test_db(Stage::Bodies);
+        db.update(|tx| BODIES.save_progress(tx, 5)).unwrap().unwrap();
+
+        // The writer holds its transaction open while the database is opened read-only.
+        let tx = db.tx_mut().unwrap();
+        BODIES.save_progress(&tx, 6).unwrap();
+        let read_only =
+            DatabaseInitializer::new(Stage::Bodies).with_path(dir.path()).open_read_only().unwrap();
+        let descriptor = Stage::Bodies.descriptor();
+        assert_eq!(descriptor.progress(Arc::clone(&read_only)).unwrap(), Some(5));
+
+        // The committed progress is seen by the next read transaction.
+        tx.commit().unwrap();
+        assert_eq!(descriptor.progress(Arc::clone(&read_only)).unwrap(), Some(6));
     }
 
-    fn get_snapshot_progress(&self, key: &str) -> u64 {
-        let key = key.strip_prefix(&self.prefix).unwrap();
-        let key = key.strip_suffix(DAT_GZ_EXT).unwrap();
-        let block: u64 = key.parse().unwrap();
-        block
+    #[test]
+    fn open_read_only_refuses_writes() {
+        let (dir, db) = create_test_db(Stage::Bodies);
+        drop(db);
+
+        let read_only =
+            DatabaseInitializer::new(Stage::Bodies).with_path(dir.path()).open_read_only().unwrap();
+        assert!(read_only.tx_mut().is_err());
+        assert!(read_only.update(|tx| BODIES.save_progress(tx, 1)).is_err());
+        assert_eq!(Stage::Bodies.descriptor().progress(read_only).unwrap(), None);
     }
 }
@@ -0,0 +1,48 @@
+use fs2::FileExt;
+use std::{
+    fs::{File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+/// The name of the lock file in the database directory.
+pub const LOCK_FILE: &str = "reth-light.lock";
+
+/// An exclusive advisory lock on the database directory, held by a single sync process.
+/// The lock is released once it is dropped, or when the process exits.
+#[derive(Debug)]
+pub struct DatabaseLock {
+    file: File,
+}
+
+impl DatabaseLock {
+    /// Acquire the lock on the database directory, creating the directory if needed.
+    /// Fails if another process holds the lock.
+    pub fn acquire(path: &Path) -> eyre::Result<Self> {
+        std::fs::create_dir_all(path)?;
+        let lock_path = path.join(LOCK_FILE);
+        let mut file = OpenOptions::new().read(true).write(true).create(true).open(&lock_path)?;
+
+        if file.try_lock_exclusive().is_err() {
+            let mut pid = String::new();
+            file.read_to_string(&mut pid)?;
+            eyre::bail!(
+                "The database at {} is already in use by pid {}",
+                path.display(),
+                Some(pid.trim()).filter(|pid| !pid.is_empty()).unwrap_or("unknown"),
+            )
+        }
+
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        write!(file, "{}", std::process::id())?;
+        file.sync_all()?;
+        Ok(Self { file })
+    }
+}
+
+impl Drop for DatabaseLock {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
@@ -0,0 +1,148 @@
+use super::{Stage, TxTypeCounts};
+use reth_db::{database::Database, tables, transaction::DbTx};
+use reth_primitives::{Chain, ChainSpec, H256};
+use std::{collections::HashMap, fmt, str::FromStr};
+
+const CHAIN_ID_KEY: &str = "chain-id";
+const GENESIS_HASH_KEY: &str = "genesis-hash";
+const ACCOUNTS_KEY: &str = "accounts";
+const BYTECODES_KEY: &str = "bytecodes";
+const TRANSACTIONS_KEY: &str = "transactions";
+const LEGACY_TRANSACTIONS_KEY: &str = "transactions-legacy";
+const EIP2930_TRANSACTIONS_KEY: &str = "transactions-eip2930";
+const EIP1559_TRANSACTIONS_KEY: &str = "transactions-eip1559";
+
+/// The description of the chain the snapshot was taken from.
+/// Stored as the metadata of the remote snapshot object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SnapshotManifest {
+    /// The chain of the snapshot.
+    pub chain: Chain,
+    /// The genesis hash of the snapshot chain.
+    pub genesis_hash: H256,
+}
+
+impl SnapshotManifest {
+    /// Create the manifest for the chain.
+    pub fn new(chain_spec: &ChainSpec) -> Self {
+        Self { chain: chain_spec.chain, genesis_hash: chain_spec.genesis_hash() }
+    }
+
+    /// Encode the manifest as the object metadata.
+    pub fn to_metadata(&self) -> HashMap<String, String> {
+        HashMap::from([
+            (CHAIN_ID_KEY.to_owned(), self.chain.id().to_string()),
+            (GENESIS_HASH_KEY.to_owned(), format!("{:?}", self.genesis_hash)),
+        ])
+    }
+
+    /// Decode the manifest from the object metadata.
+    /// Returns [None] if the snapshot was published without the manifest.
+    pub fn from_metadata(metadata: &HashMap<String, String>) -> Option<Self> {
+        let chain_id = metadata.get(CHAIN_ID_KEY)?.parse::<u64>().ok()?;
+        let genesis_hash = H256::from_str(metadata.get(GENESIS_HASH_KEY)?).ok()?;
+        Some(Self { chain: Chain::from(chain_id), genesis_hash })
+    }
+
+    /// Check that the snapshot under `key` belongs to the requested chain.
+    pub fn ensure_matches(&self, key: &str, chain_spec: &ChainSpec) -> eyre::Result<()> {
+        if self.chain != chain_spec.chain || self.genesis_hash != chain_spec.genesis_hash() {
+            eyre::bail!(
+                "Snapshot {key} is for chain {} ({}) with genesis {:?}, but --chain {} was \
+                 requested",
+                self.chain.id(),
+                self.chain,
+                self.genesis_hash,
+                chain_spec.chain,
+            )
+        }
+        Ok(())
+    }
+}
+
+/// The entry counts of the snapshot database, taken at the snapshot time.
+/// Stored as the metadata of the remote snapshot object along with the manifest.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SnapshotContents {
+    /// The number of accounts in the state snapshot.
+    pub accounts: Option<u64>,
+    /// The number of bytecodes in the state snapshot.
+    pub bytecodes: Option<u64>,
+    /// The number of transactions in the bodies snapshot.
+    pub transactions: Option<u64>,
+    /// The number of transactions of each type in the bodies snapshot, if they were tallied.
+    pub tx_types: Option<TxTypeCounts>,
+}
+
+impl SnapshotContents {
+    /// Count the entries of the stage database.
+    pub fn collect<DB: Database>(stage: Stage, db: &DB) -> eyre::Result<Self> {
+        let tx = db.tx()?;
+        let contents = match stage {
+            Stage::Headers => Self::default(),
+            Stage::Bodies => Self {
+                transactions: Some(tx.entries::<tables::Transactions>()? as u64),
+                tx_types: TxTypeCounts::load(&tx)?,
+                ..Default::default()
+            },
+            Stage::State => Self {
+                accounts: Some(tx.entries::<tables::PlainAccountState>()? as u64),
+                bytecodes: Some(tx.entries::<tables::Bytecodes>()? as u64),
+                ..Default::default()
+            },
+        };
+        Ok(contents)
+    }
+
+    /// Returns `true` if none of the counts are known.
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Encode the known counts as the object metadata.
+    pub fn to_metadata(&self) -> HashMap<String, String> {
+        [
+            (ACCOUNTS_KEY, self.accounts),
+            (BYTECODES_KEY, self.bytecodes),
+            (TRANSACTIONS_KEY, self.transactions),
+            (LEGACY_TRANSACTIONS_KEY, self.tx_types.map(|counts| counts.legacy)),
+            (EIP2930_TRANSACTIONS_KEY, self.tx_types.map(|counts| counts.eip2930)),
+            (EIP1559_TRANSACTIONS_KEY, self.tx_types.map(|counts| counts.eip1559)),
+        ]
+        .into_iter()
+        .filter_map(|(key, count)| Some((key.to_owned(), count?.to_string())))
+        .collect()
+    }
+
+    /// Decode the counts from the object metadata, skipping the missing ones.
+    pub fn from_metadata(metadata: &HashMap<String, String>) -> Self {
+        let count = |key: &str| metadata.get(key).and_then(|count| count.parse().ok());
+        Self {
+            accounts: count(ACCOUNTS_KEY),
+            bytecodes: count(BYTECODES_KEY),
+            transactions: count(TRANSACTIONS_KEY),
+            tx_types: count(LEGACY_TRANSACTIONS_KEY).map(|legacy| TxTypeCounts {
+                legacy,
+                eip2930: count(EIP2930_TRANSACTIONS_KEY).unwrap_or_default(),
+                eip1559: count(EIP1559_TRANSACTIONS_KEY).unwrap_or_default(),
+            }),
+        }
+    }
+}
+
+impl fmt::Display for SnapshotContents {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let counts = [
+            (self.accounts, "accounts"),
+            (self.bytecodes, "bytecodes"),
+            (self.transactions, "transactions"),
+            (self.tx_types.map(|counts| counts.legacy), "legacy"),
+            (self.tx_types.map(|counts| counts.eip2930), "EIP-2930"),
+            (self.tx_types.map(|counts| counts.eip1559), "EIP-1559"),
+        ]
+        .into_iter()
+        .filter_map(|(count, name)| Some(format!("{} {name}", count?)))
+        .collect::<Vec<_>>();
+        write!(f, "{}", counts.join(", "))
+    }
+}
@@ -4,8 +4,47 @@ pub use init::DatabaseInitializer;
 mod constants;
 pub use constants::*;
 
+mod diff;
+pub use diff::{apply_headers_diff, headers_diff_chain, write_headers_diff, HeadersDiffKey};
+
+mod state_diff;
+pub use state_diff::{apply_state_diff, state_diff_chain, StateDiff, StateDiffKey};
+
+mod lock;
+pub use lock::DatabaseLock;
+
+mod geometry;
+pub use geometry::DatabaseGeometry;
+
+mod copy;
+pub use copy::{copy_database, copy_tables};
+
+mod consistency;
+pub use consistency::{reconcile_progress, truncate_bodies, validate_bodies_against_headers};
+
 mod descriptor;
 pub use descriptor::*;
 
+mod checksum;
+pub use checksum::{Checksum, ChecksumAlgorithm};
+
+mod manifest;
+pub use manifest::{SnapshotContents, SnapshotManifest};
+
+mod tx_types;
+pub use tx_types::TxTypeCounts;
+
+mod proof;
+pub use proof::{account_proof, AccountProof, StorageProof};
+
+mod state_root;
+pub use state_root::calculate_state_root;
+
+mod snapshot;
+pub use snapshot::{Snapshot, Stage};
+
 mod split;
 pub use split::{LatestSplitStateProvider, SplitDatabase};
+
+#[cfg(test)]
+pub(crate) mod test_utils;
@@ -0,0 +1,81 @@
+use super::state_root::{encode_account, new_trie, plain_storage, storage_trie};
+use cita_trie::Trie;
+use reth_db::{cursor::DbCursorRO, tables, transaction::DbTx};
+use reth_primitives::{keccak256, Account, Address, Bytes, H256, U256};
+
+/// The Merkle proof of the account and its storage slots against the state root, in the form of
+/// the `eth_getProof` response.
+#[derive(Debug, Clone)]
+pub struct AccountProof {
+    /// The address of the account.
+    pub address: Address,
+    /// The account, or [None] if it does not exist and the proof is of its absence.
+    pub account: Option<Account>,
+    /// The root of the account storage trie.
+    pub storage_root: H256,
+    /// The state trie nodes on the path to the account, starting with the root.
+    pub proof: Vec<Bytes>,
+    /// The proofs of the requested storage slots.
+    pub storage_proofs: Vec<StorageProof>,
+    /// The state root the account is proven against.
+    pub state_root: H256,
+}
+
+/// The Merkle proof of the storage slot against the account storage root.
+#[derive(Debug, Clone)]
+pub struct StorageProof {
+    /// The storage slot.
+    pub key: H256,
+    /// The value of the slot, zero if it is not set.
+    pub value: U256,
+    /// The storage trie nodes on the path to the slot, starting with the root.
+    pub proof: Vec<Bytes>,
+}
+
+/// Build the Merkle proof of the account and the storage `slots` over the plain state in the
+/// transaction.
+///
+/// Like [super::calculate_state_root], the whole state trie is built in memory, so this is only
+/// feasible on the chains with a small state.
+pub fn account_proof<'a, TX: DbTx<'a>>(
+    tx: &TX,
+    address: Address,
+    slots: &[H256],
+) -> eyre::Result<AccountProof> {
+    let mut storage_cursor = tx.cursor_dup_read::<tables::PlainStorageState>()?;
+
+    let mut state_trie = new_trie();
+    let mut target = None;
+    for entry in tx.cursor_read::<tables::PlainAccountState>()?.walk(None)? {
+        let (account_address, account) = entry?;
+        let storage = plain_storage(&mut storage_cursor, account_address)?;
+        let storage_root = H256::from_slice(&storage_trie(&storage)?.root()?);
+
+        if account_address == address {
+            target = Some((account, storage));
+        }
+        state_trie
+            .insert(keccak256(account_address).to_vec(), encode_account(&account, storage_root))?;
+    }
+    let state_root = H256::from_slice(&state_trie.root()?);
+    let proof = to_bytes(state_trie.get_proof(keccak256(address).as_bytes())?);
+
+    // The storage of the missing account is empty, and so are the proofs of its slots.
+    let (account, storage) = target.unzip();
+    let storage = storage.unwrap_or_default();
+    let mut storage_trie = storage_trie(&storage)?;
+    let storage_root = H256::from_slice(&storage_trie.root()?);
+
+    let mut storage_proofs = Vec::with_capacity(slots.len());
+    for slot in slots {
+        let value = storage.iter().find(|(key, _)| key == slot).map_or(U256::ZERO, |(_, v)| *v);
+        let proof = to_bytes(storage_trie.get_proof(keccak256(slot).as_bytes())?);
+        storage_proofs.push(StorageProof { key: *slot, value, proof });
+    }
+
+    Ok(AccountProof { address, account, storage_root, proof, storage_proofs, state_root })
+}
+
+fn to_bytes(nodes: Vec<Vec<u8>>) -> Vec<Bytes> {
+    nodes.into_iter().map(Bytes::from).collect()
+}
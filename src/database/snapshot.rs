@@ -0,0 +1,157 @@
+use super::{
+    BodiesDescriptor, DatabaseDescriptor, HeadersDescriptor, StateDescriptor, BODIES_PREFIX,
+    DAT_GZ_EXT, HEADERS_PREFIX, STATE_PREFIX,
+};
+use reth_db::mdbx::{Env, WriteMap};
+use reth_primitives::BlockNumber;
+use std::{fmt, str::FromStr, sync::Arc};
+
+/// The sync stage backed by its own database and published as separate snapshots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Stage {
+    Headers,
+    Bodies,
+    State,
+}
+
+impl Stage {
+    /// All stages in the sync order.
+    pub const ALL: [Stage; 3] = [Stage::Headers, Stage::Bodies, Stage::State];
+
+    /// The remote key prefix of the stage snapshots.
+    pub fn prefix(&self) -> &'static str {
+        match self {
+            Stage::Headers => HEADERS_PREFIX,
+            Stage::Bodies => BODIES_PREFIX,
+            Stage::State => STATE_PREFIX,
+        }
+    }
+
+    /// The descriptor of the stage database.
+    pub fn descriptor(&self) -> &'static dyn DatabaseDescriptor<Arc<Env<WriteMap>>> {
+        match self {
+            Stage::Headers => &HeadersDescriptor,
+            Stage::Bodies => &BodiesDescriptor,
+            Stage::State => &StateDescriptor,
+        }
+    }
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stage::Headers => f.write_str("headers"),
+            Stage::Bodies => f.write_str("bodies"),
+            Stage::State => f.write_str("state"),
+        }
+    }
+}
+
+impl FromStr for Stage {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Stage::ALL
+            .into_iter()
+            .find(|stage| stage.to_string() == s)
+            .ok_or_else(|| eyre::eyre!("unknown stage {s}, expected headers, bodies or state"))
+    }
+}
+
+/// The remote database snapshot with the key in the form of `{prefix}{block}{DAT_GZ_EXT}`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Snapshot {
+    /// The stage the snapshot belongs to.
+    pub stage: Stage,
+    /// The block number the snapshot was taken at.
+    pub block: BlockNumber,
+}
+
+impl Snapshot {
+    /// Create new snapshot.
+    pub fn new(stage: Stage, block: BlockNumber) -> Self {
+        Self { stage, block }
+    }
+
+    /// The remote key of the snapshot.
+    pub fn key(&self) -> String {
+        format!("{}{}{DAT_GZ_EXT}", self.stage.prefix(), self.block)
+    }
+
+    /// Parse the snapshot key against the prefixes of all stages.
+    /// Returns [None] if the key does not belong to a snapshot.
+    pub fn parse(key: &str) -> Option<Self> {
+        Stage::ALL.into_iter().find_map(|stage| {
+            let block = key.strip_prefix(stage.prefix())?.strip_suffix(DAT_GZ_EXT)?;
+            if block.is_empty() || !block.bytes().all(|b| b.is_ascii_digit()) {
+                return None
+            }
+            Some(Self::new(stage, block.parse().ok()?))
+        })
+    }
+
+    /// Parse the snapshot key, returning [None] unless it belongs to the given stage.
+    pub fn parse_for(key: &str, stage: Stage) -> Option<Self> {
+        Self::parse(key).filter(|s| s.stage == stage)
+    }
+}
+
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.key())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_valid_keys() {
+        assert_eq!(Snapshot::parse("headers-100.dat.gz"), Some(Snapshot::new(Stage::Headers, 100)));
+        assert_eq!(Snapshot::parse("bodies-0.dat.gz"), Some(Snapshot::new(Stage::Bodies, 0)));
+        assert_eq!(
+            Snapshot::parse("state-snapshots/state-500000.dat.gz"),
+            Some(Snapshot::new(Stage::State, 500_000))
+        );
+
+        for stage in Stage::ALL {
+            let snapshot = Snapshot::new(stage, 42);
+            assert_eq!(Snapshot::parse(&snapshot.key()), Some(snapshot));
+            assert_eq!(Snapshot::parse_for(&snapshot.key(), stage), Some(snapshot));
+        }
+    }
+
+    #[test]
+    fn parse_malformed_keys() {
+        for key in [
+            "headers-.dat.gz",
+            "headers-abc.dat.gz",
+            "headers-+1.dat.gz",
+            "headers--1.dat.gz",
+            "headers-1 .dat.gz",
+            "headers-1.dat",
+            "headers-1.dat.gz.tmp",
+            "headers-99999999999999999999.dat.gz",
+            "bodies-0x10.dat.gz",
+            "state-1.dat.gz",
+        ] {
+            assert_eq!(Snapshot::parse(key), None, "{key}");
+        }
+    }
+
+    #[test]
+    fn parse_foreign_keys() {
+        for key in ["README.md", "", ".dat.gz", "snapshots/headers-1.dat.gz", "headers-1.DAT.GZ"] {
+            assert_eq!(Snapshot::parse(key), None, "{key}");
+        }
+
+        // The diffs share the prefixes of the snapshots, but are not snapshots themselves.
+        assert_eq!(Snapshot::parse("headers-diff-1-10.dat.gz"), None);
+        assert_eq!(Snapshot::parse("state-snapshots/state-diff-1-10.dat.gz"), None);
+
+        // The snapshot of another stage is skipped.
+        assert_eq!(Snapshot::parse_for("bodies-10.dat.gz", Stage::Headers), None);
+        assert_eq!(Snapshot::parse_for("headers-10.dat.gz", Stage::State), None);
+    }
+}
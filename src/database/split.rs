@@ -1,3 +1,4 @@
+use super::DatabaseLock;
 use reth_db::{
     cursor::DbDupCursorRO,
     mdbx::{Env, WriteMap},
@@ -5,7 +6,9 @@ use reth_db::{
     transaction::DbTx,
 };
 use reth_interfaces::Result;
-use reth_primitives::{Account, Address, Bytes, StorageKey, StorageValue, H256, U256};
+use reth_primitives::{
+    Account, Address, Bytes, StorageEntry, StorageKey, StorageValue, H256, U256,
+};
 use reth_provider::{AccountProvider, BlockHashProvider, StateProvider};
 use std::{
     marker::PhantomData,
@@ -20,6 +23,8 @@ pub struct SplitDatabase {
     bodies: Arc<Env<WriteMap>>,
     pub state_path: PathBuf,
     state: Arc<Env<WriteMap>>,
+    /// The locks of the database directories, released once the database is dropped.
+    locks: Vec<DatabaseLock>,
 }
 
 impl SplitDatabase {
@@ -34,7 +39,13 @@ impl SplitDatabase {
         let headers_path = headers_path.as_ref().to_owned();
         let bodies_path = bodies_path.as_ref().to_owned();
         let state_path = state_path.as_ref().to_owned();
-        Self { headers_path, headers, bodies_path, bodies, state_path, state }
+        Self { headers_path, headers, bodies_path, bodies, state_path, state, locks: Vec::new() }
+    }
+
+    /// Hold the locks of the database directories for as long as the database is open.
+    pub fn with_locks(mut self, locks: impl IntoIterator<Item = DatabaseLock>) -> Self {
+        self.locks.extend(locks);
+        self
     }
 
     pub fn headers(&self) -> Arc<Env<WriteMap>> {
@@ -51,37 +62,69 @@ impl SplitDatabase {
 }
 
 /// State provider over latest state that takes tx reference.
-pub struct LatestSplitStateProvider<'a, 'b, TX: DbTx<'a>> {
+pub struct LatestSplitStateProvider<'a, 'b, HTX: DbTx<'a>, STX: DbTx<'a>> {
     /// Headers database transaction
-    headers_db: &'b TX,
+    headers_db: &'b HTX,
     /// State database transaction
-    state_db: &'b TX,
+    state_db: &'b STX,
     /// Phantom data over lifetime
-    phantom: PhantomData<&'a TX>,
+    phantom: PhantomData<&'a (HTX, STX)>,
 }
 
-impl<'a, 'b, TX: DbTx<'a>> LatestSplitStateProvider<'a, 'b, TX> {
+impl<'a, 'b, HTX: DbTx<'a>, STX: DbTx<'a>> LatestSplitStateProvider<'a, 'b, HTX, STX> {
     /// Create new state provider
-    pub fn new(headers_db: &'b TX, state_db: &'b TX) -> Self {
+    pub fn new(headers_db: &'b HTX, state_db: &'b STX) -> Self {
         Self { headers_db, state_db, phantom: PhantomData {} }
     }
+
+    /// Get up to `limit` storage entries of the account ordered by key, starting at `start_key`.
+    /// Returns an empty page if the account has no storage at or after `start_key`.
+    pub fn storage_range(
+        &self,
+        account: Address,
+        start_key: StorageKey,
+        limit: usize,
+    ) -> Result<Vec<(StorageKey, StorageValue)>> {
+        let mut cursor = self.state_db.cursor_dup_read::<tables::PlainStorageState>()?;
+
+        let mut entries = Vec::new();
+        let mut entry = cursor.seek_by_key_subkey(account, start_key)?;
+        while let Some(StorageEntry { key, value }) = entry {
+            if entries.len() >= limit {
+                break
+            }
+            entries.push((key, value));
+            entry = cursor.next_dup_val()?;
+        }
+        Ok(entries)
+    }
 }
 
-impl<'a, 'b, TX: DbTx<'a>> AccountProvider for LatestSplitStateProvider<'a, 'b, TX> {
+impl<'a, 'b, HTX: DbTx<'a>, STX: DbTx<'a>> AccountProvider
+    for LatestSplitStateProvider<'a, 'b, HTX, STX>
+{
     /// Get basic account information.
     fn basic_account(&self, address: Address) -> Result<Option<Account>> {
         self.state_db.get::<tables::PlainAccountState>(address).map_err(Into::into)
     }
 }
 
-impl<'a, 'b, TX: DbTx<'a>> BlockHashProvider for LatestSplitStateProvider<'a, 'b, TX> {
+impl<'a, 'b, HTX: DbTx<'a>, STX: DbTx<'a>> BlockHashProvider
+    for LatestSplitStateProvider<'a, 'b, HTX, STX>
+{
     /// Get block hash by number.
+    ///
+    /// Returns [None] for the blocks past the synced head, including the numbers that do not fit
+    /// into a block number, instead of failing the execution.
     fn block_hash(&self, number: U256) -> Result<Option<H256>> {
-        self.headers_db.get::<tables::CanonicalHeaders>(number.to::<u64>()).map_err(Into::into)
+        let Ok(number) = u64::try_from(number) else { return Ok(None) };
+        self.headers_db.get::<tables::CanonicalHeaders>(number).map_err(Into::into)
     }
 }
 
-impl<'a, 'b, TX: DbTx<'a>> StateProvider for LatestSplitStateProvider<'a, 'b, TX> {
+impl<'a, 'b, HTX: DbTx<'a>, STX: DbTx<'a>> StateProvider
+    for LatestSplitStateProvider<'a, 'b, HTX, STX>
+{
     /// Get storage.
     fn storage(&self, account: Address, storage_key: StorageKey) -> Result<Option<StorageValue>> {
         let mut cursor = self.state_db.cursor_dup_read::<tables::PlainStorageState>()?;
@@ -101,3 +144,117 @@ impl<'a, 'b, TX: DbTx<'a>> StateProvider for LatestSplitStateProvider<'a, 'b, TX
             .map(|r| r.map(Bytes::from))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{test_utils::create_test_db, Stage};
+    use reth_db::{database::Database, transaction::DbTxMut};
+
+    fn slot(n: u64) -> StorageKey {
+        H256::from_low_u64_be(n)
+    }
+
+    #[test]
+    fn storage_range() {
+        let (_dir, db) = create_test_db(Stage::State);
+        let (account, other) = (Address::from_low_u64_be(0x10), Address::from_low_u64_be(0x20));
+        db.update(|tx| {
+            for n in 1..=5 {
+                let entry = StorageEntry { key: slot(n * 2), value: U256::from(n) };
+                tx.put::<tables::PlainStorageState>(account, entry)?;
+            }
+            let entry = StorageEntry { key: slot(1), value: U256::from(100) };
+            tx.put::<tables::PlainStorageState>(other, entry)
+        })
+        .unwrap()
+        .unwrap();
+
+        let tx = db.tx().unwrap();
+        let provider = LatestSplitStateProvider::new(&tx, &tx);
+
+        assert_eq!(
+            provider.storage_range(account, H256::zero(), 2).unwrap(),
+            vec![(slot(2), U256::from(1)), (slot(4), U256::from(2))]
+        );
+        // The page starts at the slot itself if it is set, or at the next one otherwise.
+        assert_eq!(
+            provider.storage_range(account, slot(4), 2).unwrap(),
+            vec![(slot(4), U256::from(2)), (slot(6), U256::from(3))]
+        );
+        // The page does not run into the storage of the next account.
+        assert_eq!(
+            provider.storage_range(account, slot(5), 10).unwrap(),
+            vec![(slot(6), U256::from(3)), (slot(8), U256::from(4)), (slot(10), U256::from(5))]
+        );
+        assert!(provider.storage_range(account, slot(11), 10).unwrap().is_empty());
+        assert!(provider.storage_range(account, H256::zero(), 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn storage_range_pages_through_storage() {
+        let (_dir, db) = create_test_db(Stage::State);
+        let account = Address::from_low_u64_be(0x10);
+        db.update(|tx| {
+            for n in 1..=5 {
+                let entry = StorageEntry { key: slot(n), value: U256::from(n) };
+                tx.put::<tables::PlainStorageState>(account, entry)?;
+            }
+            Ok::<_, reth_db::Error>(())
+        })
+        .unwrap()
+        .unwrap();
+
+        let tx = db.tx().unwrap();
+        let provider = LatestSplitStateProvider::new(&tx, &tx);
+
+        let mut start_key = H256::zero();
+        let mut pages = Vec::new();
+        loop {
+            let page = provider.storage_range(account, start_key, 2).unwrap();
+            let Some((last, _)) = page.last() else { break };
+            start_key = slot(last.to_low_u64_be() + 1);
+            pages.push(page);
+        }
+        assert_eq!(pages.iter().map(Vec::len).collect::<Vec<_>>(), vec![2, 2, 1]);
+        assert_eq!(pages.concat(), (1..=5).map(|n| (slot(n), U256::from(n))).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn storage_range_without_storage() {
+        let (_dir, db) = create_test_db(Stage::State);
+        let account = Address::from_low_u64_be(0x10);
+        db.update(|tx| {
+            let entry = StorageEntry { key: slot(1), value: U256::from(1) };
+            tx.put::<tables::PlainStorageState>(account, entry)
+        })
+        .unwrap()
+        .unwrap();
+
+        let tx = db.tx().unwrap();
+        let provider = LatestSplitStateProvider::new(&tx, &tx);
+
+        // The accounts without storage get an empty page, whether they are ordered before or after
+        // the account with storage.
+        for empty in [Address::zero(), Address::from_low_u64_be(0x11), Address::repeat_byte(0xff)] {
+            assert!(provider.storage_range(empty, H256::zero(), 10).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn block_hash_past_head() {
+        let (_dir, db) = create_test_db(Stage::Headers);
+        let hash = H256::repeat_byte(0x01);
+        db.update(|tx| tx.put::<tables::CanonicalHeaders>(1, hash)).unwrap().unwrap();
+
+        let tx = db.tx().unwrap();
+        let provider = LatestSplitStateProvider::new(&tx, &tx);
+
+        assert_eq!(provider.block_hash(U256::from(1)).unwrap(), Some(hash));
+        assert_eq!(provider.block_hash(U256::from(2)).unwrap(), None);
+        // The numbers that do not fit into a block number are past the head as well.
+        assert_eq!(provider.block_hash(U256::from(u64::MAX)).unwrap(), None);
+        assert_eq!(provider.block_hash(U256::from(u64::MAX) + U256::from(1)).unwrap(), None);
+        assert_eq!(provider.block_hash(U256::MAX).unwrap(), None);
+    }
+}
@@ -0,0 +1,356 @@
+use super::{DAT_GZ_EXT, STATE_DIFF_PREFIX};
+use flate2::{write::GzEncoder, Compression};
+use reth_db::{
+    cursor::{DbCursorRW, DbDupCursorRO},
+    database::Database,
+    tables,
+    transaction::{DbTx, DbTxMut},
+};
+use reth_primitives::{Account, Address, BlockNumber, Bytes, StorageEntry, H256, U256};
+use reth_stages::stages::EXECUTION;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    io::{BufRead, Write},
+    path::Path,
+};
+use tempfile::NamedTempFile;
+
+/// The key of a remote state diff in the form of `{STATE_DIFF_PREFIX}{from}-{to}{DAT_GZ_EXT}`.
+///
+/// A diff contains the values of the accounts, storage slots and bytecodes changed by the blocks
+/// in the `from..=to` range as of block `to`, and is applied on top of a state synced up to any
+/// block in `from - 1..to`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StateDiffKey {
+    /// The first block in the diff.
+    pub from: BlockNumber,
+    /// The last block in the diff.
+    pub to: BlockNumber,
+}
+
+impl StateDiffKey {
+    /// Parse the state diff key.
+    /// Returns [None] if the key does not belong to a state diff.
+    pub fn parse(key: &str) -> Option<Self> {
+        let range = key.strip_prefix(STATE_DIFF_PREFIX)?.strip_suffix(DAT_GZ_EXT)?;
+        let (from, to) = range.split_once('-')?;
+        let is_number = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+        if !is_number(from) || !is_number(to) {
+            return None
+        }
+        let key = Self { from: from.parse().ok()?, to: to.parse().ok()? };
+        (key.from <= key.to).then_some(key)
+    }
+}
+
+impl fmt::Display for StateDiffKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{STATE_DIFF_PREFIX}{}-{}{DAT_GZ_EXT}", self.from, self.to)
+    }
+}
+
+/// Resolve the chain of diffs that extends the state synced up to `progress` as far as possible.
+///
+/// Since a diff holds the latest values of everything changed in its range, the diffs that
+/// partially overlap with the already synced state can be applied as well.
+pub fn state_diff_chain<'a>(
+    keys: impl IntoIterator<Item = &'a str>,
+    mut progress: BlockNumber,
+) -> Vec<StateDiffKey> {
+    let diffs = keys.into_iter().filter_map(StateDiffKey::parse).collect::<Vec<_>>();
+
+    let mut chain = Vec::new();
+    while let Some(next) =
+        diffs.iter().filter(|d| d.from <= progress + 1 && d.to > progress).max_by_key(|d| d.to)
+    {
+        progress = next.to;
+        chain.push(*next);
+    }
+    chain
+}
+
+/// The state changes accumulated since the last state snapshot.
+///
+/// Only the latest value of every changed account and storage slot is kept, so the memory usage
+/// grows with the state touched within the snapshot interval rather than with its length.
+#[derive(Debug, Default)]
+pub struct StateDiff {
+    from: Option<BlockNumber>,
+    accounts: BTreeMap<Address, AccountDiff>,
+    bytecodes: BTreeMap<H256, Bytes>,
+}
+
+#[derive(Debug, Default)]
+struct AccountDiff {
+    account: AccountChange,
+    wipe_storage: bool,
+    storage: BTreeMap<H256, U256>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum AccountChange {
+    #[default]
+    Unchanged,
+    Updated(Account),
+    Deleted,
+}
+
+/// A line of the encoded diff.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum DiffEntry {
+    /// The range of the diff and the total difficulty at its last block. Always the first line.
+    Range {
+        from: BlockNumber,
+        to: BlockNumber,
+        td: U256,
+    },
+    Account {
+        address: Address,
+        account: AccountChange,
+        wipe_storage: bool,
+        storage: Vec<(H256, U256)>,
+    },
+    Bytecode {
+        hash: H256,
+        code: Bytes,
+    },
+}
+
+impl StateDiff {
+    /// Record that the changes of the block are being applied.
+    pub fn record_block(&mut self, block: BlockNumber) {
+        self.from.get_or_insert(block);
+    }
+
+    /// The first block whose changes were recorded.
+    pub fn from(&self) -> Option<BlockNumber> {
+        self.from
+    }
+
+    /// Returns `true` if no changes were recorded.
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty() && self.bytecodes.is_empty()
+    }
+
+    /// Record the new value of the account, or its removal.
+    pub fn set_account(&mut self, address: Address, account: Option<Account>) {
+        self.accounts.entry(address).or_default().account = match account {
+            Some(account) => AccountChange::Updated(account),
+            None => AccountChange::Deleted,
+        };
+    }
+
+    /// Record the removal of all storage of the account. The slots written before are dropped.
+    pub fn wipe_storage(&mut self, address: Address) {
+        let diff = self.accounts.entry(address).or_default();
+        diff.wipe_storage = true;
+        diff.storage.clear();
+    }
+
+    /// Record the new value of the storage slot. A zero value removes the slot.
+    pub fn set_storage(&mut self, address: Address, key: H256, value: U256) {
+        self.accounts.entry(address).or_default().storage.insert(key, value);
+    }
+
+    pub fn set_bytecode(&mut self, hash: H256, code: Vec<u8>) {
+        self.bytecodes.insert(hash, code.into());
+    }
+
+    /// Write the diff of the `from..=to` range into a compressed file in `temp_dir`, as JSON
+    /// lines.
+    pub fn write(
+        &self,
+        from: BlockNumber,
+        to: BlockNumber,
+        td: U256,
+        temp_dir: &Path,
+    ) -> eyre::Result<NamedTempFile> {
+        let mut encoder = GzEncoder::new(NamedTempFile::new_in(temp_dir)?, Compression::default());
+        let mut write_line = |entry: &DiffEntry| -> eyre::Result<()> {
+            serde_json::to_writer(&mut encoder, entry)?;
+            encoder.write_all(b"\n")?;
+            Ok(())
+        };
+
+        write_line(&DiffEntry::Range { from, to, td })?;
+        for (address, diff) in &self.accounts {
+            write_line(&DiffEntry::Account {
+                address: *address,
+                account: diff.account,
+                wipe_storage: diff.wipe_storage,
+                storage: diff.storage.iter().map(|(key, value)| (*key, *value)).collect(),
+            })?;
+        }
+        for (hash, code) in &self.bytecodes {
+            write_line(&DiffEntry::Bytecode { hash: *hash, code: code.clone() })?;
+        }
+        Ok(encoder.finish()?)
+    }
+}
+
+/// Apply the decompressed diff contents on top of the state database.
+///
+/// The diff must start at or below the block after the current progress, and end above it.
+/// Returns the new progress.
+pub fn apply_state_diff<DB: Database>(db: &DB, contents: &[u8]) -> eyre::Result<BlockNumber> {
+    let mut lines = contents.lines();
+    let Some(DiffEntry::Range { from, to, td }) = lines
+        .next()
+        .transpose()?
+        .map(|line| serde_json::from_str::<DiffEntry>(&line))
+        .transpose()?
+    else {
+        eyre::bail!("State diff does not start with its range")
+    };
+
+    // The transaction is aborted on any error, so that a malformed diff is not applied partially.
+    let tx = db.tx_mut()?;
+    let progress = EXECUTION.get_progress(&tx)?.unwrap_or_default();
+    if from > progress + 1 || to <= progress {
+        eyre::bail!("State diff {from}-{to} does not extend the state at block #{progress}")
+    }
+
+    let mut storage_cursor = tx.cursor_dup_write::<tables::PlainStorageState>()?;
+    for line in lines {
+        match serde_json::from_str(&line?)? {
+            DiffEntry::Range { .. } => eyre::bail!("State diff has more than one range"),
+            DiffEntry::Account { address, account, wipe_storage, storage } => {
+                match account {
+                    AccountChange::Updated(account) => {
+                        tx.put::<tables::PlainAccountState>(address, account)?
+                    }
+                    AccountChange::Deleted => {
+                        tx.delete::<tables::PlainAccountState>(address, None)?;
+                    }
+                    AccountChange::Unchanged => {}
+                }
+                if wipe_storage {
+                    tx.delete::<tables::PlainStorageState>(address, None)?;
+                }
+                for (key, value) in storage {
+                    if storage_cursor
+                        .seek_by_key_subkey(address, key)?
+                        .filter(|entry| entry.key == key)
+                        .is_some()
+                    {
+                        storage_cursor.delete_current()?;
+                    }
+                    if value != U256::ZERO {
+                        storage_cursor.upsert(address, StorageEntry { key, value })?;
+                    }
+                }
+            }
+            DiffEntry::Bytecode { hash, code } => {
+                tx.put::<tables::Bytecodes>(hash, code.to_vec())?;
+            }
+        }
+    }
+
+    drop(storage_cursor);
+
+    EXECUTION.save_progress(&tx, to)?;
+    tx.clear::<tables::HeaderTD>()?;
+    tx.put::<tables::HeaderTD>(to, td.into())?;
+    tx.commit()?;
+    Ok(to)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{test_utils::create_test_db, Stage};
+    use flate2::read::MultiGzDecoder;
+    use reth_db::cursor::DbCursorRO;
+    use std::io::Read;
+
+    #[test]
+    fn parse_state_diff_keys() {
+        let key = StateDiffKey { from: 1_000_001, to: 1_010_000 };
+        assert_eq!(key.to_string(), "state-snapshots/state-diff-1000001-1010000.dat.gz");
+        assert_eq!(StateDiffKey::parse(&key.to_string()), Some(key));
+
+        for key in [
+            "state-snapshots/state-diff-10-1.dat.gz",
+            "state-snapshots/state-diff-1.dat.gz",
+            "state-snapshots/state-diff-1-x.dat.gz",
+            "state-snapshots/state-1000000.dat.gz",
+            "state-diff-1-10.dat.gz",
+            "headers-diff-1-10.dat.gz",
+            "README.md",
+        ] {
+            assert_eq!(StateDiffKey::parse(key), None, "{key}");
+        }
+    }
+
+    #[test]
+    fn state_diff_chain_from_progress() {
+        let keys = [
+            "state-snapshots/state-diff-1-10.dat.gz",
+            "state-snapshots/state-diff-11-20.dat.gz",
+            "state-snapshots/state-diff-11-15.dat.gz",
+            "state-snapshots/state-diff-25-30.dat.gz",
+            "state-snapshots/state-10.dat.gz",
+        ];
+        assert_eq!(state_diff_chain(keys, 10), vec![StateDiffKey { from: 11, to: 20 }]);
+        // The state synced in the middle of a diff is extended by the whole diff.
+        assert_eq!(
+            state_diff_chain(keys, 5),
+            vec![StateDiffKey { from: 1, to: 10 }, StateDiffKey { from: 11, to: 20 }]
+        );
+        assert!(state_diff_chain(keys, 20).is_empty());
+    }
+
+    #[test]
+    fn write_and_apply_state_diff() {
+        let (dir, db) = create_test_db(Stage::State);
+        let (updated, deleted) = (Address::from_low_u64_be(0x10), Address::from_low_u64_be(0x20));
+        let slot = H256::from_low_u64_be;
+        db.update(|tx| {
+            tx.put::<tables::PlainAccountState>(deleted, Account::default())?;
+            for (key, value) in [(slot(1), 9), (slot(3), 7)] {
+                let entry = StorageEntry { key, value: U256::from(value) };
+                tx.put::<tables::PlainStorageState>(updated, entry)?;
+            }
+            Ok::<_, reth_db::Error>(())
+        })
+        .unwrap()
+        .unwrap();
+
+        let account = Account { nonce: 1, balance: U256::from(2), bytecode_hash: Some(slot(0xc0)) };
+        let mut diff = StateDiff::default();
+        diff.record_block(1);
+        diff.set_account(updated, Some(account));
+        // The slot written before the wipe is dropped along with the stored ones.
+        diff.set_storage(updated, slot(2), U256::from(1));
+        diff.wipe_storage(updated);
+        diff.set_storage(updated, slot(1), U256::from(5));
+        diff.set_account(deleted, None);
+        diff.set_bytecode(slot(0xc0), vec![0x00]);
+
+        let td = U256::from(100);
+        let file = diff.write(1, 2, td, dir.path()).unwrap();
+        let mut contents = Vec::new();
+        MultiGzDecoder::new(file.reopen().unwrap()).read_to_end(&mut contents).unwrap();
+        assert_eq!(apply_state_diff(&db, &contents).unwrap(), 2);
+
+        let tx = db.tx().unwrap();
+        assert_eq!(EXECUTION.get_progress(&tx).unwrap(), Some(2));
+        assert_eq!(tx.get::<tables::HeaderTD>(2).unwrap().map(U256::from), Some(td));
+        assert_eq!(tx.get::<tables::PlainAccountState>(updated).unwrap(), Some(account));
+        assert_eq!(tx.get::<tables::PlainAccountState>(deleted).unwrap(), None);
+        assert_eq!(tx.get::<tables::Bytecodes>(slot(0xc0)).unwrap(), Some(vec![0x00]));
+
+        let mut cursor = tx.cursor_dup_read::<tables::PlainStorageState>().unwrap();
+        let mut storage = Vec::new();
+        let mut entry = cursor.seek_exact(updated).unwrap().map(|(_, entry)| entry);
+        while let Some(next) = entry {
+            storage.push(next);
+            entry = cursor.next_dup_val().unwrap();
+        }
+        assert_eq!(storage, vec![StorageEntry { key: slot(1), value: U256::from(5) }]);
+    }
+}
@@ -0,0 +1,87 @@
+use cita_trie::{MemoryDB, PatriciaTrie, Trie};
+use hasher::HasherKeccak;
+use reth_db::{
+    cursor::{DbCursorRO, DbDupCursorRO},
+    tables,
+    transaction::DbTx,
+};
+use reth_primitives::{keccak256, Account, Address, H256, KECCAK_EMPTY, U256};
+use reth_rlp::Encodable;
+use std::sync::Arc;
+
+/// Compute the state root of the plain state in the transaction.
+///
+/// The whole state trie is built in memory, so this is only feasible on the chains with a small
+/// state, or for diagnostics.
+pub fn calculate_state_root<'a, TX: DbTx<'a>>(tx: &TX) -> eyre::Result<H256> {
+    let mut storage_cursor = tx.cursor_dup_read::<tables::PlainStorageState>()?;
+
+    let mut state_trie = new_trie();
+    for entry in tx.cursor_read::<tables::PlainAccountState>()?.walk(None)? {
+        let (address, account) = entry?;
+
+        let storage = plain_storage(&mut storage_cursor, address)?;
+        let storage_root = H256::from_slice(&storage_trie(&storage)?.root()?);
+
+        state_trie.insert(keccak256(address).to_vec(), encode_account(&account, storage_root))?;
+    }
+    Ok(H256::from_slice(&state_trie.root()?))
+}
+
+/// Build the in-memory trie of the account storage.
+pub(super) fn storage_trie(
+    storage: &[(H256, U256)],
+) -> eyre::Result<PatriciaTrie<MemoryDB, HasherKeccak>> {
+    let mut trie = new_trie();
+    for (slot, value) in storage {
+        trie.insert(keccak256(slot).to_vec(), encode_u256(*value))?;
+    }
+    Ok(trie)
+}
+
+/// Create an empty in-memory trie keyed by the hashes of the addresses or the storage slots.
+pub(super) fn new_trie() -> PatriciaTrie<MemoryDB, HasherKeccak> {
+    PatriciaTrie::new(Arc::new(MemoryDB::new(true)), Arc::new(HasherKeccak::new()))
+}
+
+/// Read the non-zero storage slots of the account.
+pub(super) fn plain_storage<'a, C>(
+    cursor: &mut C,
+    address: Address,
+) -> eyre::Result<Vec<(H256, U256)>>
+where
+    C: DbCursorRO<'a, tables::PlainStorageState> + DbDupCursorRO<'a, tables::PlainStorageState>,
+{
+    let mut storage = Vec::new();
+    let mut slot = cursor.seek_exact(address)?.map(|(_, entry)| entry);
+    while let Some(entry) = slot {
+        if entry.value != U256::ZERO {
+            storage.push((entry.key, entry.value));
+        }
+        slot = cursor.next_dup_val()?;
+    }
+    Ok(storage)
+}
+
+/// Encode the account as the leaf of the state trie.
+pub(super) fn encode_account(account: &Account, storage_root: H256) -> Vec<u8> {
+    let mut payload = Vec::new();
+    account.nonce.encode(&mut payload);
+    payload.extend(encode_u256(account.balance));
+    storage_root.encode(&mut payload);
+    account.bytecode_hash.unwrap_or(KECCAK_EMPTY).encode(&mut payload);
+
+    let mut encoded = Vec::new();
+    reth_rlp::Header { list: true, payload_length: payload.len() }.encode(&mut encoded);
+    encoded.extend(payload);
+    encoded
+}
+
+/// Encode the value as the RLP string of its big-endian bytes without the leading zeros.
+fn encode_u256(value: U256) -> Vec<u8> {
+    let bytes = value.to_be_bytes::<32>();
+    let start = bytes.iter().position(|byte| *byte != 0).unwrap_or(bytes.len());
+    let mut encoded = Vec::new();
+    bytes[start..].encode(&mut encoded);
+    encoded
+}
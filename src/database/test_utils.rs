@@ -0,0 +1,32 @@
+use super::{DatabaseGeometry, DatabaseInitializer, Stage};
+use reth_db::mdbx::{Env, WriteMap};
+use reth_primitives::{ChainSpec, MAINNET};
+use std::{path::Path, sync::Arc};
+use tempfile::TempDir;
+
+/// The geometry of the test databases, small enough for any temporary directory.
+const TEST_GEOMETRY: DatabaseGeometry =
+    DatabaseGeometry { max_size: 1024 * 1024 * 1024, growth_step: 1024 * 1024 };
+
+/// The chain spec the test databases are initialized for.
+pub(crate) fn test_chain_spec() -> ChainSpec {
+    ChainSpec::clone(&MAINNET)
+}
+
+/// Create the stage database with the genesis of [test_chain_spec] in a temporary directory.
+/// The directory is removed once the returned handle is dropped.
+pub(crate) fn create_test_db(stage: Stage) -> (TempDir, Arc<Env<WriteMap>>) {
+    let dir = tempfile::tempdir().expect("failed to create temporary directory");
+    let db = open_test_db(stage, dir.path());
+    (dir, db)
+}
+
+/// Open the stage database at the path, creating it with the genesis of [test_chain_spec] if it
+/// does not exist yet.
+pub(crate) fn open_test_db(stage: Stage, path: &Path) -> Arc<Env<WriteMap>> {
+    DatabaseInitializer::new(stage)
+        .with_path(path)
+        .with_geometry(TEST_GEOMETRY)
+        .init_local(test_chain_spec())
+        .expect("failed to initialize test database")
+}
@@ -0,0 +1,74 @@
+use reth_db::{
+    cursor::DbCursorRO,
+    tables,
+    transaction::{DbTx, DbTxMut},
+};
+use reth_primitives::TxType;
+
+const LEGACY_KEY: &str = "TxTypeLegacy";
+const EIP2930_KEY: &str = "TxTypeEip2930";
+const EIP1559_KEY: &str = "TxTypeEip1559";
+
+/// The number of transactions of each type in the bodies database.
+///
+/// The counts are kept in the sync stage table next to the bodies progress, so that they are
+/// committed along with the bodies and carried over in the snapshots.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TxTypeCounts {
+    pub legacy: u64,
+    pub eip2930: u64,
+    pub eip1559: u64,
+}
+
+impl TxTypeCounts {
+    /// Load the counts from the bodies database.
+    /// Returns [None] if the transaction types have not been tallied.
+    pub fn load<'a>(tx: &impl DbTx<'a>) -> eyre::Result<Option<Self>> {
+        let count = |key: &str| tx.get::<tables::SyncStage>(key.as_bytes().to_vec());
+        // The counts are always written together.
+        let Some(legacy) = count(LEGACY_KEY)? else { return Ok(None) };
+        Ok(Some(Self {
+            legacy,
+            eip2930: count(EIP2930_KEY)?.unwrap_or_default(),
+            eip1559: count(EIP1559_KEY)?.unwrap_or_default(),
+        }))
+    }
+
+    /// Tally the types of all transactions in the bodies database.
+    pub fn count<'a>(tx: &impl DbTx<'a>) -> eyre::Result<Self> {
+        let mut counts = Self::default();
+        for entry in tx.cursor_read::<tables::Transactions>()?.walk(None)? {
+            let (_, transaction) = entry?;
+            counts.record(transaction.tx_type());
+        }
+        Ok(counts)
+    }
+
+    /// Count one more transaction of the type.
+    pub fn record(&mut self, tx_type: TxType) {
+        match tx_type {
+            TxType::Legacy => self.legacy += 1,
+            TxType::EIP2930 => self.eip2930 += 1,
+            TxType::EIP1559 => self.eip1559 += 1,
+        }
+    }
+
+    pub fn save<'a>(&self, tx: &impl DbTxMut<'a>) -> eyre::Result<()> {
+        for (key, count) in self.entries() {
+            tx.put::<tables::SyncStage>(key.as_bytes().to_vec(), count)?;
+        }
+        Ok(())
+    }
+
+    /// Remove the counts from the bodies database, so that the transactions are tallied anew.
+    pub fn clear<'a>(tx: &impl DbTxMut<'a>) -> eyre::Result<()> {
+        for (key, _) in Self::default().entries() {
+            tx.delete::<tables::SyncStage>(key.as_bytes().to_vec(), None)?;
+        }
+        Ok(())
+    }
+
+    fn entries(&self) -> [(&'static str, u64); 3] {
+        [(LEGACY_KEY, self.legacy), (EIP2930_KEY, self.eip2930), (EIP1559_KEY, self.eip1559)]
+    }
+}
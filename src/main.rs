@@ -1,5 +1,6 @@
 pub mod cli;
 pub mod compression;
+pub mod config;
 pub mod database;
 pub mod remote;
 pub mod sync;
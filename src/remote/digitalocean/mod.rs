@@ -1 +1,24 @@
-pub mod store;
+use super::S3Store;
+use aws_sdk_s3::Credentials;
+use reth_primitives::Chain;
+
+/// The regions DigitalOcean Spaces are available in.
+const SPACES_REGIONS: [&str; 8] = ["ams3", "blr1", "fra1", "nyc3", "sfo2", "sfo3", "sgp1", "syd1"];
+
+/// Create the store for a DigitalOcean Spaces bucket, reached at the endpoint of its region.
+pub async fn spaces_store(
+    region: String,
+    bucket: String,
+    chain: Chain,
+    credentials: Option<Credentials>,
+) -> eyre::Result<S3Store> {
+    if !SPACES_REGIONS.contains(&region.as_str()) {
+        eyre::bail!(
+            "Unknown DigitalOcean Spaces region {region:?}, expected one of {}. Use \
+             `--remote.endpoint` for other S3-compatible hosts.",
+            SPACES_REGIONS.join(", ")
+        )
+    }
+    let endpoint = format!("https://{region}.digitaloceanspaces.com");
+    S3Store::new(Some(endpoint), region, bucket, chain, credentials).await
+}
@@ -1,3 +1,39 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::time::SystemTime;
+
+mod s3;
+pub use s3::store::S3Store;
 
 mod digitalocean;
-pub use digitalocean::store::RemoteStore;
+pub use digitalocean::spaces_store;
+
+/// The store the snapshots are published to and restored from.
+pub type RemoteStore = S3Store;
+
+/// The kind of host the snapshots bucket is on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RemoteBackend {
+    /// DigitalOcean Spaces, reached at the endpoint of the region.
+    Spaces,
+    /// Any S3-compatible host, such as AWS S3, MinIO, Wasabi or Backblaze B2.
+    S3,
+}
+
+/// The object listed in the remote store.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteEntry {
+    /// The object key relative to the chain namespace.
+    pub key: String,
+    /// The size of the object in bytes.
+    pub size: u64,
+    /// The time the object was last modified at, if the store reports it.
+    pub last_modified: Option<SystemTime>,
+}
+
+/// The region of the bucket the snapshots are published to.
+pub const DEFAULT_REGION: &str = "fra1";
+
+/// The bucket the snapshots are published to.
+pub const DEFAULT_BUCKET: &str = "reth-state-snapshots";
@@ -0,0 +1,353 @@
+use crate::compression::{decompress_file_parallel, is_gzip};
+use aws_config::from_env;
+use aws_sdk_s3::{
+    error::{GetObjectError, GetObjectErrorKind, HeadObjectError, HeadObjectErrorKind},
+    model::{Object, ObjectCannedAcl},
+    Client, Credentials, Region as AwsRegion,
+};
+use aws_smithy_http::byte_stream::ByteStream;
+use flate2::write::MultiGzDecoder;
+use reth_primitives::Chain;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::Write,
+    path::Path,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::remote::RemoteEntry;
+
+/// The interval between the download progress logs.
+const DOWNLOAD_LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Map the listed object to the entry with the key relative to the namespace, or [None] if the
+/// object has no key.
+fn remote_entry(object: &Object, namespace: &str) -> Option<RemoteEntry> {
+    let key = object.key()?;
+    let key = key.strip_prefix(namespace).unwrap_or(key).to_owned();
+    let last_modified = object
+        .last_modified()
+        .map(|time| UNIX_EPOCH + Duration::from_secs(time.secs().max(0) as u64));
+    Some(RemoteEntry { key, size: object.size().max(0) as u64, last_modified })
+}
+
+/// Snapshot store backed by a bucket on any S3-compatible host.
+#[derive(Clone)]
+pub struct S3Store {
+    bucket: String,
+    namespace: String,
+    client: Client,
+}
+
+impl S3Store {
+    /// Create the store for the snapshots of the given chain.
+    ///
+    /// The snapshots of each chain are kept under their own namespace in the bucket. Mainnet
+    /// snapshots stay at the root for compatibility with the already published ones.
+    ///
+    /// The AWS S3 endpoint of the region is used if no endpoint is set. The credentials are read
+    /// from the environment unless they are given explicitly.
+    pub async fn new(
+        endpoint: Option<String>,
+        region: String,
+        bucket: String,
+        chain: Chain,
+        credentials: Option<Credentials>,
+    ) -> eyre::Result<Self> {
+        let mut config = from_env().region(AwsRegion::new(region));
+        if let Some(endpoint) = endpoint {
+            config = config.endpoint_url(endpoint);
+        }
+        if let Some(credentials) = credentials {
+            config = config.credentials_provider(credentials);
+        }
+        let namespace = if chain == Chain::mainnet() { String::new() } else { format!("{chain}/") };
+        Ok(Self { bucket, namespace, client: Client::new(&config.load().await) })
+    }
+
+    /// List the objects under the prefix in the chain namespace.
+    /// The objects without a key are skipped.
+    pub async fn list(&self, prefix: Option<&str>) -> eyre::Result<Vec<RemoteEntry>> {
+        tracing::trace!(target: "remote::s3", ?prefix, "Listing objects");
+        let prefix = format!("{}{}", self.namespace, prefix.unwrap_or_default());
+        let response = self
+            .client
+            .list_objects_v2()
+            .bucket(&self.bucket)
+            .set_prefix(Some(prefix).filter(|p| !p.is_empty()))
+            .send()
+            .await?;
+        let entries = response
+            .contents()
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|object| remote_entry(object, &self.namespace))
+            .collect();
+        Ok(entries)
+    }
+
+    fn namespaced(&self, path: &str) -> String {
+        format!("{}{path}", self.namespace)
+    }
+
+    /// Check whether the object exists without downloading it.
+    pub async fn exists(&self, path: &str) -> eyre::Result<bool> {
+        tracing::trace!(target: "remote::s3", path, "Checking object");
+        match self.client.head_object().bucket(&self.bucket).key(self.namespaced(path)).send().await
+        {
+            Ok(_) => Ok(true),
+            Err(err) => match err.into_service_error() {
+                HeadObjectError { kind: HeadObjectErrorKind::NotFound(_), .. } => Ok(false),
+                err @ _ => Err(err.into()),
+            },
+        }
+    }
+
+    /// Retrieve the user metadata of the object without downloading it.
+    pub async fn metadata(&self, path: &str) -> eyre::Result<Option<HashMap<String, String>>> {
+        tracing::trace!(target: "remote::s3", path, "Retrieving object metadata");
+        match self.client.head_object().bucket(&self.bucket).key(self.namespaced(path)).send().await
+        {
+            Ok(obj) => Ok(Some(obj.metadata().cloned().unwrap_or_default())),
+            Err(err) => match err.into_service_error() {
+                HeadObjectError { kind: HeadObjectErrorKind::NotFound(_), .. } => Ok(None),
+                err @ _ => Err(err.into()),
+            },
+        }
+    }
+
+    /// Download the object into memory, decompressing it if it is gzip. Objects in any other
+    /// format, or served already decoded by the host, are returned as is.
+    pub async fn retrieve(&self, path: &str) -> eyre::Result<Option<Vec<u8>>> {
+        tracing::trace!(target: "remote::s3", path, "Retrieving object");
+        match self.client.get_object().bucket(&self.bucket).key(self.namespaced(path)).send().await
+        {
+            Ok(obj) => {
+                let contents = obj.body.collect().await?.to_vec();
+                if !is_gzip(&contents) {
+                    tracing::trace!(target: "remote::s3", path, "Object is not gzip, skipping decompression");
+                    return Ok(Some(contents))
+                }
+                let mut decoder = MultiGzDecoder::new(Vec::new());
+                decoder.write_all(&contents)?;
+                Ok(Some(decoder.finish()?))
+            }
+            Err(err) => match err.into_service_error() {
+                GetObjectError { kind: GetObjectErrorKind::NoSuchKey(_), .. } => Ok(None),
+                err @ _ => Err(err.into()),
+            },
+        }
+    }
+
+    /// Download the object and decompress it into the file at `destination` as it is received,
+    /// logging the download progress. The file is synced to disk once the download completes.
+    ///
+    /// With more than one `decompress_threads`, the object must be in the parallel compression
+    /// format. It is downloaded next to the destination first, and then decompressed in parallel.
+    ///
+    /// Returns `false` if the object does not exist.
+    pub async fn retrieve_to(
+        &self,
+        path: &str,
+        destination: &Path,
+        decompress_threads: usize,
+    ) -> eyre::Result<bool> {
+        tracing::trace!(target: "remote::s3", path, "Retrieving object");
+        let mut obj = match self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(self.namespaced(path))
+            .send()
+            .await
+        {
+            Ok(obj) => obj,
+            Err(err) => match err.into_service_error() {
+                GetObjectError { kind: GetObjectErrorKind::NoSuchKey(_), .. } => return Ok(false),
+                err @ _ => return Err(err.into()),
+            },
+        };
+
+        let total = obj.content_length().max(0) as u64;
+        let compressed_path = destination.with_extension("gz.part");
+        let mut sink = if decompress_threads > 1 {
+            DownloadSink::Compressed(File::create(&compressed_path)?)
+        } else {
+            // The snapshot might consist of multiple gzip members.
+            DownloadSink::Decoder(MultiGzDecoder::new(File::create(destination)?))
+        };
+        let started_at = Instant::now();
+        let mut last_logged = started_at;
+        let mut downloaded = 0u64;
+        while let Some(chunk) = obj.body.next().await {
+            let chunk = chunk?;
+            downloaded += chunk.len() as u64;
+            sink.write_all(&chunk)?;
+
+            if last_logged.elapsed() >= DOWNLOAD_LOG_INTERVAL {
+                last_logged = Instant::now();
+                tracing::info!(
+                    target: "remote::s3",
+                    path,
+                    downloaded_mb = downloaded / 1024 / 1024,
+                    total_mb = total / 1024 / 1024,
+                    percent = %format!("{:.2}", downloaded as f64 / total.max(1) as f64 * 100.0),
+                    mb_per_sec = %format!("{:.2}", mb_per_sec(downloaded, started_at.elapsed())),
+                    "Download progress"
+                );
+            }
+        }
+
+        match sink {
+            DownloadSink::Decoder(decoder) => decoder.finish()?.sync_all()?,
+            DownloadSink::Compressed(mut file) => {
+                file.flush()?;
+                drop(file);
+                let destination = destination.to_owned();
+                let source = File::open(&compressed_path)?;
+                let result = tokio::task::spawn_blocking(move || {
+                    decompress_file_parallel(source, &destination, decompress_threads)
+                })
+                .await?;
+                let _ = std::fs::remove_file(&compressed_path);
+                result?;
+            }
+        }
+        let file = File::open(destination)?;
+        let elapsed = started_at.elapsed();
+        tracing::info!(
+            target: "remote::s3",
+            path,
+            downloaded_mb = downloaded / 1024 / 1024,
+            decompressed_mb = file.metadata()?.len() / 1024 / 1024,
+            mb_per_sec = %format!("{:.2}", mb_per_sec(downloaded, elapsed)),
+            elapsed = ?elapsed,
+            "Download finished"
+        );
+        Ok(true)
+    }
+
+    /// Upload an already compressed file under the given path along with the user metadata.
+    ///
+    /// The upload is skipped if the object already has the same contents, as determined by its
+    /// ETag. Returns whether the file was uploaded.
+    pub async fn save(
+        &self,
+        path: &str,
+        compressed_path: &Path,
+        metadata: HashMap<String, String>,
+    ) -> eyre::Result<bool> {
+        // The whole file is hashed, so it is kept off the runtime threads.
+        let owned_path = compressed_path.to_path_buf();
+        let etag = tokio::task::spawn_blocking(move || file_etag(&owned_path)).await??;
+        if self.etag(path).await?.as_deref() == Some(etag.as_str()) {
+            tracing::trace!(target: "remote::s3", path, %etag, "Object unchanged, skipping upload");
+            return Ok(false)
+        }
+
+        tracing::trace!(target: "remote::s3", path, "Putting object");
+        tracing::trace!(target: "remote::s3", compressed = %compressed_path.display(), "Creating body from compressed file");
+        let body = ByteStream::from_path(compressed_path).await?;
+        let _ = self
+            .client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(self.namespaced(path))
+            .body(body)
+            .set_metadata(Some(metadata))
+            .acl(ObjectCannedAcl::Private)
+            .send()
+            .await?;
+
+        Ok(true)
+    }
+
+    /// Retrieve the ETag of the object without downloading it.
+    async fn etag(&self, path: &str) -> eyre::Result<Option<String>> {
+        match self.client.head_object().bucket(&self.bucket).key(self.namespaced(path)).send().await
+        {
+            Ok(obj) => Ok(obj.e_tag().map(ToOwned::to_owned)),
+            Err(err) => match err.into_service_error() {
+                HeadObjectError { kind: HeadObjectErrorKind::NotFound(_), .. } => Ok(None),
+                err @ _ => Err(err.into()),
+            },
+        }
+    }
+
+    pub async fn delete(&self, path: &str) -> eyre::Result<()> {
+        tracing::trace!(target: "remote::s3", path, "Deleting object");
+        let _ = self
+            .client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(self.namespaced(path))
+            .send()
+            .await?;
+
+        Ok(())
+    }
+}
+
+/// The file the downloaded object is written to.
+enum DownloadSink {
+    /// Decompressed as it is received.
+    Decoder(MultiGzDecoder<File>),
+    /// Written compressed, to be decompressed once the download completes.
+    Compressed(File),
+}
+
+impl DownloadSink {
+    fn write_all(&mut self, chunk: &[u8]) -> std::io::Result<()> {
+        match self {
+            DownloadSink::Decoder(decoder) => decoder.write_all(chunk),
+            DownloadSink::Compressed(file) => file.write_all(chunk),
+        }
+    }
+}
+
+/// Compute the ETag the store assigns to the file uploaded in a single part, which is the quoted
+/// hex MD5 digest of its contents.
+fn file_etag(path: &Path) -> eyre::Result<String> {
+    let mut context = md5::Context::new();
+    std::io::copy(&mut File::open(path)?, &mut context)?;
+    Ok(format!("\"{:x}\"", context.compute()))
+}
+
+fn mb_per_sec(bytes: u64, elapsed: Duration) -> f64 {
+    bytes as f64 / 1024.0 / 1024.0 / elapsed.as_secs_f64().max(f64::EPSILON)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_s3::types::DateTime;
+
+    #[test]
+    fn remote_entry_strips_namespace() {
+        let object = Object::builder()
+            .key("sepolia/headers/headers-100.dat.gz")
+            .size(10)
+            .last_modified(DateTime::from_secs(60))
+            .build();
+        assert_eq!(
+            remote_entry(&object, "sepolia/"),
+            Some(RemoteEntry {
+                key: "headers/headers-100.dat.gz".to_owned(),
+                size: 10,
+                last_modified: Some(UNIX_EPOCH + Duration::from_secs(60)),
+            })
+        );
+        // The mainnet objects are kept at the root.
+        assert_eq!(
+            remote_entry(&object, "").map(|entry| entry.key),
+            Some("sepolia/headers/headers-100.dat.gz".to_owned())
+        );
+    }
+
+    #[test]
+    fn remote_entry_without_key() {
+        let object = Object::builder().size(10).build();
+        assert_eq!(remote_entry(&object, ""), None);
+    }
+}
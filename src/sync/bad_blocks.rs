@@ -0,0 +1,51 @@
+use reth_primitives::{BlockNumber, H256};
+use serde_json::json;
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+/// Records the blocks that failed to execute, so that the sync can skip them and collect all the
+/// divergent blocks of a range in one run.
+///
+/// The state changes of the skipped blocks are missing, so the state synced past them diverges
+/// from the chain and must not be published.
+#[derive(Default)]
+pub struct BadBlocks {
+    blocks: Mutex<Vec<BlockNumber>>,
+    writer: Option<Mutex<BufWriter<File>>>,
+}
+
+impl BadBlocks {
+    /// Also write the bad blocks to the file as JSON lines, truncating the existing one.
+    pub fn with_log(mut self, path: &Path) -> eyre::Result<Self> {
+        self.writer = Some(Mutex::new(BufWriter::new(File::create(path)?)));
+        Ok(self)
+    }
+
+    /// Record the block that failed to execute with the error.
+    pub fn record(&self, block: BlockNumber, hash: Option<H256>, error: &str) -> eyre::Result<()> {
+        self.blocks.lock().expect("not poisoned").push(block);
+
+        if let Some(writer) = &self.writer {
+            let line = json!({ "block": block, "hash": hash, "error": error });
+            let mut writer = writer.lock().expect("not poisoned");
+            serde_json::to_writer(&mut *writer, &line)?;
+            writer.write_all(b"\n")?;
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Log the blocks skipped so far.
+    pub fn log_summary(&self) {
+        let blocks = self.blocks.lock().expect("not poisoned");
+        if blocks.is_empty() {
+            tracing::info!(target: "sync::state", "No bad blocks encountered");
+        } else {
+            tracing::warn!(target: "sync::state", count = blocks.len(), blocks = ?*blocks, "Skipped bad blocks");
+        }
+    }
+}
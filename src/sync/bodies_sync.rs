@@ -1,4 +1,5 @@
 use futures::TryStreamExt;
+use itertools::Itertools;
 use reth_db::{
     cursor::{DbCursorRO, DbCursorRW},
     database::{Database, DatabaseGAT},
@@ -7,20 +8,64 @@ use reth_db::{
     transaction::{DbTx, DbTxMut},
 };
 use reth_interfaces::p2p::bodies::{downloader::BodyDownloader, response::BlockResponse};
-use reth_primitives::{BlockNumber, SealedHeader};
+use reth_primitives::{proofs, BlockNumber, ChainSpec, Hardfork, SealedBlock, SealedHeader};
 use reth_provider::ProviderError;
 use reth_stages::stages::BODIES;
+use std::ops::RangeInclusive;
 
-use super::Tip;
+use super::{ConsensusValidation, Tip};
+use crate::database::TxTypeCounts;
+
+/// The number of consecutive batches that may fail to advance the progress before the download
+/// range is reset.
+const MAX_STALLED_BATCHES: u32 = 3;
+
+/// The number of download range resets after which the sync gives up.
+const MAX_RANGE_RESETS: u32 = 5;
+
+/// The default number of blocks written before the bodies are committed.
+pub const DEFAULT_BODIES_COMMIT_THRESHOLD: u64 = 10_000;
 
 pub struct BodiesSync<DB, B> {
     db: DB,
     downloader: B,
+    chain_spec: ChainSpec,
+    commit_threshold: u64,
+    tx_type_stats: bool,
+    validation: ConsensusValidation,
 }
 
 impl<DB: Database, B: BodyDownloader> BodiesSync<DB, B> {
-    pub fn new(db: DB, downloader: B) -> Self {
-        Self { db, downloader }
+    pub fn new(db: DB, downloader: B, chain_spec: ChainSpec) -> Self {
+        Self {
+            db,
+            downloader,
+            chain_spec,
+            commit_threshold: DEFAULT_BODIES_COMMIT_THRESHOLD,
+            tx_type_stats: false,
+            validation: ConsensusValidation::default(),
+        }
+    }
+
+    /// Set the number of blocks written before the bodies are committed, regardless of the
+    /// downloader batch size.
+    pub fn with_commit_threshold(mut self, commit_threshold: u64) -> Self {
+        self.commit_threshold = commit_threshold;
+        self
+    }
+
+    /// Tally the types of the inserted transactions. The transactions already in the database are
+    /// tallied once on the first run.
+    pub fn with_tx_type_stats(mut self, tx_type_stats: bool) -> Self {
+        self.tx_type_stats = tx_type_stats;
+        self
+    }
+
+    /// Set the consensus validation level. The bodies are only checked against their headers on
+    /// the full validation, and the withdrawals presence as long as the headers are validated.
+    pub fn with_consensus_validation(mut self, validation: ConsensusValidation) -> Self {
+        self.validation = validation;
+        self
     }
 
     pub fn get_progress(&self) -> eyre::Result<BlockNumber> {
@@ -43,36 +88,131 @@ impl<DB: Database, B: BodyDownloader> BodiesSync<DB, B> {
             return Ok(())
         }
 
+        if self.tx_type_stats && TxTypeCounts::load(&self.db.tx()?)?.is_none() {
+            tracing::info!(target: "sync::bodies", progress, "Tallying the transaction types of the existing bodies");
+            let counts = TxTypeCounts::count(&self.db.tx()?)?;
+            self.db.update(|tx| counts.save(tx))??;
+            tracing::info!(target: "sync::bodies", ?counts, "Transaction types tallied");
+        }
+
         let mut latest_block_number = progress;
         let start_block = progress + 1;
         self.downloader.set_download_range(start_block..tip.number + 1)?;
         tracing::trace!(target: "sync::bodies", progress = progress, "Commencing sync");
 
+        // The progress is saved along with the bodies, so the uncommitted blocks are downloaded
+        // again if the sync is interrupted.
+        let mut tx = self.db.tx_mut()?;
+        let mut uncommitted = 0;
+        let (mut stalled, mut resets) = (0, 0);
         while latest_block_number < tip.number {
             let bodies = self.downloader.try_next().await?.ok_or(eyre::eyre!("channel closed"))?;
 
-            let tx = self.db.tx_mut()?;
+            // The batch must continue right after the last inserted block.
+            let first = bodies.first().map(|b| b.block_number());
+            if first != Some(latest_block_number + 1) {
+                stalled += 1;
+                tracing::warn!(target: "sync::bodies", progress = latest_block_number, ?first, len = bodies.len(), stalled, "Received bodies do not advance the progress");
+                if stalled >= MAX_STALLED_BATCHES {
+                    resets += 1;
+                    if resets > MAX_RANGE_RESETS {
+                        eyre::bail!(
+                            "Bodies download stalled at block #{latest_block_number} after {} \
+                             range resets",
+                            MAX_RANGE_RESETS
+                        )
+                    }
+                    tracing::warn!(target: "sync::bodies", progress = latest_block_number, resets, "Resetting the download range");
+                    self.downloader.set_download_range(latest_block_number + 1..tip.number + 1)?;
+                    stalled = 0;
+                }
+                continue
+            }
+            stalled = 0;
+
+            uncommitted += bodies.len() as u64;
             latest_block_number = self.insert_bodies(&tx, bodies)?;
-            tx.commit()?;
+
+            if uncommitted >= self.commit_threshold || latest_block_number >= tip.number {
+                tx.commit()?;
+                tracing::trace!(target: "sync::bodies", progress = latest_block_number, "Committed bodies");
+                uncommitted = 0;
+                tx = self.db.tx_mut()?;
+            }
         }
 
         tracing::trace!(target: "sync::bodies", progress = latest_block_number, "Finished syncing");
         Ok(())
     }
 
+    /// Download the bodies of an arbitrary set of blocks, skipping the ones already stored.
+    ///
+    /// The consecutive blocks are downloaded as one range. The bodies are stored along with their
+    /// transactions, which get the ids after the last stored transaction regardless of the block
+    /// number. The progress is left untouched, so the database must not be synced into.
+    pub async fn download_blocks(&mut self, blocks: &[BlockNumber]) -> eyre::Result<()> {
+        // The consecutive missing blocks are merged into the download ranges.
+        let mut ranges = Vec::<RangeInclusive<BlockNumber>>::new();
+        let mut skipped = 0;
+        {
+            let tx = self.db.tx()?;
+            let mut cursor = tx.cursor_read::<tables::BlockBodies>()?;
+            for block in blocks.iter().copied().sorted().dedup() {
+                if cursor.seek_exact(block)?.is_some() {
+                    skipped += 1;
+                    continue
+                }
+                match ranges.last_mut() {
+                    Some(range) if *range.end() + 1 == block => *range = *range.start()..=block,
+                    _ => ranges.push(block..=block),
+                }
+            }
+        }
+        tracing::info!(target: "sync::bodies", ranges = ranges.len(), skipped, "Downloading sparse bodies");
+
+        for range in ranges {
+            self.downloader.set_download_range(*range.start()..*range.end() + 1)?;
+            let mut next = *range.start();
+            let tx = self.db.tx_mut()?;
+            while next <= *range.end() {
+                let bodies =
+                    self.downloader.try_next().await?.ok_or(eyre::eyre!("channel closed"))?;
+                // The bodies outside of the range are ignored.
+                let bodies = bodies
+                    .into_iter()
+                    .skip_while(|body| body.block_number() < next)
+                    .take_while(|body| body.block_number() <= *range.end())
+                    .collect::<Vec<_>>();
+                if bodies.first().map(|b| b.block_number()) != Some(next) {
+                    tracing::warn!(target: "sync::bodies", next, "Received bodies do not continue the range, resetting it");
+                    self.downloader.set_download_range(next..*range.end() + 1)?;
+                    continue
+                }
+                next = self.insert_sparse_bodies(&tx, bodies)? + 1;
+            }
+            tx.commit()?;
+            tracing::info!(target: "sync::bodies", ?range, "Stored sparse bodies");
+        }
+        Ok(())
+    }
+
     fn insert_bodies<'a>(
         &self,
         tx: &<DB as DatabaseGAT<'a>>::TXMut,
         bodies: Vec<BlockResponse>,
     ) -> eyre::Result<BlockNumber> {
-        let last_body = self.get_last_body()?;
+        let mut body_cursor = tx.cursor_write::<tables::BlockBodies>()?;
+
+        // Read the last body within the same transaction to account for the uncommitted bodies.
+        let (_, last_body) = body_cursor.last()?.ok_or(ProviderError::BlockBody { number: 0 })?;
         let mut current_tx_id = last_body.start_tx_id + last_body.tx_count;
 
-        let mut body_cursor = tx.cursor_write::<tables::BlockBodies>()?;
         let mut tx_cursor = tx.cursor_write::<tables::Transactions>()?;
         let mut ommers_cursor = tx.cursor_write::<tables::BlockOmmers>()?;
         let mut withdrawals_cursor = tx.cursor_write::<tables::BlockWithdrawals>()?;
 
+        let mut tx_types = if self.tx_type_stats { TxTypeCounts::load(tx)? } else { None };
+
         let range = bodies.first().unwrap().block_number()..=bodies.last().unwrap().block_number();
         tracing::trace!(target: "sync::bodies", ?range, "Inserting bodies");
 
@@ -83,6 +223,9 @@ impl<DB: Database, B: BodyDownloader> BodiesSync<DB, B> {
 
             match response {
                 BlockResponse::Full(block) => {
+                    self.validate_header(&block.header)?;
+                    self.validate_body(&block)?;
+
                     let body = StoredBlockBody {
                         start_tx_id: current_tx_id,
                         tx_count: block.body.len() as u64,
@@ -90,6 +233,9 @@ impl<DB: Database, B: BodyDownloader> BodiesSync<DB, B> {
                     body_cursor.append(block_number, body)?;
 
                     for transaction in block.body {
+                        if let Some(tx_types) = tx_types.as_mut() {
+                            tx_types.record(transaction.tx_type());
+                        }
                         tx_cursor.append(current_tx_id, transaction)?;
                         current_tx_id += 1;
                     }
@@ -99,25 +245,262 @@ impl<DB: Database, B: BodyDownloader> BodiesSync<DB, B> {
                         ommers_cursor.append(block_number, StoredBlockOmmers { ommers })?;
                     }
 
+                    // Store the withdrawals even if the list is empty to distinguish post-Shanghai
+                    // blocks from the ones that have no withdrawals field.
                     if let Some(withdrawals) = block.withdrawals {
-                        if !withdrawals.is_empty() {
-                            withdrawals_cursor
-                                .append(block_number, StoredBlockWithdrawals { withdrawals })?;
-                        }
+                        withdrawals_cursor
+                            .append(block_number, StoredBlockWithdrawals { withdrawals })?;
                     }
                 }
-                BlockResponse::Empty(_) => {
+                BlockResponse::Empty(header) => {
+                    self.validate_header(&header)?;
                     body_cursor.append(
                         block_number,
                         StoredBlockBody { start_tx_id: current_tx_id, tx_count: 0 },
                     )?;
+
+                    if header.withdrawals_root.is_some() {
+                        withdrawals_cursor.append(
+                            block_number,
+                            StoredBlockWithdrawals { withdrawals: Vec::new() },
+                        )?;
+                    }
                 }
             };
         }
 
+        if let Some(tx_types) = tx_types {
+            tx_types.save(tx)?;
+        }
+
         let progress = latest.unwrap();
         tracing::trace!(target: "sync::bodies", progress, "Progress updated");
         BODIES.save_progress(tx, progress)?;
         Ok(progress)
     }
+
+    /// Store the consecutive bodies at any position. Returns the last stored block.
+    fn insert_sparse_bodies<'a>(
+        &self,
+        tx: &<DB as DatabaseGAT<'a>>::TXMut,
+        bodies: Vec<BlockResponse>,
+    ) -> eyre::Result<BlockNumber> {
+        // The bodies are not stored in order, so the last body might not hold the last
+        // transaction.
+        let mut tx_cursor = tx.cursor_write::<tables::Transactions>()?;
+        let mut current_tx_id = tx_cursor.last()?.map_or(0, |(id, _)| id + 1);
+
+        let mut latest = 0;
+        for response in bodies {
+            let block_number = response.block_number();
+            latest = block_number;
+
+            let header = match &response {
+                BlockResponse::Full(block) => &block.header,
+                BlockResponse::Empty(header) => header,
+            };
+            self.validate_header(header)?;
+            let has_withdrawals_root = header.withdrawals_root.is_some();
+
+            match response {
+                BlockResponse::Full(block) => {
+                    self.validate_body(&block)?;
+                    let body = StoredBlockBody {
+                        start_tx_id: current_tx_id,
+                        tx_count: block.body.len() as u64,
+                    };
+                    tx.put::<tables::BlockBodies>(block_number, body)?;
+                    for transaction in block.body {
+                        tx_cursor.append(current_tx_id, transaction)?;
+                        current_tx_id += 1;
+                    }
+                    if !block.ommers.is_empty() {
+                        let ommers = block.ommers.into_iter().map(SealedHeader::unseal).collect();
+                        tx.put::<tables::BlockOmmers>(block_number, StoredBlockOmmers { ommers })?;
+                    }
+                    if let Some(withdrawals) = block.withdrawals {
+                        tx.put::<tables::BlockWithdrawals>(
+                            block_number,
+                            StoredBlockWithdrawals { withdrawals },
+                        )?;
+                    }
+                }
+                BlockResponse::Empty(_) => {
+                    tx.put::<tables::BlockBodies>(
+                        block_number,
+                        StoredBlockBody { start_tx_id: current_tx_id, tx_count: 0 },
+                    )?;
+                    if has_withdrawals_root {
+                        tx.put::<tables::BlockWithdrawals>(
+                            block_number,
+                            StoredBlockWithdrawals { withdrawals: Vec::new() },
+                        )?;
+                    }
+                }
+            }
+        }
+        Ok(latest)
+    }
+
+    fn validate_header(&self, header: &SealedHeader) -> eyre::Result<()> {
+        if self.validation.validates_headers() {
+            validate_withdrawals_presence(header, &self.chain_spec)?;
+        }
+        Ok(())
+    }
+
+    fn validate_body(&self, block: &SealedBlock) -> eyre::Result<()> {
+        if self.validation.validates_bodies() {
+            validate_body(block)?;
+        }
+        Ok(())
+    }
+}
+
+/// Check that the header has the withdrawals root if and only if Shanghai is active at its
+/// timestamp. The body withdrawals are then checked against the root by [validate_body].
+fn validate_withdrawals_presence(
+    header: &SealedHeader,
+    chain_spec: &ChainSpec,
+) -> eyre::Result<()> {
+    let shanghai_active = chain_spec.fork(Hardfork::Shanghai).active_at_timestamp(header.timestamp);
+    match (shanghai_active, header.withdrawals_root.is_some()) {
+        (true, false) => eyre::bail!(
+            "Block #{} is past Shanghai activation but has no withdrawals root",
+            header.number
+        ),
+        (false, true) => eyre::bail!(
+            "Block #{} is before Shanghai activation but has a withdrawals root",
+            header.number
+        ),
+        _ => Ok(()),
+    }
+}
+
+/// Check that the downloaded body matches the roots of the header from the headers database.
+fn validate_body(block: &SealedBlock) -> eyre::Result<()> {
+    let header = &block.header;
+
+    let transactions_root = proofs::calculate_transaction_root(block.body.iter());
+    if transactions_root != header.transactions_root {
+        eyre::bail!(
+            "Body of block #{} does not match the transactions root: expected {:?}, got {:?}",
+            header.number,
+            header.transactions_root,
+            transactions_root,
+        )
+    }
+
+    let ommers_hash = proofs::calculate_ommers_root(block.ommers.iter().map(|h| &**h));
+    if ommers_hash != header.ommers_hash {
+        eyre::bail!(
+            "Body of block #{} does not match the ommers hash: expected {:?}, got {:?}",
+            header.number,
+            header.ommers_hash,
+            ommers_hash,
+        )
+    }
+
+    let withdrawals_root =
+        block.withdrawals.as_ref().map(|w| proofs::calculate_withdrawals_root(w.iter()));
+    if withdrawals_root != header.withdrawals_root {
+        eyre::bail!(
+            "Body of block #{} does not match the withdrawals root: expected {:?}, got {:?}",
+            header.number,
+            header.withdrawals_root,
+            withdrawals_root,
+        )
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::{
+            test_utils::{create_test_db, test_chain_spec},
+            Stage,
+        },
+        sync::test_utils::{test_genesis, test_headers, TestBodyDownloader},
+    };
+
+    fn empty_bodies(headers: &[SealedHeader]) -> Vec<BlockResponse> {
+        headers.iter().cloned().map(BlockResponse::Empty).collect()
+    }
+
+    fn stored_blocks<DB: Database>(db: &DB) -> Vec<BlockNumber> {
+        let tx = db.tx().unwrap();
+        let mut cursor = tx.cursor_read::<tables::BlockBodies>().unwrap();
+        let walker = cursor.walk(None).unwrap();
+        walker.map(|entry| entry.unwrap().0).collect()
+    }
+
+    #[tokio::test]
+    async fn run_syncs_to_tip() {
+        let (_dir, db) = create_test_db(Stage::Bodies);
+        let headers = test_headers(&test_genesis(), 10);
+        let tip = headers.last().unwrap();
+
+        let downloader = TestBodyDownloader::new(empty_bodies(&headers), 3);
+        let mut sync = BodiesSync::new(db, downloader, test_chain_spec()).with_commit_threshold(4);
+        sync.run(Tip::new(tip.hash(), tip.number)).await.unwrap();
+
+        assert_eq!(sync.get_progress().unwrap(), 10);
+        assert_eq!(stored_blocks(&sync.db), (0..=10).collect::<Vec<_>>());
+        assert_eq!(sync.get_last_body().unwrap(), StoredBlockBody { start_tx_id: 0, tx_count: 0 });
+        // The blocks before Shanghai have no withdrawals.
+        let tx = sync.db.tx().unwrap();
+        assert_eq!(tx.cursor_read::<tables::BlockWithdrawals>().unwrap().first().unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn run_validates_bodies() {
+        let headers = test_headers(&test_genesis(), 2);
+        let tip = headers.last().unwrap();
+        // The ommer is not committed to by the ommers hash of the header.
+        let ommer = test_genesis();
+        let block = SealedBlock {
+            header: tip.clone(),
+            body: Vec::new(),
+            ommers: vec![ommer.clone()],
+            withdrawals: None,
+        };
+        let responses = vec![BlockResponse::Empty(headers[0].clone()), BlockResponse::Full(block)];
+
+        let (_dir, db) = create_test_db(Stage::Bodies);
+        let downloader = TestBodyDownloader::new(responses.clone(), 10);
+        let mut sync = BodiesSync::new(db, downloader, test_chain_spec());
+        let error = sync.run(Tip::new(tip.hash(), tip.number)).await.unwrap_err();
+        assert!(error.to_string().contains("does not match the ommers hash"), "{error}");
+        assert_eq!(sync.get_progress().unwrap(), 0);
+        assert_eq!(stored_blocks(&sync.db), vec![0]);
+
+        // The bodies are stored as downloaded if only the headers are validated.
+        let (_dir, db) = create_test_db(Stage::Bodies);
+        let downloader = TestBodyDownloader::new(responses, 10);
+        let mut sync = BodiesSync::new(db, downloader, test_chain_spec())
+            .with_consensus_validation(ConsensusValidation::Headers);
+        sync.run(Tip::new(tip.hash(), tip.number)).await.unwrap();
+        assert_eq!(sync.get_progress().unwrap(), 2);
+        let ommers = sync.db.tx().unwrap().get::<tables::BlockOmmers>(2).unwrap();
+        assert_eq!(ommers.map(|stored| stored.ommers), Some(vec![ommer.unseal()]));
+    }
+
+    #[tokio::test]
+    async fn download_blocks_stores_sparse_bodies() {
+        let (_dir, db) = create_test_db(Stage::Bodies);
+        let headers = test_headers(&test_genesis(), 10);
+
+        let downloader = TestBodyDownloader::new(empty_bodies(&headers), 3);
+        let mut sync = BodiesSync::new(db, downloader, test_chain_spec());
+        sync.download_blocks(&[7, 2, 3, 3, 9]).await.unwrap();
+        assert_eq!(stored_blocks(&sync.db), vec![0, 2, 3, 7, 9]);
+
+        // The stored blocks are skipped, and the progress is left untouched.
+        sync.download_blocks(&[2, 4]).await.unwrap();
+        assert_eq!(stored_blocks(&sync.db), vec![0, 2, 3, 4, 7, 9]);
+        assert_eq!(sync.get_progress().unwrap(), 0);
+    }
 }
@@ -0,0 +1,101 @@
+use clap::ValueEnum;
+use reth_interfaces::consensus::{Consensus, ConsensusError, ForkchoiceState};
+use reth_primitives::{SealedBlock, SealedHeader, U256};
+use std::{fmt, sync::Arc};
+use tokio::sync::watch;
+
+/// How much of the consensus rules the downloaded headers and bodies are validated against.
+///
+/// The headers are always linked by their hashes to the configured tip, so the chain itself
+/// cannot be forged as long as the tip is trusted. The consensus rules additionally protect
+/// against the peers that serve malformed headers or bodies that do not match their headers.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ConsensusValidation {
+    /// Validate both the headers and the bodies.
+    #[default]
+    Full,
+    /// Validate the headers only. The bodies are not checked against the transactions, ommers
+    /// and withdrawals roots of their headers, so a bogus body only surfaces once it fails to
+    /// execute.
+    Headers,
+    /// Skip the validation altogether. Only safe if the peers are trusted as well as the tip.
+    None,
+}
+
+impl ConsensusValidation {
+    /// Whether the header rules are checked.
+    pub fn validates_headers(&self) -> bool {
+        matches!(self, ConsensusValidation::Full | ConsensusValidation::Headers)
+    }
+
+    /// Whether the bodies are checked against their headers.
+    pub fn validates_bodies(&self) -> bool {
+        matches!(self, ConsensusValidation::Full)
+    }
+}
+
+impl fmt::Display for ConsensusValidation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConsensusValidation::Full => f.write_str("full"),
+            ConsensusValidation::Headers => f.write_str("headers"),
+            ConsensusValidation::None => f.write_str("none"),
+        }
+    }
+}
+
+/// The consensus engine that skips the checks above the validation level.
+#[derive(Debug)]
+pub struct ValidatingConsensus<C> {
+    inner: Arc<C>,
+    validation: ConsensusValidation,
+}
+
+impl<C> ValidatingConsensus<C> {
+    pub fn new(inner: Arc<C>, validation: ConsensusValidation) -> Self {
+        Self { inner, validation }
+    }
+}
+
+impl<C: Consensus> Consensus for ValidatingConsensus<C> {
+    fn fork_choice_state(&self) -> watch::Receiver<ForkchoiceState> {
+        self.inner.fork_choice_state()
+    }
+
+    fn pre_validate_header(
+        &self,
+        header: &SealedHeader,
+        parent: &SealedHeader,
+    ) -> Result<(), ConsensusError> {
+        if self.validation.validates_headers() {
+            self.inner.pre_validate_header(header, parent)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn validate_header(
+        &self,
+        header: &SealedHeader,
+        total_difficulty: U256,
+    ) -> Result<(), ConsensusError> {
+        if self.validation.validates_headers() {
+            self.inner.validate_header(header, total_difficulty)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn pre_validate_block(&self, block: &SealedBlock) -> Result<(), ConsensusError> {
+        if self.validation.validates_bodies() {
+            self.inner.pre_validate_block(block)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// The block rewards are part of the execution, so they are never skipped.
+    fn has_block_reward(&self, total_difficulty: U256, difficulty: U256) -> bool {
+        self.inner.has_block_reward(total_difficulty, difficulty)
+    }
+}
@@ -7,20 +7,57 @@ use reth_db::{
     Error as DatabaseError,
 };
 use reth_interfaces::p2p::headers::downloader::{HeaderDownloader, SyncTarget};
-use reth_primitives::{BlockNumber, SealedHeader, H256};
+use reth_primitives::{BlockNumber, ChainSpec, ForkCondition, Hardfork, SealedHeader, H256, U256};
 use reth_provider::ProviderError;
 use reth_stages::stages::{SyncGap, HEADERS};
+use std::time::{Duration, Instant};
 
-use super::Tip;
+use super::{ConsensusValidation, Tip};
+
+/// The maximum time to wait for the next batch of headers before considering the download stalled.
+const HEADERS_STALL_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// The default maximum number of canonical headers that can be unwound on a reorg.
+pub const DEFAULT_MAX_REORG_DEPTH: u64 = 64;
 
 pub struct HeadersSync<DB, H> {
     pub db: DB,
     header_downloader: H,
+    chain_spec: ChainSpec,
+    max_reorg_depth: u64,
+    /// The number of the first proof-of-stake block, if known.
+    merge_block: Option<BlockNumber>,
+    validation: ConsensusValidation,
 }
 
 impl<DB: Database, H: HeaderDownloader> HeadersSync<DB, H> {
-    pub fn new(db: DB, header_downloader: H) -> Self {
-        Self { db, header_downloader }
+    pub fn new(db: DB, header_downloader: H, chain_spec: ChainSpec) -> Self {
+        let merge_block = match chain_spec.fork(Hardfork::Paris) {
+            ForkCondition::TTD { fork_block, .. } => fork_block,
+            ForkCondition::Block(block) => Some(block),
+            _ => None,
+        };
+        Self {
+            db,
+            header_downloader,
+            chain_spec,
+            max_reorg_depth: DEFAULT_MAX_REORG_DEPTH,
+            merge_block,
+            validation: ConsensusValidation::default(),
+        }
+    }
+
+    /// Set the maximum number of canonical headers that can be unwound on a reorg.
+    pub fn with_max_reorg_depth(mut self, max_reorg_depth: u64) -> Self {
+        self.max_reorg_depth = max_reorg_depth;
+        self
+    }
+
+    /// Set the consensus validation level. The downloaded headers are not checked at all if the
+    /// validation is skipped.
+    pub fn with_consensus_validation(mut self, validation: ConsensusValidation) -> Self {
+        self.validation = validation;
+        self
     }
 
     pub fn get_progress(&self) -> eyre::Result<BlockNumber> {
@@ -35,23 +72,61 @@ impl<DB: Database, H: HeaderDownloader> HeadersSync<DB, H> {
         Ok(last_number)
     }
 
-    pub async fn run(&mut self, tip: Tip) -> eyre::Result<()> {
+    /// Download the headers up to the tip, unwinding the stored ones on a competing fork.
+    /// Returns the lowest block the headers were unwound from, if any, so that the bodies and the
+    /// state past it can be dealt with.
+    pub async fn run(&mut self, tip: Tip) -> eyre::Result<Option<BlockNumber>> {
         // Download headers
-        let headers_progress = self.get_progress()?;
+        let mut headers_progress = self.get_progress()?;
+        let mut reorg_depth = 0;
+        let mut unwound_from = None;
+
+        // The tip is below the local progress, but on a different chain.
+        let canonical_tip = self.db.view(|tx| tx.get::<tables::CanonicalHeaders>(tip.number))??;
+        if tip.number <= headers_progress && canonical_tip.map_or(false, |hash| hash != tip.hash) {
+            reorg_depth += self.unwind(tip.number, &mut headers_progress, reorg_depth)?;
+            unwound_from = Some(tip.number);
+        }
 
         if tip.number <= headers_progress {
             tracing::info!(target: "sync::headers", progress = headers_progress, tip = tip.number, "Nothing to sync");
-            return Ok(())
+            self.ensure_tip_reached(tip)?;
+            return Ok(unwound_from)
         }
 
         tracing::trace!(target: "sync::headers", progress = headers_progress, "Commencing sync");
+        let mut progress = HeadersProgress::new(headers_progress, tip.number);
         while let Some(gap) = self.get_sync_gap(headers_progress, tip.hash)? {
             if !gap.is_closed() {
+                let local_head_number = gap.local_head.number;
                 self.header_downloader.update_sync_gap(gap.local_head, gap.target);
 
                 let headers =
-                    self.header_downloader.next().await.ok_or(eyre::eyre!("channel closed"))?;
+                    tokio::time::timeout(HEADERS_STALL_TIMEOUT, self.header_downloader.next())
+                        .await
+                        .map_err(|_| {
+                            eyre::eyre!(
+                                "No headers received for {}s while closing the gap from block #{} \
+                                 towards tip {:?}. Make sure that `--debug.tip` is the hash of a \
+                                 canonical block known to the network.",
+                                HEADERS_STALL_TIMEOUT.as_secs(),
+                                local_head_number,
+                                tip.hash,
+                            )
+                        })?
+                        .ok_or(eyre::eyre!("channel closed"))?;
                 tracing::trace!(target: "sync::headers", len = headers.len(), "Downloaded headers");
+                self.validate_headers(&headers)?;
+                if let Some(lowest) = headers.last() {
+                    progress.update(headers.len() as u64, lowest.number);
+                }
+
+                if let Some(fork_block) = self.find_fork(&headers)? {
+                    reorg_depth += self.unwind(fork_block, &mut headers_progress, reorg_depth)?;
+                    unwound_from =
+                        Some(unwound_from.map_or(fork_block, |from| fork_block.min(from)));
+                }
+
                 self.db.update(|tx| {
                     let mut cursor_header = tx.cursor_write::<tables::Headers>()?;
                     let mut cursor_canonical = tx.cursor_write::<tables::CanonicalHeaders>()?;
@@ -60,6 +135,11 @@ impl<DB: Database, H: HeaderDownloader> HeadersSync<DB, H> {
                         let header_number = header.number;
                         let header = header.unseal();
 
+                        // The header is already stored on the same chain.
+                        if cursor_canonical.seek_exact(header_number)?.is_some() {
+                            continue
+                        }
+
                         cursor_header.insert(header_number, header)?;
                         cursor_canonical.insert(header_number, header_hash)?;
                     }
@@ -72,6 +152,121 @@ impl<DB: Database, H: HeaderDownloader> HeadersSync<DB, H> {
         let latest_block_number = self.get_last_header_number()?;
         self.db.update(|tx| HEADERS.save_progress(tx, latest_block_number))??;
         tracing::trace!(target: "sync::headers", progress = latest_block_number, "Finished syncing headers");
+        self.ensure_tip_reached(tip)?;
+        Ok(unwound_from)
+    }
+
+    /// Check the fields of the downloaded headers, ordered from the highest to the lowest, before
+    /// they are inserted. The lowest header is also checked against its stored parent, if any.
+    fn validate_headers(&self, headers: &[SealedHeader]) -> eyre::Result<()> {
+        if !self.validation.validates_headers() {
+            return Ok(())
+        }
+        for header in headers {
+            validate_header(header, &self.chain_spec)?;
+            if self.merge_block.map_or(false, |merge_block| header.number >= merge_block) {
+                validate_pos_header(header)?;
+            }
+        }
+        for pair in headers.windows(2) {
+            validate_child_timestamp(&pair[1], &pair[0])?;
+        }
+
+        if let Some(lowest) = headers.last().filter(|h| h.number > 0) {
+            let parent_number = lowest.number - 1;
+            if let Some(parent) = self.db.view(|tx| tx.get::<tables::Headers>(parent_number))?? {
+                validate_child_timestamp(&parent.seal_slow(), lowest)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Find the lowest block number at which the downloaded headers conflict with the stored
+    /// canonical chain, if any.
+    ///
+    /// A conflict is either a stored header with a different hash at the same number, or a stored
+    /// header right below the batch that is not the parent of the lowest downloaded header.
+    fn find_fork(&self, headers: &[SealedHeader]) -> eyre::Result<Option<BlockNumber>> {
+        let tx = self.db.tx()?;
+        let mut cursor = tx.cursor_read::<tables::CanonicalHeaders>()?;
+
+        let mut fork_block = None;
+        for header in headers {
+            if let Some((_, hash)) = cursor.seek_exact(header.number)? {
+                if hash != header.hash() {
+                    fork_block = Some(header.number);
+                }
+            }
+        }
+
+        if let Some(lowest) = headers.last().filter(|h| h.number > 0) {
+            let parent_number = lowest.number - 1;
+            if let Some((_, hash)) = cursor.seek_exact(parent_number)? {
+                if hash != lowest.parent_hash {
+                    fork_block = Some(parent_number);
+                }
+            }
+        }
+
+        Ok(fork_block)
+    }
+
+    /// Unwind the stored headers starting from `fork_block` and lower the progress accordingly.
+    /// Returns the number of removed canonical headers.
+    fn unwind(
+        &self,
+        fork_block: BlockNumber,
+        progress: &mut BlockNumber,
+        reorg_depth: u64,
+    ) -> eyre::Result<u64> {
+        if fork_block == 0 {
+            eyre::bail!("The downloaded chain does not share the genesis block with the local one")
+        }
+
+        let last_number = self.get_last_header_number()?;
+        let depth = last_number.saturating_sub(fork_block) + 1;
+        if reorg_depth + depth > self.max_reorg_depth {
+            eyre::bail!(
+                "Reorg at block #{fork_block} requires unwinding {} headers which exceeds the \
+                 maximum reorg depth of {}",
+                reorg_depth + depth,
+                self.max_reorg_depth,
+            )
+        }
+
+        tracing::warn!(target: "sync::headers", fork_block, depth, "Competing fork detected, unwinding headers");
+        self.db.update(|tx| {
+            let mut cursor_canonical = tx.cursor_write::<tables::CanonicalHeaders>()?;
+            while let Some((number, _)) = cursor_canonical.last()? {
+                if number < fork_block {
+                    break
+                }
+                cursor_canonical.delete_current()?;
+                tx.delete::<tables::Headers>(number, None)?;
+            }
+
+            if *progress >= fork_block {
+                *progress = fork_block - 1;
+                HEADERS.save_progress(tx, *progress)?;
+            }
+            Ok::<(), DatabaseError>(())
+        })??;
+
+        Ok(depth)
+    }
+
+    /// Check that the canonical header at the tip number has the tip hash.
+    fn ensure_tip_reached(&self, tip: Tip) -> eyre::Result<()> {
+        let canonical = self.db.view(|tx| tx.get::<tables::CanonicalHeaders>(tip.number))??;
+        if canonical != Some(tip.hash) {
+            eyre::bail!(
+                "Header sync did not reach the tip {:?} at block #{}, found {:?} instead. \
+                 Make sure that `--debug.tip` is the hash of a canonical block.",
+                tip.hash,
+                tip.number,
+                canonical,
+            )
+        }
         Ok(())
     }
 
@@ -114,3 +309,241 @@ impl<DB: Database, H: HeaderDownloader> HeadersSync<DB, H> {
         Ok(Some(SyncGap { local_head, target }))
     }
 }
+
+/// Check the header fields that can be verified without the rest of the chain.
+fn validate_header(header: &SealedHeader, chain_spec: &ChainSpec) -> eyre::Result<()> {
+    if header.gas_used > header.gas_limit {
+        eyre::bail!(
+            "Header #{} ({:?}) uses {} gas over its gas limit of {}",
+            header.number,
+            header.hash(),
+            header.gas_used,
+            header.gas_limit,
+        )
+    }
+
+    let london_active = chain_spec.fork(Hardfork::London).active_at_block(header.number);
+    match (london_active, header.base_fee_per_gas.is_some()) {
+        (true, false) => eyre::bail!(
+            "Header #{} ({:?}) is past London activation but has no base fee",
+            header.number,
+            header.hash(),
+        ),
+        (false, true) => eyre::bail!(
+            "Header #{} ({:?}) is before London activation but has a base fee",
+            header.number,
+            header.hash(),
+        ),
+        _ => Ok(()),
+    }
+}
+
+/// Check that the child header is timestamped after its parent.
+fn validate_child_timestamp(parent: &SealedHeader, child: &SealedHeader) -> eyre::Result<()> {
+    if child.number == parent.number + 1 && child.timestamp <= parent.timestamp {
+        eyre::bail!(
+            "Header #{} ({:?}) has timestamp {} which is not after its parent timestamp {}",
+            child.number,
+            child.hash(),
+            child.timestamp,
+            parent.timestamp,
+        )
+    }
+    Ok(())
+}
+
+/// Check that the post-merge header does not claim any proof-of-work.
+fn validate_pos_header(header: &SealedHeader) -> eyre::Result<()> {
+    if header.difficulty != U256::ZERO {
+        eyre::bail!(
+            "Header #{} ({:?}) is past the merge but has a non-zero difficulty of {}",
+            header.number,
+            header.hash(),
+            header.difficulty,
+        )
+    }
+    if header.nonce != 0 {
+        eyre::bail!(
+            "Header #{} ({:?}) is past the merge but has a non-zero nonce of {}",
+            header.number,
+            header.hash(),
+            header.nonce,
+        )
+    }
+    Ok(())
+}
+
+/// Tracks the progress of the reverse header download from the tip towards the local head.
+struct HeadersProgress {
+    /// The local head block number the download is closing the gap towards.
+    local_head: BlockNumber,
+    /// The total number of headers in the gap.
+    total: u64,
+    /// The number of headers downloaded so far.
+    downloaded: u64,
+    /// The time the download started at.
+    started_at: Instant,
+}
+
+impl HeadersProgress {
+    fn new(local_head: BlockNumber, tip: BlockNumber) -> Self {
+        Self { local_head, total: tip - local_head, downloaded: 0, started_at: Instant::now() }
+    }
+
+    /// Record a downloaded batch and log the overall progress.
+    ///
+    /// Headers are downloaded in reverse, so the gap is closed once the lowest downloaded header
+    /// is right above the local head.
+    fn update(&mut self, len: u64, lowest: BlockNumber) {
+        self.downloaded += len;
+        let remaining = lowest.saturating_sub(self.local_head + 1);
+        let percent = self.downloaded as f64 / self.total.max(1) as f64 * 100.0;
+
+        let elapsed = self.started_at.elapsed().as_secs_f64();
+        let rate = if elapsed > 0.0 { self.downloaded as f64 / elapsed } else { 0.0 };
+        let eta = (rate > 0.0).then(|| Duration::from_secs_f64(remaining as f64 / rate));
+
+        tracing::info!(
+            target: "sync::headers",
+            lowest,
+            remaining,
+            percent = %format!("{percent:.2}"),
+            blocks_per_sec = rate as u64,
+            eta = ?eta,
+            "Header download progress"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::{
+            test_utils::{create_test_db, test_chain_spec},
+            Stage,
+        },
+        sync::test_utils::{test_fork_headers, test_genesis, test_headers, TestHeaderDownloader},
+    };
+
+    /// The batch of the reverse download, ordered from the highest header.
+    fn reversed(headers: &[SealedHeader]) -> Vec<SealedHeader> {
+        headers.iter().rev().cloned().collect()
+    }
+
+    #[tokio::test]
+    async fn run_syncs_to_tip() {
+        let (_dir, db) = create_test_db(Stage::Headers);
+        let headers = test_headers(&test_genesis(), 10);
+        let tip = headers.last().unwrap();
+
+        let downloader = TestHeaderDownloader::new([reversed(&headers[5..]), reversed(&headers)]);
+        let mut sync = HeadersSync::new(db, downloader, test_chain_spec());
+        sync.run(Tip::new(tip.hash(), tip.number)).await.unwrap();
+
+        assert_eq!(sync.get_progress().unwrap(), 10);
+        let tx = sync.db.tx().unwrap();
+        for header in &headers {
+            assert_eq!(
+                tx.get::<tables::CanonicalHeaders>(header.number).unwrap(),
+                Some(header.hash())
+            );
+            assert_eq!(
+                tx.get::<tables::Headers>(header.number).unwrap(),
+                Some(header.clone().unseal())
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn run_with_synced_tip() {
+        let (_dir, db) = create_test_db(Stage::Headers);
+        let headers = test_headers(&test_genesis(), 3);
+        let tip = headers.last().unwrap();
+
+        let mut sync = HeadersSync::new(
+            db,
+            TestHeaderDownloader::new([reversed(&headers)]),
+            test_chain_spec(),
+        );
+        sync.run(Tip::new(tip.hash(), tip.number)).await.unwrap();
+        // Nothing is downloaded once the tip is reached.
+        sync.run(Tip::new(tip.hash(), tip.number)).await.unwrap();
+        assert_eq!(sync.get_progress().unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn run_validates_headers() {
+        let mut headers = test_headers(&test_genesis(), 3);
+        let mut invalid = headers.pop().unwrap().unseal();
+        invalid.gas_used = invalid.gas_limit + 1;
+        let tip = invalid.seal_slow();
+        headers.push(tip.clone());
+
+        let (_dir, db) = create_test_db(Stage::Headers);
+        let downloader = TestHeaderDownloader::new([reversed(&headers)]);
+        let mut sync = HeadersSync::new(db, downloader, test_chain_spec());
+        let error = sync.run(Tip::new(tip.hash(), tip.number)).await.unwrap_err();
+        assert!(error.to_string().contains("gas over its gas limit"), "{error}");
+        assert_eq!(sync.get_progress().unwrap(), 0);
+        assert_eq!(sync.get_last_header_number().unwrap(), 0);
+
+        // The header rules are not checked if the validation is skipped.
+        let (_dir, db) = create_test_db(Stage::Headers);
+        let downloader = TestHeaderDownloader::new([reversed(&headers)]);
+        let mut sync = HeadersSync::new(db, downloader, test_chain_spec())
+            .with_consensus_validation(ConsensusValidation::None);
+        sync.run(Tip::new(tip.hash(), tip.number)).await.unwrap();
+        assert_eq!(sync.get_progress().unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn run_unwinds_competing_fork() {
+        let (_dir, db) = create_test_db(Stage::Headers);
+        let headers = test_headers(&test_genesis(), 5);
+        let tip = headers.last().unwrap();
+        // The fork replaces the blocks from #3 onwards and extends the chain by one block.
+        let fork = test_fork_headers(&headers[1], 4);
+        let fork_tip = fork.last().unwrap();
+
+        let downloader = TestHeaderDownloader::new([reversed(&headers), reversed(&fork)]);
+        let mut sync = HeadersSync::new(db, downloader, test_chain_spec());
+        assert_eq!(sync.run(Tip::new(tip.hash(), tip.number)).await.unwrap(), None);
+        assert_eq!(sync.run(Tip::new(fork_tip.hash(), fork_tip.number)).await.unwrap(), Some(3));
+
+        assert_eq!(sync.get_progress().unwrap(), 6);
+        let tx = sync.db.tx().unwrap();
+        for header in headers[..2].iter().chain(&fork) {
+            assert_eq!(
+                tx.get::<tables::CanonicalHeaders>(header.number).unwrap(),
+                Some(header.hash())
+            );
+            assert_eq!(
+                tx.get::<tables::Headers>(header.number).unwrap(),
+                Some(header.clone().unseal())
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn run_rejects_reorg_over_max_depth() {
+        let (_dir, db) = create_test_db(Stage::Headers);
+        let headers = test_headers(&test_genesis(), 5);
+        let tip = headers.last().unwrap();
+        // Unwinding the blocks from #3 onwards removes three canonical headers.
+        let fork = test_fork_headers(&headers[1], 4);
+        let fork_tip = fork.last().unwrap();
+
+        let downloader = TestHeaderDownloader::new([reversed(&headers), reversed(&fork)]);
+        let mut sync = HeadersSync::new(db, downloader, test_chain_spec()).with_max_reorg_depth(2);
+        sync.run(Tip::new(tip.hash(), tip.number)).await.unwrap();
+        let error = sync.run(Tip::new(fork_tip.hash(), fork_tip.number)).await.unwrap_err();
+        assert!(error.to_string().contains("exceeds the maximum reorg depth of 2"), "{error}");
+
+        // The local chain is left untouched.
+        assert_eq!(sync.get_progress().unwrap(), 5);
+        assert_eq!(sync.get_last_header_number().unwrap(), 5);
+        let tx = sync.db.tx().unwrap();
+        assert_eq!(tx.get::<tables::CanonicalHeaders>(3).unwrap(), Some(headers[2].hash()));
+    }
+}
@@ -0,0 +1,49 @@
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+use tokio::sync::Notify;
+
+/// Signals the sync to stop at the next point where all of the written data is committed.
+#[derive(Debug, Clone, Default)]
+pub struct Interrupt {
+    triggered: Arc<AtomicBool>,
+    notify: Arc<Notify>,
+}
+
+impl Interrupt {
+    /// Request the sync to stop.
+    pub fn trigger(&self) {
+        self.triggered.store(true, Ordering::SeqCst);
+        self.notify.notify_waiters();
+    }
+
+    /// Returns `true` if the sync was requested to stop.
+    pub fn is_triggered(&self) -> bool {
+        self.triggered.load(Ordering::SeqCst)
+    }
+
+    /// Resolves once the sync was requested to stop.
+    pub async fn triggered(&self) {
+        loop {
+            // Register for the notification before checking the flag to not miss the trigger.
+            let notified = self.notify.notified();
+            if self.is_triggered() {
+                return
+            }
+            notified.await;
+        }
+    }
+
+    /// Drive the future until it completes or the interrupt is triggered.
+    /// Returns [None] if the future was interrupted.
+    pub async fn run<F: Future>(&self, fut: F) -> Option<F::Output> {
+        tokio::select! {
+            output = fut => Some(output),
+            _ = self.triggered() => None,
+        }
+    }
+}
@@ -1,23 +1,72 @@
-use std::path::Path;
-
-use crate::{
-    database::{SplitDatabase, BODIES_PREFIX, DAT_GZ_EXT, HEADERS_PREFIX, MDBX_DAT, STATE_PREFIX},
-    remote::RemoteStore,
+use crate::database::{
+    headers_diff_chain, reconcile_progress, state_diff_chain, truncate_bodies, write_headers_diff,
+    HeadersDiffKey, Snapshot, SnapshotContents, SplitDatabase, Stage, StateDiffKey, STATE_PREFIX,
 };
-use reth_db::database::Database;
+use reth_db::{database::Database, tables, transaction::DbTx};
 use reth_interfaces::p2p::{
     bodies::downloader::BodyDownloader, headers::downloader::HeaderDownloader,
 };
-use reth_primitives::{BlockNumber, H256};
+use reth_primitives::{BlockNumber, Chain, H256};
+use reth_provider::ProviderError;
+use std::time::Duration;
 
 mod headers_sync;
-pub use headers_sync::HeadersSync;
+pub use headers_sync::{HeadersSync, DEFAULT_MAX_REORG_DEPTH};
 
 mod bodies_sync;
-pub use bodies_sync::BodiesSync;
+pub use bodies_sync::{BodiesSync, DEFAULT_BODIES_COMMIT_THRESHOLD};
 
 mod state_sync;
-pub use state_sync::StateSync;
+pub use state_sync::{ExecutionMetrics, StateSync};
+
+mod profile;
+pub use profile::EvmProfile;
+
+mod consensus;
+pub use consensus::{ConsensusValidation, ValidatingConsensus};
+
+mod bad_blocks;
+pub use bad_blocks::BadBlocks;
+
+mod trace;
+pub use trace::ExecutionTrace;
+
+mod rpc;
+pub use rpc::{RpcTipSource, FINALIZED};
+
+mod interrupt;
+pub use interrupt::Interrupt;
+
+mod uploader;
+pub use uploader::{
+    RetentionPolicy, SnapshotUploader, DEFAULT_CLEANUP_CONCURRENCY, DEFAULT_CLEANUP_GRACE_PERIOD,
+    DEFAULT_COMPRESSION_LEVEL, DEFAULT_MAX_CONCURRENT_UPLOADS, UPLOAD_QUEUE_CAPACITY,
+};
+
+#[cfg(test)]
+pub(crate) mod test_utils;
+
+/// The default number of blocks between the state snapshots.
+pub const DEFAULT_STATE_SNAPSHOT_INTERVAL: BlockNumber = 100_000;
+
+/// The default number of blocks between the state snapshots of the chain.
+/// The mainnet state snapshots take long to upload, so they are published less often, while the
+/// small dev chains are snapshotted more often.
+pub fn default_state_snapshot_interval(chain: Chain) -> BlockNumber {
+    if chain == Chain::mainnet() {
+        500_000
+    } else if chain == Chain::goerli() || chain == Chain::sepolia() {
+        DEFAULT_STATE_SNAPSHOT_INTERVAL
+    } else {
+        10_000
+    }
+}
+
+/// The number of blocks the headers diffs may span before a new base snapshot is uploaded.
+pub const HEADERS_BASE_INTERVAL: BlockNumber = 500_000;
+
+/// The number of blocks the state diffs may span before a new base snapshot is uploaded.
+pub const STATE_BASE_INTERVAL: BlockNumber = 1_000_000;
 
 #[derive(Debug, Clone, Copy)]
 pub struct Tip {
@@ -31,67 +80,321 @@ impl Tip {
     }
 }
 
+/// Keeps following the tip of the chain once the initial sync is done.
+#[derive(Debug, Clone)]
+pub struct Follow {
+    /// The source of the new tips.
+    pub source: RpcTipSource,
+    /// The interval between the tip queries.
+    pub poll_interval: Duration,
+}
+
+/// Sync the headers up to the tip, then the bodies and the state up to the tip or `max_block`,
+/// whichever is lower. A state snapshot is uploaded every `snapshot_interval` blocks.
+///
+/// The progress of the stages is reconciled first, see [reconcile_progress].
+///
+/// The headers are always synced up to the tip, since they are downloaded in reverse from it. The
+/// capped tip is then taken from the downloaded canonical chain, and a state snapshot is uploaded
+/// at it regardless of the snapshot interval.
+///
+/// With `follow`, the finalized tip is polled once the tip is reached, and the sync is extended
+/// to every new one until interrupted.
+///
+/// With `halt_at`, the bodies and the state are synced exactly up to the block, which must not be
+/// past the tip or behind their progress, and their final snapshots are uploaded at it.
 pub async fn run_sync_with_snapshots<'a, DB: Database, H: HeaderDownloader, B: BodyDownloader>(
     mut headers_sync: HeadersSync<DB, H>,
     mut bodies_sync: BodiesSync<DB, B>,
     mut state_sync: StateSync<'a, DB>,
-    tip: Tip,
-    remote: RemoteStore,
+    mut tip: Tip,
+    max_block: Option<BlockNumber>,
+    halt_at: Option<BlockNumber>,
+    snapshot_interval: BlockNumber,
+    mut uploader: SnapshotUploader,
     db: SplitDatabase,
+    interrupt: Interrupt,
+    follow: Option<Follow>,
+) -> eyre::Result<()> {
+    // The stages resume from their own progress, so make sure that they do not leave a gap.
+    reconcile_progress(&db.headers(), &db.bodies(), &db.state())?;
+
+    if let Some(halt_at) = halt_at {
+        if halt_at > tip.number {
+            eyre::bail!("The halt block #{halt_at} is past the tip #{}", tip.number)
+        }
+        let bodies_progress = bodies_sync.get_progress()?;
+        let state_progress = state_sync.get_progress()?;
+        if bodies_progress > halt_at || state_progress > halt_at {
+            eyre::bail!(
+                "The halt block #{halt_at} is behind the local progress: bodies at \
+                 #{bodies_progress}, state at #{state_progress}"
+            )
+        }
+        tracing::info!(target: "sync", halt_at, "Syncing up to the halt block");
+    }
+    let max_block = halt_at.or(max_block);
+
+    // The state diverges from the chain once a bad block is skipped.
+    let publish_state = !state_sync.skips_bad_blocks();
+    if !publish_state {
+        tracing::warn!(target: "sync", "Skipping the bad blocks, the state snapshots will not be uploaded");
+    }
+
+    let mut reached;
+    loop {
+        reached = sync_to_tip(
+            &mut headers_sync,
+            &mut bodies_sync,
+            &mut state_sync,
+            tip,
+            max_block,
+            snapshot_interval,
+            publish_state,
+            &mut uploader,
+            &db,
+            &interrupt,
+        )
+        .await?;
+
+        let Some(follow) = follow.as_ref().filter(|_| reached) else { break };
+        tracing::info!(target: "sync", tip = tip.number, "Tip reached, following the chain");
+        match interrupt.run(next_tip(follow, tip)).await.transpose()? {
+            Some(next) => tip = next,
+            None => {
+                tracing::info!(target: "sync", "Following interrupted");
+                break
+            }
+        }
+    }
+
+    if let Some(halt_at) = halt_at.filter(|_| reached) {
+        upload_halt_snapshots(&mut uploader, &db, &bodies_sync, &state_sync, halt_at).await?;
+    }
+
+    // Wait for the pending snapshots to be uploaded
+    uploader.finish().await
+}
+
+/// Upload the final snapshots of the bodies and the state at the halt block. The state snapshot
+/// is skipped if it has already been uploaded once the halt block was reached.
+async fn upload_halt_snapshots<'a, DB: Database, B: BodyDownloader>(
+    uploader: &mut SnapshotUploader,
+    db: &SplitDatabase,
+    bodies_sync: &BodiesSync<DB, B>,
+    state_sync: &StateSync<'a, DB>,
+    halt_at: BlockNumber,
 ) -> eyre::Result<()> {
+    let bodies_progress = bodies_sync.get_progress()?;
+    let state_progress = state_sync.get_progress()?;
+    if bodies_progress != halt_at || state_progress != halt_at {
+        eyre::bail!(
+            "The sync did not halt at block #{halt_at}: bodies at #{bodies_progress}, state at \
+             #{state_progress}"
+        )
+    }
+
+    tracing::info!(target: "sync", halt_at, "Halt block reached, uploading the final snapshots");
+    let contents = SnapshotContents::collect(Stage::Bodies, &db.bodies())?;
+    uploader.upload_single(Stage::Bodies, &db.bodies_path, halt_at, contents).await?;
+    upload_state(uploader, db, state_sync, halt_at).await
+}
+
+/// Poll the tip source until it returns a tip ahead of the current one.
+/// The failed queries are retried, so that the sync survives the outages of the source.
+async fn next_tip(follow: &Follow, current: Tip) -> eyre::Result<Tip> {
+    loop {
+        tokio::time::sleep(follow.poll_interval).await;
+        match follow.source.tip(FINALIZED).await {
+            Ok(tip) if tip.number > current.number => {
+                tracing::info!(target: "sync", number = tip.number, hash = ?tip.hash, "New tip");
+                return Ok(tip)
+            }
+            Ok(_) => {}
+            Err(error) => {
+                tracing::warn!(target: "sync", %error, "Failed to fetch the tip, retrying");
+            }
+        }
+    }
+}
+
+/// Sync all stages up to the tip, or `max_block` if it is lower.
+/// Returns `false` if the sync was interrupted before reaching it.
+async fn sync_to_tip<'a, DB: Database, H: HeaderDownloader, B: BodyDownloader>(
+    headers_sync: &mut HeadersSync<DB, H>,
+    bodies_sync: &mut BodiesSync<DB, B>,
+    state_sync: &mut StateSync<'a, DB>,
+    tip: Tip,
+    max_block: Option<BlockNumber>,
+    snapshot_interval: BlockNumber,
+    publish_state: bool,
+    uploader: &mut SnapshotUploader,
+    db: &SplitDatabase,
+    interrupt: &Interrupt,
+) -> eyre::Result<bool> {
+    // Headers are downloaded in reverse, so the progress does not advance until the download
+    // completes and there is nothing to snapshot on interrupt.
     let last_headers_progress = headers_sync.get_progress()?;
-    headers_sync.run(tip.clone()).await?;
+    let Some(unwound_from) = interrupt.run(headers_sync.run(tip)).await.transpose()? else {
+        tracing::info!(target: "sync", "Headers sync interrupted");
+        return Ok(false)
+    };
+
+    if let Some(fork_block) = unwound_from {
+        // The state can not be unwound, since the changesets are not stored.
+        let state_progress = state_sync.get_progress()?;
+        if state_progress >= fork_block {
+            eyre::bail!(
+                "The headers were unwound from block #{fork_block} on a reorg, but the state is                  already executed up to #{state_progress}. Restore the state from a snapshot                  below the fork."
+            )
+        }
+        if bodies_sync.get_progress()? >= fork_block {
+            tracing::warn!(target: "sync", fork_block, "Truncating the bodies of the unwound headers");
+            truncate_bodies(&db.bodies(), fork_block)?;
+        }
+    }
 
     let new_headers_progress = headers_sync.get_progress()?;
-    if new_headers_progress > last_headers_progress {
-        save_single_snapshot(&remote, HEADERS_PREFIX, &db.headers_path, new_headers_progress)
-            .await?;
+    if unwound_from.is_some() || new_headers_progress > last_headers_progress {
+        // The remote headers diffs past the fork are stale, so they are replaced by a new base.
+        upload_headers(uploader, db, new_headers_progress, unwound_from.is_some()).await?;
     }
 
+    let capped = max_block.filter(|max_block| *max_block < tip.number);
+    let tip = match capped {
+        Some(max_block) => {
+            let hash = db
+                .headers()
+                .view(|tx| tx.get::<tables::CanonicalHeaders>(max_block))??
+                .ok_or(ProviderError::CanonicalHeader { block_number: max_block })?;
+            tracing::info!(target: "sync", max_block, ?hash, "Capping the sync at the maximum block");
+            Tip::new(hash, max_block)
+        }
+        None => tip,
+    };
+
     let last_bodies_progress = bodies_sync.get_progress()?;
-    bodies_sync.run(tip.clone()).await?;
+    if interrupt.run(bodies_sync.run(tip)).await.transpose()?.is_none() {
+        tracing::info!(target: "sync", "Bodies sync interrupted");
+        return Ok(false)
+    }
 
     // let new_bodies_progress = bodies_sync.get_progress()?;
     // if new_bodies_progress > last_bodies_progress {
-    //     save_single_snapshot(&remote, BODIES_PREFIX, &db.bodies_path,
-    // new_bodies_progress).await?; }
+    //     let contents = SnapshotContents::collect(Stage::Bodies, &db.bodies())?;
+    //     uploader.upload_single(Stage::Bodies, &db.bodies_path, new_bodies_progress,
+    // contents).await?; }
 
-    let snapshot_interval = 100_000;
     let mut sync_from = state_sync.get_progress()? + 1;
     while sync_from <= tip.number {
         let sync_until =
             tip.number.min(sync_from + snapshot_interval - (sync_from % snapshot_interval));
         state_sync.run(sync_from..=sync_until).await?;
+
+        if interrupt.is_triggered() {
+            // Publish the progress made since the last snapshot
+            let progress = state_sync.get_progress()?;
+            if publish_state && progress >= sync_from {
+                tracing::info!(target: "sync", block = progress, "Creating state snapshot on interrupt");
+                upload_state(uploader, db, state_sync, progress).await?;
+            }
+            return Ok(false)
+        }
         sync_from = sync_until + 1;
 
-        if sync_until != tip.number ||
-            (sync_until == tip.number && tip.number % snapshot_interval == 0)
-        {
+        let snapshot_due =
+            sync_until != tip.number || tip.number % snapshot_interval == 0 || capped.is_some();
+        if publish_state && snapshot_due {
             tracing::trace!(target: "sync", block = sync_until, "Creating state snapshot");
-            let snapshot_key = format!("{STATE_PREFIX}{sync_until}{DAT_GZ_EXT}");
-            let state_db_path = db.state_path.join(MDBX_DAT);
-            remote.save(&snapshot_key, &state_db_path).await?;
+            upload_state(uploader, db, state_sync, sync_until).await?;
         }
     }
+    Ok(true)
+}
 
-    Ok(())
+/// Upload the state at the block.
+///
+/// With the state diffs enabled, the changes since the previous state snapshot are uploaded as a
+/// diff on top of the latest remote base snapshot instead. A full base snapshot is uploaded if
+/// there is none yet, if the diffs would span more than [STATE_BASE_INTERVAL] blocks, or if the
+/// local changes do not extend the remote diffs.
+async fn upload_state<'a, DB: Database>(
+    uploader: &mut SnapshotUploader,
+    db: &SplitDatabase,
+    state_sync: &StateSync<'a, DB>,
+    block: BlockNumber,
+) -> eyre::Result<()> {
+    let diff = state_sync.take_state_diff();
+    if let Some((diff, from)) = diff.and_then(|diff| diff.from().map(|from| (diff, from))) {
+        let entries = uploader.remote().list(Some(STATE_PREFIX)).await?;
+        let keys = entries.iter().map(|e| e.key.as_str()).collect::<Vec<_>>();
+        let base = keys
+            .iter()
+            .filter_map(|key| Snapshot::parse_for(key, Stage::State))
+            .map(|s| s.block)
+            .max()
+            .filter(|base| block.saturating_sub(*base) < STATE_BASE_INTERVAL);
+
+        if let Some(base) = base {
+            let remote_progress = state_diff_chain(keys, base).last().map_or(base, |diff| diff.to);
+            if remote_progress >= block {
+                return Ok(())
+            }
+            if from <= remote_progress + 1 {
+                let key = StateDiffKey { from, to: block };
+                tracing::trace!(target: "sync", %key, "Creating state diff");
+                let td = state_sync.get_td(block)?;
+                let compressed = diff.write(from, block, td, &uploader.temp_dir(&db.state_path))?;
+                return uploader.upload_compressed(key.to_string(), compressed).await
+            }
+            tracing::warn!(target: "sync", from, remote_progress, "State changes do not extend the remote diffs, uploading base snapshot");
+        }
+    }
+
+    tracing::trace!(target: "sync", block, "Creating state base snapshot");
+    let contents = SnapshotContents::collect(Stage::State, &db.state())?;
+    uploader.upload(Snapshot::new(Stage::State, block), &db.state_path, contents).await
 }
 
-async fn save_single_snapshot(
-    remote: &RemoteStore,
-    prefix: &str,
-    path: &Path,
+/// Upload the new headers as a diff on top of the latest remote base snapshot.
+/// A full base snapshot is uploaded instead if there is none yet, if the diffs would span more
+/// than [HEADERS_BASE_INTERVAL] blocks, or if `rebase` is set.
+async fn upload_headers(
+    uploader: &mut SnapshotUploader,
+    db: &SplitDatabase,
     progress: BlockNumber,
+    rebase: bool,
 ) -> eyre::Result<()> {
-    let snapshot_key = format!("{prefix}{progress}{DAT_GZ_EXT}");
-    remote.save(&snapshot_key, &path.join(MDBX_DAT)).await?;
-
-    // Clean up any previous snapshot entries
-    for entry in remote.list(Some(prefix)).await? {
-        let key = entry.key().unwrap();
-        if !key.ends_with(&snapshot_key) {
-            remote.delete(key).await?;
+    let entries = uploader.remote().list(Some(Stage::Headers.prefix())).await?;
+    let keys = entries.iter().map(|e| e.key.as_str()).collect::<Vec<_>>();
+
+    let base = keys
+        .iter()
+        .filter_map(|key| Snapshot::parse_for(key, Stage::Headers))
+        .map(|s| s.block)
+        .max();
+    let base = match base {
+        Some(base) if !rebase && progress.saturating_sub(base) < HEADERS_BASE_INTERVAL => base,
+        _ => {
+            tracing::trace!(target: "sync", block = progress, "Creating headers base snapshot");
+            let contents = SnapshotContents::collect(Stage::Headers, &db.headers())?;
+            return uploader
+                .upload_single(Stage::Headers, &db.headers_path, progress, contents)
+                .await
         }
+    };
+
+    let remote_progress = headers_diff_chain(keys, base).last().map_or(base, |diff| diff.to);
+    if remote_progress >= progress {
+        return Ok(())
     }
-    Ok(())
+
+    let diff = HeadersDiffKey { from: remote_progress + 1, to: progress };
+    tracing::trace!(target: "sync", %diff, "Creating headers diff");
+    let compressed = write_headers_diff(
+        &db.headers(),
+        diff.from..=diff.to,
+        &uploader.temp_dir(&db.headers_path),
+    )?;
+    uploader.upload_compressed(diff.to_string(), compressed).await
 }
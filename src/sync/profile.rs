@@ -0,0 +1,80 @@
+use reth_primitives::BlockNumber;
+use std::{sync::Mutex, time::Duration};
+
+/// The number of the most expensive blocks listed in the summary.
+const TOP_BLOCKS: usize = 10;
+
+/// The execution cost of a single block.
+#[derive(Debug, Clone, Copy)]
+struct BlockProfile {
+    block: BlockNumber,
+    transactions: u64,
+    gas: u64,
+    execution: Duration,
+}
+
+impl BlockProfile {
+    fn gas_per_sec(&self) -> u64 {
+        (self.gas as f64 / self.execution.as_secs_f64().max(f64::EPSILON)) as u64
+    }
+}
+
+#[derive(Debug, Default)]
+struct ProfileState {
+    blocks: u64,
+    gas: u64,
+    execution: Duration,
+    /// The slowest blocks, sorted by the execution time in descending order.
+    slowest: Vec<BlockProfile>,
+}
+
+/// Records the gas used and the execution time of every executed block, and summarizes them at
+/// the end of the range.
+#[derive(Debug, Default)]
+pub struct EvmProfile {
+    state: Mutex<ProfileState>,
+}
+
+impl EvmProfile {
+    /// Record the execution of a single block.
+    pub fn record(&self, block: BlockNumber, transactions: u64, gas: u64, execution: Duration) {
+        let mut state = self.state.lock().expect("not poisoned");
+        state.blocks += 1;
+        state.gas += gas;
+        state.execution += execution;
+
+        let profile = BlockProfile { block, transactions, gas, execution };
+        let position = state.slowest.partition_point(|p| p.execution >= execution);
+        if position < TOP_BLOCKS {
+            state.slowest.insert(position, profile);
+            state.slowest.truncate(TOP_BLOCKS);
+        }
+    }
+
+    /// Log the totals and the slowest blocks of the recorded range.
+    pub fn log_summary(&self) {
+        let state = self.state.lock().expect("not poisoned");
+        let gas_per_sec =
+            (state.gas as f64 / state.execution.as_secs_f64().max(f64::EPSILON)) as u64;
+        tracing::info!(
+            target: "sync::state",
+            blocks = state.blocks,
+            gas = state.gas,
+            gas_per_block = state.gas / state.blocks.max(1),
+            mgas_per_sec = gas_per_sec / 1_000_000,
+            execution = ?state.execution,
+            "EVM profile"
+        );
+        for profile in &state.slowest {
+            tracing::info!(
+                target: "sync::state",
+                block = profile.block,
+                transactions = profile.transactions,
+                gas = profile.gas,
+                mgas_per_sec = profile.gas_per_sec() / 1_000_000,
+                execution = ?profile.execution,
+                "Slow block"
+            );
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use super::Tip;
+use reth_primitives::{H256, U64};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+
+/// The block tag of the latest finalized block, which can no longer be reorged out.
+pub const FINALIZED: &str = "finalized";
+
+/// Queries the tip of the chain from an Ethereum JSON-RPC endpoint.
+#[derive(Debug, Clone)]
+pub struct RpcTipSource {
+    client: reqwest::Client,
+    url: String,
+}
+
+#[derive(Deserialize)]
+struct BlockResponse {
+    hash: H256,
+    number: U64,
+}
+
+impl RpcTipSource {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self { client: reqwest::Client::new(), url: url.into() }
+    }
+
+    /// Fetch the hash and the number of the block with the tag, such as [FINALIZED].
+    pub async fn tip(&self, tag: &str) -> eyre::Result<Tip> {
+        let block = self
+            .request::<Option<BlockResponse>>("eth_getBlockByNumber", json!([tag, false]))
+            .await?
+            .ok_or_else(|| eyre::eyre!("No {tag} block returned by {}", self.url))?;
+        Ok(Tip::new(block.hash, block.number.to::<u64>()))
+    }
+
+    /// Fetch the id of the chain the endpoint serves.
+    pub async fn chain_id(&self) -> eyre::Result<u64> {
+        Ok(self.request::<U64>("eth_chainId", json!([])).await?.to::<u64>())
+    }
+
+    async fn request<T: DeserializeOwned>(&self, method: &str, params: Value) -> eyre::Result<T> {
+        tracing::trace!(target: "sync::rpc", url = %self.url, method, "Sending request");
+        let request = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+        let mut response = self
+            .client
+            .post(&self.url)
+            .json(&request)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<Value>()
+            .await?;
+        if let Some(error) = response.get("error") {
+            eyre::bail!("{method} request to {} failed: {error}", self.url)
+        }
+        Ok(serde_json::from_value(response["result"].take())?)
+    }
+}
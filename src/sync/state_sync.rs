@@ -1,7 +1,8 @@
-use crate::database::LatestSplitStateProvider;
+use super::{BadBlocks, EvmProfile, ExecutionTrace, Interrupt};
+use crate::database::{calculate_state_root, LatestSplitStateProvider, StateDiff};
 use rayon::prelude::*;
 use reth_db::{
-    cursor::DbCursorRO,
+    cursor::{DbCursorRO, DbCursorRW, DbDupCursorRO},
     database::Database,
     tables,
     transaction::{DbTx, DbTxMut},
@@ -14,13 +15,70 @@ use reth_primitives::{Address, Block, BlockNumber, ChainSpec, Hardfork, StorageE
 use reth_provider::{test_utils::NoopProvider, ProviderError};
 use reth_revm::database::{State, SubState};
 use reth_stages::stages::EXECUTION;
-use std::ops::RangeInclusive;
+use std::{
+    ops::{AddAssign, RangeInclusive},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// The estimated memory held by the execution result of a single transaction, excluding the
+/// storage changes.
+const ESTIMATED_TX_RESULT_SIZE: u64 = 1024;
+
+/// The amount of gas assumed to be spent per storage change when estimating the changeset size.
+const GAS_PER_STORAGE_CHANGE: u64 = 5_000;
+
+/// The estimated memory held by a single storage change.
+const ESTIMATED_STORAGE_CHANGE_SIZE: u64 = 128;
+
+/// The cumulative metrics of the executed state batches.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ExecutionMetrics {
+    /// The number of executed blocks.
+    pub blocks: u64,
+    /// The number of executed transactions.
+    pub transactions: u64,
+    /// The total gas used by the executed blocks.
+    pub gas: u64,
+    /// The time spent reading headers and bodies from the databases.
+    pub body_read: Duration,
+    /// The time spent recovering transaction senders.
+    pub sender_recovery: Duration,
+    /// The time spent executing blocks.
+    pub execution: Duration,
+    /// The time spent writing the state changes.
+    pub apply: Duration,
+    /// The time spent committing the state database transaction.
+    pub commit: Duration,
+}
+
+impl AddAssign for ExecutionMetrics {
+    fn add_assign(&mut self, other: Self) {
+        self.blocks += other.blocks;
+        self.transactions += other.transactions;
+        self.gas += other.gas;
+        self.body_read += other.body_read;
+        self.sender_recovery += other.sender_recovery;
+        self.execution += other.execution;
+        self.apply += other.apply;
+        self.commit += other.commit;
+    }
+}
 
 pub struct StateSync<'a, DB> {
     headers_db: DB,
     bodies_db: DB,
     state_db: DB,
     commit_threshold: u64,
+    memory_budget: Option<u64>,
+    state_clear_override: Option<bool>,
+    interrupt: Interrupt,
+    metrics: ExecutionMetrics,
+    trace: Option<ExecutionTrace>,
+    profile: Option<EvmProfile>,
+    bad_blocks: Option<BadBlocks>,
+    verify_state_root_every: Option<u64>,
+    state_diff: Option<Mutex<StateDiff>>,
     executor: Executor<'a, NoopProvider>,
 }
 
@@ -37,15 +95,98 @@ impl<'a, DB: Database> StateSync<'a, DB> {
             bodies_db,
             state_db,
             commit_threshold,
+            memory_budget: None,
+            state_clear_override: None,
+            interrupt: Interrupt::default(),
+            metrics: ExecutionMetrics::default(),
+            trace: None,
+            profile: None,
+            bad_blocks: None,
+            verify_state_root_every: None,
+            state_diff: None,
             executor: Executor::from(chain_spec),
         }
     }
 
+    /// Override whether the state clear EIP (EIP-161) is applied when writing the state changes,
+    /// regardless of the Spurious Dragon activation in the chain spec.
+    pub fn with_state_clear_override(mut self, state_clear: Option<bool>) -> Self {
+        self.state_clear_override = state_clear;
+        self
+    }
+
+    /// Set the interrupt that stops the sync after the current batch is committed.
+    pub fn with_interrupt(mut self, interrupt: Interrupt) -> Self {
+        self.interrupt = interrupt;
+        self
+    }
+
+    /// Set the memory budget in bytes for the execution results held before the batch is applied.
+    /// A warning is logged for the batches that are estimated to exceed it.
+    pub fn with_memory_budget(mut self, memory_budget: u64) -> Self {
+        self.memory_budget = Some(memory_budget);
+        self
+    }
+
+    /// Write the state changes of every executed transaction to the trace.
+    pub fn with_execution_trace(mut self, trace: ExecutionTrace) -> Self {
+        self.trace = Some(trace);
+        self
+    }
+
+    /// Record the gas used and the execution time of every block. The summary of all blocks
+    /// profiled so far is logged at the end of each run.
+    pub fn with_evm_profile(mut self) -> Self {
+        self.profile = Some(EvmProfile::default());
+        self
+    }
+
+    /// Skip the blocks that fail to execute instead of returning the error, and record them.
+    /// The state changes of the skipped blocks are missing, so the state diverges from the chain.
+    pub fn with_skip_bad_blocks(mut self, bad_blocks: BadBlocks) -> Self {
+        self.bad_blocks = Some(bad_blocks);
+        self
+    }
+
+    /// Returns `true` if the blocks that fail to execute are skipped.
+    pub fn skips_bad_blocks(&self) -> bool {
+        self.bad_blocks.is_some()
+    }
+
+    /// Accumulate the applied state changes, so that they can be published as a diff between the
+    /// state snapshots. See [Self::take_state_diff].
+    pub fn with_state_diffs(mut self) -> Self {
+        self.state_diff = Some(Mutex::default());
+        self
+    }
+
+    /// Returns the state changes accumulated since the previous call, or since the sync started.
+    /// Returns [None] unless the state diffs are enabled.
+    pub fn take_state_diff(&self) -> Option<StateDiff> {
+        let diff = self.state_diff.as_ref()?;
+        Some(std::mem::take(&mut *diff.lock().expect("not poisoned")))
+    }
+
+    /// Check the state root against the header at the end of every batch that reaches a multiple
+    /// of `interval` blocks. The sync halts on the first mismatch, without committing the batch.
+    pub fn with_state_root_verification(mut self, interval: u64) -> Self {
+        self.verify_state_root_every = Some(interval.max(1));
+        self
+    }
+
+    /// Returns the total difficulty up to and including the block.
+    ///
+    /// The total difficulty at the execution progress is persisted along with the state, so that
+    /// it is not recomputed from genesis when the sync is resumed.
     pub fn get_td(&self, block: BlockNumber) -> eyre::Result<U256> {
         if block == 0 {
             return Ok(self.executor.chain_spec.genesis.difficulty)
         }
 
+        if let Some(td) = self.state_db.tx()?.get::<tables::HeaderTD>(block)? {
+            return Ok(td.into())
+        }
+
         let mut td = U256::ZERO;
         let tx = self.headers_db.tx()?;
         for entry in tx.cursor_read::<tables::Headers>()?.walk_range(..=block)? {
@@ -55,35 +196,64 @@ impl<'a, DB: Database> StateSync<'a, DB> {
         Ok(td)
     }
 
+    /// Returns the metrics of all batches executed so far.
+    pub fn metrics(&self) -> &ExecutionMetrics {
+        &self.metrics
+    }
+
     pub fn get_progress(&self) -> eyre::Result<BlockNumber> {
         Ok(EXECUTION.get_progress(&self.state_db.tx()?)?.unwrap_or_default())
     }
 
+    fn is_shanghai_active(&self, timestamp: u64) -> bool {
+        self.executor.chain_spec.fork(Hardfork::Shanghai).active_at_timestamp(timestamp)
+    }
+
     pub async fn run(&mut self, range: RangeInclusive<BlockNumber>) -> eyre::Result<()> {
         tracing::trace!(target: "sync::state", ?range, "Commencing state sync");
 
-        let mut td = self.get_td(*range.start())?; // TODO:
+        // The difficulty of each executed block is added to the total difficulty of its parent.
+        let mut progress = self.get_progress()?;
+        let mut td = self.get_td(progress)?;
         tracing::trace!(target: "sync::state", td = td.to_string(), "Total difficulty calculated");
 
-        let mut progress = self.get_progress()?;
         while progress < *range.end() {
+            if self.interrupt.is_triggered() {
+                tracing::info!(target: "sync::state", progress, "State sync interrupted");
+                break
+            }
+
             let start = progress + 1;
             let range = start..=range.end().clone().min(start + self.commit_threshold);
-            std::thread::scope(|scope| {
+            let metrics = std::thread::scope(|scope| {
                 let handle = std::thread::Builder::new()
                     .stack_size(50 * 1024 * 1024)
                     .spawn_scoped(scope, || self.execute_inner(range, &mut td))
                     .expect("Expects that thread name is not null");
                 handle.join().expect("Expects for thread to not panic")
             })?;
+            self.metrics += metrics;
             progress = self.get_progress()?;
         }
 
+        if let Some(profile) = &self.profile {
+            profile.log_summary();
+        }
+        if let Some(bad_blocks) = &self.bad_blocks {
+            bad_blocks.log_summary();
+        }
         Ok(())
     }
 
-    fn execute_inner(&self, range: RangeInclusive<BlockNumber>, td: &mut U256) -> eyre::Result<()> {
-        let headers_tx = self.headers_db.tx_mut()?;
+    fn execute_inner(
+        &self,
+        range: RangeInclusive<BlockNumber>,
+        td: &mut U256,
+    ) -> eyre::Result<ExecutionMetrics> {
+        let mut metrics = ExecutionMetrics::default();
+        let started_at = Instant::now();
+
+        let headers_tx = self.headers_db.tx()?;
         let bodies_tx = self.bodies_db.tx()?;
         let tx = self.state_db.tx_mut()?;
 
@@ -102,66 +272,153 @@ impl<'a, DB: Database> StateSync<'a, DB> {
                 let (_, body) =
                     bodies_cursor.seek_exact(number)?.ok_or(ProviderError::BlockBody { number })?;
                 let (_, stored_ommers) = ommers_cursor.seek_exact(number)?.unwrap_or_default();
-                let withdrawals =
-                    withdrawals_cursor.seek_exact(number)?.map(|(_, w)| w.withdrawals);
-                Ok((header, td.clone(), body, stored_ommers.ommers, withdrawals))
+                // Empty withdrawals might be missing from the bodies stored before they were
+                // distinguished from the absent ones.
+                let withdrawals = withdrawals_cursor
+                    .seek_exact(number)?
+                    .map(|(_, w)| w.withdrawals)
+                    .or_else(|| self.is_shanghai_active(header.timestamp).then(Vec::new));
+
+                let mut tx_walker = tx_cursor.walk(Some(body.start_tx_id))?;
+                let mut transactions = Vec::with_capacity(body.tx_count as usize);
+                // get next N transactions.
+                for index in body.tx_id_range() {
+                    let (tx_index, tx) =
+                        tx_walker.next().ok_or(ProviderError::EndOfTransactionTable)??;
+                    if tx_index != index {
+                        tracing::error!(target: "sync::stages::execution", block = header.number, expected = index, found = tx_index, ?body, "Transaction gap");
+                        return Err(ProviderError::TransactionsGap { missing: tx_index }.into())
+                    }
+                    transactions.push(tx);
+                }
+                Ok((header, td.clone(), body, transactions, stored_ommers.ommers, withdrawals))
             })
             .collect::<Result<Vec<_>, _>>()?;
+        metrics.body_read += started_at.elapsed();
 
+        // Recover the senders of the whole batch in one pass, since the small blocks alone do not
+        // keep all the cores busy.
+        let started_at = Instant::now();
+        let senders = block_batch
+            .iter()
+            .flat_map(|(_, _, _, transactions, ..)| transactions)
+            .collect::<Vec<_>>()
+            .into_par_iter()
+            .map(|transaction| {
+                transaction
+                    .recover_signer()
+                    .ok_or(eyre::eyre!("failed to recover sender for tx {}", transaction.hash))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut senders = senders.into_iter();
+        metrics.sender_recovery += started_at.elapsed();
+
+        if let Some(budget) = self.memory_budget {
+            let estimate = block_batch
+                .iter()
+                .map(|(header, _, body, ..)| {
+                    body.tx_count * ESTIMATED_TX_RESULT_SIZE +
+                        header.gas_used / GAS_PER_STORAGE_CHANGE * ESTIMATED_STORAGE_CHANGE_SIZE
+                })
+                .sum::<u64>();
+            if estimate > budget {
+                let suggested_threshold =
+                    (block_batch.len() as u64 * budget / estimate).saturating_sub(1).max(1);
+                tracing::warn!(
+                    target: "sync::state",
+                    ?range,
+                    estimate_mb = estimate / 1024 / 1024,
+                    budget_mb = budget / 1024 / 1024,
+                    suggested_threshold,
+                    "Estimated batch memory exceeds the budget, consider lowering the commit threshold"
+                );
+            }
+        }
+
+        let expected_state_root = block_batch.last().map(|(header, ..)| header.state_root);
+        // The progress includes the skipped bad blocks, so that they are not executed again.
+        let last_block = block_batch.last().map(|(header, ..)| header.number);
         let mut state_provider =
             SubState::new(State::new(LatestSplitStateProvider::new(&headers_tx, &tx)));
         let mut changesets = Vec::with_capacity(block_batch.len());
-        for (header, td, body, ommers, withdrawals) in block_batch {
+        for (header, td, body, transactions, ommers, withdrawals) in block_batch {
             let block_number = header.number;
+            let (tx_count, gas_used) = (body.tx_count, header.gas_used);
+            metrics.blocks += 1;
+            metrics.transactions += body.tx_count;
+            metrics.gas += header.gas_used;
 
-            let mut tx_walker = tx_cursor.walk(Some(body.start_tx_id))?;
-            let mut transactions = Vec::with_capacity(body.tx_count as usize);
-            // get next N transactions.
-            for index in body.tx_id_range() {
-                let (tx_index, tx) =
-                    tx_walker.next().ok_or(ProviderError::EndOfTransactionTable)??;
-                if tx_index != index {
-                    tracing::error!(target: "sync::stages::execution", block = header.number, expected = index, found = tx_index, ?body, "Transaction gap");
-                    return Err(ProviderError::TransactionsGap { missing: tx_index }.into())
-                }
-                transactions.push(tx);
+            // The senders were recovered in the order of the blocks and their transactions.
+            let block_senders = senders.by_ref().take(transactions.len()).collect::<Vec<_>>();
+            if block_senders.len() != transactions.len() {
+                eyre::bail!(
+                    "Recovered {} senders for {} transactions at block #{block_number}",
+                    block_senders.len(),
+                    transactions.len(),
+                )
             }
 
-            let senders = transactions
-                .par_iter()
-                .map(|transaction| {
-                    transaction
-                        .recover_signer()
-                        .ok_or(eyre::eyre!("failed to recover sender for tx {}", transaction.hash))
-                })
-                .collect::<Result<Vec<_>, _>>()?;
-
+            let started_at = Instant::now();
             let mut executor = self.executor.with_db(&mut state_provider);
-            let changeset = executor
-                .execute_and_verify_receipt(
-                    &Block { header, body: transactions, ommers, withdrawals },
-                    td,
-                    Some(senders),
-                )
-                .map_err(|error| {
-                    eyre::eyre!("Execution error at block #{block_number}: {error:?}")
-                })?;
+            let changeset = match executor.execute_and_verify_receipt(
+                &Block { header, body: transactions, ommers, withdrawals },
+                td,
+                Some(block_senders),
+            ) {
+                Ok(changeset) => changeset,
+                Err(error) => match &self.bad_blocks {
+                    Some(bad_blocks) => {
+                        let hash = headers_tx.get::<tables::CanonicalHeaders>(block_number)?;
+                        tracing::error!(target: "sync::state", block = block_number, ?hash, ?error, "Execution error, skipping the block");
+                        bad_blocks.record(block_number, hash, &format!("{error:?}"))?;
+                        continue
+                    }
+                    None => eyre::bail!("Execution error at block #{block_number}: {error:?}"),
+                },
+            };
+            let elapsed = started_at.elapsed();
+            metrics.execution += elapsed;
+            if let Some(profile) = &self.profile {
+                profile.record(block_number, tx_count, gas_used, elapsed);
+            }
             changesets.push((block_number, changeset));
         }
         tracing::trace!(target: "sync::state", ?range, "Executed blocks");
 
         // apply changes to plain database.
-        let mut latest = None;
+        let started_at = Instant::now();
+        let mut state_diff =
+            self.state_diff.as_ref().map(|diff| diff.lock().expect("not poisoned"));
         for (block_number, result) in changesets.into_iter() {
-            latest = Some(block_number);
-            self.apply_state_changes(&tx, block_number, result)?;
+            self.apply_state_changes(&tx, block_number, result, state_diff.as_deref_mut())?;
+        }
+        drop(state_diff);
+        metrics.apply += started_at.elapsed();
+        if let Some(trace) = &self.trace {
+            trace.flush()?;
         }
 
-        let latest = latest.unwrap();
+        let latest = last_block.unwrap();
+        if let Some(interval) = self.verify_state_root_every {
+            if latest / interval > (*range.start() - 1) / interval {
+                let state_root = calculate_state_root(&tx)?;
+                if Some(state_root) != expected_state_root {
+                    eyre::bail!(
+                        "State root mismatch at block #{latest}: expected {:?}, got {state_root:?}",
+                        expected_state_root,
+                    )
+                }
+                tracing::info!(target: "sync::state", block = latest, ?state_root, "State root verified");
+            }
+        }
         EXECUTION.save_progress(&tx, latest)?;
+        tx.clear::<tables::HeaderTD>()?;
+        tx.put::<tables::HeaderTD>(latest, (*td).into())?;
+        let started_at = Instant::now();
         tx.commit()?;
+        metrics.commit += started_at.elapsed();
         tracing::trace!(target: "sync::state", progress = latest, "Plain state updated");
-        Ok(())
+        Ok(metrics)
     }
 
     fn apply_state_changes<'tx, Tx: DbTxMut<'tx>>(
@@ -169,14 +426,19 @@ impl<'a, DB: Database> StateSync<'a, DB> {
         tx: &Tx,
         block: BlockNumber,
         result: ExecutionResult,
+        mut diff: Option<&mut StateDiff>,
     ) -> eyre::Result<()> {
-        let spurious_dragon_active =
-            self.executor.chain_spec.fork(Hardfork::SpuriousDragon).active_at_block(block);
+        if let Some(diff) = diff.as_deref_mut() {
+            diff.record_block(block);
+        }
+        let state_clear_active = self.state_clear_override.unwrap_or_else(|| {
+            self.executor.chain_spec.fork(Hardfork::SpuriousDragon).active_at_block(block)
+        });
 
-        for result in result.tx_changesets.into_iter() {
+        let mut storage_cursor = tx.cursor_dup_write::<tables::PlainStorageState>()?;
+        for (tx_index, result) in result.tx_changesets.into_iter().enumerate() {
             for (address, account_change_set) in result.changeset.into_iter() {
                 let AccountChangeSet { account, wipe_storage, storage } = account_change_set;
-                self.apply_account_changeset(tx, account, address, spurious_dragon_active)?;
 
                 let storage = storage
                     .into_iter()
@@ -185,41 +447,76 @@ impl<'a, DB: Database> StateSync<'a, DB> {
                     })
                     .collect::<Vec<_>>();
 
+                if let Some(trace) = &self.trace {
+                    trace.record(
+                        block,
+                        Some(tx_index),
+                        address,
+                        &account,
+                        wipe_storage,
+                        &storage,
+                    )?;
+                }
+                self.apply_account_changeset(
+                    tx,
+                    account,
+                    address,
+                    state_clear_active,
+                    diff.as_deref_mut(),
+                )?;
+
+                // The storage is a dup-sorted table, where a put adds another entry for the slot
+                // next to the existing one instead of replacing it. The changesets of the
+                // transactions are applied in order, so a wipe of the destroyed account is
+                // followed by the writes of the recreated one.
                 if wipe_storage {
+                    // Deleting without a value removes all the entries of the account.
                     tx.delete::<tables::PlainStorageState>(address, None)?;
-
-                    for (key, _, new_value) in storage {
-                        if new_value != U256::ZERO {
-                            tx.put::<tables::PlainStorageState>(
-                                address,
-                                StorageEntry { key, value: new_value },
-                            )?;
-                        }
+                    if let Some(diff) = diff.as_deref_mut() {
+                        diff.wipe_storage(address);
+                    }
+                }
+                for (key, _, new_value) in storage {
+                    if let Some(diff) = diff.as_deref_mut() {
+                        diff.set_storage(address, key, new_value);
                     }
-                } else {
-                    for (key, old_value, new_value) in storage {
-                        tx.delete::<tables::PlainStorageState>(
-                            address,
-                            Some(StorageEntry { key, value: old_value }),
-                        )?;
-                        if new_value != U256::ZERO {
-                            tx.put::<tables::PlainStorageState>(
-                                address,
-                                StorageEntry { key, value: new_value },
-                            )?;
-                        }
+                    // The stored entry is looked up by the slot rather than deleted by the old
+                    // value of the changeset, which is relative to the executor cache and might
+                    // not match the entry in the database.
+                    if storage_cursor
+                        .seek_by_key_subkey(address, key)?
+                        .filter(|entry| entry.key == key)
+                        .is_some()
+                    {
+                        storage_cursor.delete_current()?;
+                    }
+                    if new_value != U256::ZERO {
+                        storage_cursor.upsert(address, StorageEntry { key, value: new_value })?;
                     }
                 }
             }
 
             for (hash, bytecode) in result.new_bytecodes.into_iter() {
                 let bytecode = bytecode.bytes();
-                tx.put::<tables::Bytecodes>(hash, bytecode[..bytecode.len()].to_vec())?;
+                let code = bytecode[..bytecode.len()].to_vec();
+                if let Some(diff) = diff.as_deref_mut() {
+                    diff.set_bytecode(hash, code.clone());
+                }
+                tx.put::<tables::Bytecodes>(hash, code)?;
             }
         }
 
         for (address, changeset) in result.block_changesets.into_iter() {
-            self.apply_account_changeset(tx, changeset, address, spurious_dragon_active)?;
+            if let Some(trace) = &self.trace {
+                trace.record(block, None, address, &changeset, false, &[])?;
+            }
+            self.apply_account_changeset(
+                tx,
+                changeset,
+                address,
+                state_clear_active,
+                diff.as_deref_mut(),
+            )?;
         }
         Ok(())
     }
@@ -231,22 +528,433 @@ impl<'a, DB: Database> StateSync<'a, DB> {
         changeset: AccountInfoChangeSet,
         address: Address,
         has_state_clear_eip: bool,
+        diff: Option<&mut StateDiff>,
     ) -> eyre::Result<()> {
-        match changeset {
+        let written = match changeset {
             AccountInfoChangeSet::Changed { new, .. } => {
                 tx.put::<tables::PlainAccountState>(address, new)?;
+                Some(Some(new))
             }
             AccountInfoChangeSet::Created { new } => {
                 if has_state_clear_eip && new.is_empty() {
                     return Ok(())
                 }
                 tx.put::<tables::PlainAccountState>(address, new)?;
+                Some(Some(new))
             }
             AccountInfoChangeSet::Destroyed { .. } => {
                 tx.delete::<tables::PlainAccountState>(address, None)?;
+                Some(None)
             }
-            AccountInfoChangeSet::NoChange => {}
+            AccountInfoChangeSet::NoChange => None,
+        };
+        if let Some((diff, account)) = diff.zip(written) {
+            diff.set_account(address, account);
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::{
+            test_utils::{create_test_db, open_test_db, test_chain_spec},
+            Stage,
+        },
+        sync::test_utils::{
+            insert_blocks, insert_empty_blocks, test_beneficiary, test_genesis, test_headers,
+        },
+    };
+    use reth_db::mdbx::{Env, WriteMap};
+    use reth_executor::execution_result::TransactionChangeSet;
+    use reth_primitives::{
+        keccak256, sign_message, Account, Bytes, Header, Receipt, Transaction, TransactionKind,
+        TransactionSigned, TxLegacy, TxType,
+    };
+    use std::{collections::BTreeMap, sync::Arc};
+    use tempfile::TempDir;
+
+    /// The block reward before Byzantium.
+    const FRONTIER_BLOCK_REWARD: u128 = 5_000_000_000_000_000_000;
+
+    /// The gas used by a plain value transfer.
+    const TRANSFER_GAS: u64 = 21_000;
+
+    /// Sign the transfer of the value to the recipient with the secret key.
+    fn transfer(secret: u64, nonce: u64, to: Address, value: u128) -> TransactionSigned {
+        call(secret, nonce, to, value, TRANSFER_GAS)
+    }
+
+    /// Sign the call of the account with the secret key, paying a gas price of one.
+    fn call(
+        secret: u64,
+        nonce: u64,
+        to: Address,
+        value: u128,
+        gas_limit: u64,
+    ) -> TransactionSigned {
+        let transaction = Transaction::Legacy(TxLegacy {
+            chain_id: None,
+            nonce,
+            gas_price: 1,
+            gas_limit,
+            to: TransactionKind::Call(to),
+            value,
+            input: Bytes::default(),
+        });
+        let signature =
+            sign_message(H256::from_low_u64_be(secret), transaction.signature_hash()).unwrap();
+        TransactionSigned::from_transaction_and_signature(transaction, signature)
+    }
+
+    /// Create the state sync over empty stage databases in temporary directories, which are
+    /// removed once the returned handles are dropped.
+    fn create_test_sync() -> ([TempDir; 3], StateSync<'static, Arc<Env<WriteMap>>>) {
+        let (headers_dir, headers_db) = create_test_db(Stage::Headers);
+        let (bodies_dir, bodies_db) = create_test_db(Stage::Bodies);
+        let (state_dir, state_db) = create_test_db(Stage::State);
+        let sync = StateSync::new(headers_db, bodies_db, state_db, 10, test_chain_spec());
+        ([headers_dir, bodies_dir, state_dir], sync)
+    }
+
+    /// The changes of a successful transaction.
+    fn tx_changeset(
+        changeset: impl IntoIterator<Item = (Address, AccountChangeSet)>,
+    ) -> TransactionChangeSet {
+        TransactionChangeSet {
+            receipt: Receipt {
+                tx_type: TxType::Legacy,
+                success: true,
+                cumulative_gas_used: 0,
+                logs: Vec::new(),
+            },
+            changeset: changeset.into_iter().collect(),
+            new_bytecodes: BTreeMap::new(),
+        }
+    }
+
+    /// Apply the execution result of the block and commit it.
+    fn apply(
+        sync: &StateSync<'_, Arc<Env<WriteMap>>>,
+        block: BlockNumber,
+        result: ExecutionResult,
+    ) {
+        let tx = sync.state_db.tx_mut().unwrap();
+        sync.apply_state_changes(&tx, block, result, None).unwrap();
+        tx.commit().unwrap();
+    }
+
+    /// Returns all the stored storage entries of the account, including the duplicate ones.
+    fn storage_entries(
+        sync: &StateSync<'_, Arc<Env<WriteMap>>>,
+        address: Address,
+    ) -> Vec<StorageEntry> {
+        let tx = sync.state_db.tx().unwrap();
+        let mut cursor = tx.cursor_dup_read::<tables::PlainStorageState>().unwrap();
+        let mut entries = Vec::new();
+        let mut entry = cursor.seek_exact(address).unwrap().map(|(_, entry)| entry);
+        while let Some(next) = entry {
+            entries.push(next);
+            entry = cursor.next_dup_val().unwrap();
+        }
+        entries
+    }
+
+    #[tokio::test]
+    async fn run_executes_blocks() {
+        let (_headers_dir, headers_db) = create_test_db(Stage::Headers);
+        let (_bodies_dir, bodies_db) = create_test_db(Stage::Bodies);
+        let (_state_dir, state_db) = create_test_db(Stage::State);
+        let headers = test_headers(&test_genesis(), 5);
+        insert_empty_blocks(&headers_db, &bodies_db, &headers).unwrap();
+
+        // The blocks are executed in several batches.
+        let mut sync = StateSync::new(headers_db, bodies_db, state_db, 2, test_chain_spec());
+        sync.run(1..=5).await.unwrap();
+
+        assert_eq!(sync.get_progress().unwrap(), 5);
+        assert_eq!(sync.metrics().blocks, 5);
+        assert_eq!(sync.get_td(5).unwrap(), test_chain_spec().genesis.difficulty + U256::from(5));
+
+        let beneficiary = sync
+            .state_db
+            .tx()
+            .unwrap()
+            .get::<tables::PlainAccountState>(test_beneficiary())
+            .unwrap()
+            .expect("beneficiary is rewarded");
+        assert_eq!(beneficiary.balance, U256::from(5 * FRONTIER_BLOCK_REWARD));
+    }
+
+    #[tokio::test]
+    async fn run_resumes_from_progress() {
+        let (_headers_dir, headers_db) = create_test_db(Stage::Headers);
+        let (_bodies_dir, bodies_db) = create_test_db(Stage::Bodies);
+        let (_state_dir, state_db) = create_test_db(Stage::State);
+        let headers = test_headers(&test_genesis(), 4);
+        insert_empty_blocks(&headers_db, &bodies_db, &headers).unwrap();
+
+        let mut sync = StateSync::new(headers_db, bodies_db, state_db, 10, test_chain_spec());
+        sync.run(1..=2).await.unwrap();
+        assert_eq!(sync.get_progress().unwrap(), 2);
+
+        // The total difficulty is taken from the state, and the executed blocks are not repeated.
+        sync.run(1..=4).await.unwrap();
+        assert_eq!(sync.get_progress().unwrap(), 4);
+        assert_eq!(sync.metrics().blocks, 4);
+        assert_eq!(sync.get_td(4).unwrap(), test_chain_spec().genesis.difficulty + U256::from(4));
+    }
+
+    #[tokio::test]
+    async fn run_resumes_td_from_reopened_state() {
+        let (_headers_dir, headers_db) = create_test_db(Stage::Headers);
+        let (_bodies_dir, bodies_db) = create_test_db(Stage::Bodies);
+        let (state_dir, state_db) = create_test_db(Stage::State);
+        let headers = test_headers(&test_genesis(), 4);
+        insert_empty_blocks(&headers_db, &bodies_db, &headers).unwrap();
+        let genesis_td = test_chain_spec().genesis.difficulty;
+
+        let mut sync = StateSync::new(
+            Arc::clone(&headers_db),
+            Arc::clone(&bodies_db),
+            state_db,
+            10,
+            test_chain_spec(),
+        );
+        sync.run(1..=2).await.unwrap();
+        drop(sync);
+
+        // The total difficulty at the progress is stored along with the state.
+        let state_db = open_test_db(Stage::State, state_dir.path());
+        let stored = state_db.tx().unwrap().get::<tables::HeaderTD>(2).unwrap();
+        assert_eq!(stored.map(U256::from), Some(genesis_td + U256::from(2)));
+
+        // A header below the progress is altered, which would change the total difficulty if it
+        // was recomputed from the headers.
+        headers_db
+            .update(|tx| {
+                let header = Header { difficulty: U256::from(100), ..headers[0].clone().unseal() };
+                tx.put::<tables::Headers>(1, header)
+            })
+            .unwrap()
+            .unwrap();
+
+        let mut sync = StateSync::new(headers_db, bodies_db, state_db, 10, test_chain_spec());
+        sync.run(1..=4).await.unwrap();
+        assert_eq!(sync.metrics().blocks, 2);
+        assert_eq!(sync.get_td(4).unwrap(), genesis_td + U256::from(4));
+    }
+
+    #[test]
+    fn apply_state_changes_recreates_destroyed_storage() {
+        let (_dirs, sync) = create_test_sync();
+        let address = Address::from_low_u64_be(0x10);
+        let account = Account { nonce: 1, balance: U256::from(1), bytecode_hash: None };
+        let slot = |n: u64| U256::from(n);
+        sync.state_db
+            .update(|tx| {
+                tx.put::<tables::PlainAccountState>(address, account)?;
+                for n in 1..=2 {
+                    let entry = StorageEntry { key: H256::from_low_u64_be(n), value: slot(n * 10) };
+                    tx.put::<tables::PlainStorageState>(address, entry)?;
+                }
+                Ok::<_, reth_db::Error>(())
+            })
+            .unwrap()
+            .unwrap();
+
+        let recreated = Account { nonce: 1, balance: U256::ZERO, bytecode_hash: None };
+        let changed = Account { nonce: 2, ..recreated };
+        let result = ExecutionResult {
+            tx_changesets: vec![
+                // The account self-destructs, wiping its storage.
+                tx_changeset([(
+                    address,
+                    AccountChangeSet {
+                        account: AccountInfoChangeSet::Destroyed { old: account },
+                        storage: BTreeMap::new(),
+                        wipe_storage: true,
+                    },
+                )]),
+                // The account is recreated at the same address with new storage.
+                tx_changeset([(
+                    address,
+                    AccountChangeSet {
+                        account: AccountInfoChangeSet::Created { new: recreated },
+                        storage: BTreeMap::from([
+                            (slot(1), (U256::ZERO, slot(11))),
+                            (slot(3), (U256::ZERO, slot(33))),
+                        ]),
+                        wipe_storage: true,
+                    },
+                )]),
+                // A later transaction of the same batch changes the recreated storage.
+                tx_changeset([(
+                    address,
+                    AccountChangeSet {
+                        account: AccountInfoChangeSet::Changed { old: recreated, new: changed },
+                        storage: BTreeMap::from([
+                            (slot(1), (slot(11), slot(12))),
+                            (slot(3), (slot(33), U256::ZERO)),
+                        ]),
+                        wipe_storage: false,
+                    },
+                )]),
+            ],
+            block_changesets: BTreeMap::new(),
+        };
+        apply(&sync, 1, result);
+
+        // Only the slot written last remains, without the entries of the destroyed account or
+        // any duplicates of the rewritten slot.
+        assert_eq!(
+            storage_entries(&sync, address),
+            vec![StorageEntry { key: H256::from_low_u64_be(1), value: slot(12) }]
+        );
+        let tx = sync.state_db.tx().unwrap();
+        assert_eq!(tx.get::<tables::PlainAccountState>(address).unwrap(), Some(changed));
+    }
+
+    #[test]
+    fn apply_state_changes_overrides_state_clear() {
+        let address = Address::from_low_u64_be(0x10);
+        // The state clear is active from Spurious Dragon, at block #2,675,000 on mainnet.
+        for (state_clear, block, stored) in [
+            (None, 1, true),
+            (None, 3_000_000, false),
+            (Some(true), 1, false),
+            (Some(false), 3_000_000, true),
+        ] {
+            let (_dirs, sync) = create_test_sync();
+            let sync = sync.with_state_clear_override(state_clear);
+            let result = ExecutionResult {
+                tx_changesets: Vec::new(),
+                block_changesets: BTreeMap::from([(
+                    address,
+                    AccountInfoChangeSet::Created { new: Account::default() },
+                )]),
+            };
+            apply(&sync, block, result);
+
+            let account = sync.state_db.tx().unwrap().get::<tables::PlainAccountState>(address);
+            assert_eq!(
+                account.unwrap().is_some(),
+                stored,
+                "empty account at block #{block} with the override {state_clear:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn run_recovers_senders_of_batch() {
+        let (_headers_dir, headers_db) = create_test_db(Stage::Headers);
+        let (_bodies_dir, bodies_db) = create_test_db(Stage::Bodies);
+        let (_state_dir, state_db) = create_test_db(Stage::State);
+        let recipient = Address::from_low_u64_be(0x10);
+
+        // The second block holds the transactions of both senders, so that a sender mapped to the
+        // wrong block or transaction fails the nonce check.
+        let transactions = vec![
+            vec![transfer(1, 0, recipient, 1)],
+            vec![transfer(2, 0, recipient, 2), transfer(1, 1, recipient, 3)],
+        ];
+        let (first, second) = (
+            transactions[0][0].recover_signer().unwrap(),
+            transactions[1][0].recover_signer().unwrap(),
+        );
+        assert_ne!(first, second);
+
+        let mut parent = test_genesis();
+        let blocks = transactions
+            .into_iter()
+            .map(|transactions| {
+                // The genesis gas limit does not fit more than a single transfer.
+                let header = Header {
+                    gas_limit: 1_000_000,
+                    gas_used: TRANSFER_GAS * transactions.len() as u64,
+                    ..test_headers(&parent, 1).remove(0).unseal()
+                }
+                .seal_slow();
+                parent = header.clone();
+                (header, transactions)
+            })
+            .collect::<Vec<_>>();
+        insert_blocks(&headers_db, &bodies_db, &blocks).unwrap();
+
+        let funds = U256::from(1_000_000);
+        state_db
+            .update(|tx| {
+                for sender in [first, second] {
+                    let account = Account { nonce: 0, balance: funds, bytecode_hash: None };
+                    tx.put::<tables::PlainAccountState>(sender, account)?;
+                }
+                Ok::<_, reth_db::Error>(())
+            })
+            .unwrap()
+            .unwrap();
+
+        // Both blocks are executed in a single batch.
+        let mut sync = StateSync::new(headers_db, bodies_db, state_db, 10, test_chain_spec());
+        sync.run(1..=2).await.unwrap();
+        assert_eq!(sync.metrics().transactions, 3);
+
+        let tx = sync.state_db.tx().unwrap();
+        let account = |address| tx.get::<tables::PlainAccountState>(address).unwrap().unwrap();
+        let fee = U256::from(TRANSFER_GAS);
+        assert_eq!(account(first).nonce, 2);
+        assert_eq!(account(first).balance, funds - fee - U256::from(1) - fee - U256::from(3));
+        assert_eq!(account(second).nonce, 1);
+        assert_eq!(account(second).balance, funds - fee - U256::from(2));
+        assert_eq!(account(recipient).balance, U256::from(6));
+    }
+
+    #[tokio::test]
+    async fn run_reads_block_hashes_from_headers() {
+        let (_headers_dir, headers_db) = create_test_db(Stage::Headers);
+        let (_bodies_dir, bodies_db) = create_test_db(Stage::Bodies);
+        let (_state_dir, state_db) = create_test_db(Stage::State);
+
+        // PUSH1 1, BLOCKHASH, PUSH1 0, SSTORE, STOP
+        let code = vec![0x60, 0x01, 0x40, 0x60, 0x00, 0x55, 0x00];
+        // The intrinsic gas, two pushes, the block hash and the store of a non-zero value.
+        let call_gas = TRANSFER_GAS + 3 + 20 + 3 + 20_000;
+        let contract = Address::from_low_u64_be(0x10);
+        let transaction = call(1, 0, contract, 0, 100_000);
+        let sender = transaction.recover_signer().unwrap();
+
+        let first = test_headers(&test_genesis(), 1).remove(0);
+        let second = Header {
+            gas_limit: 1_000_000,
+            gas_used: call_gas,
+            ..test_headers(&first, 1).remove(0).unseal()
+        }
+        .seal_slow();
+        let blocks = vec![(first.clone(), Vec::new()), (second, vec![transaction])];
+        insert_blocks(&headers_db, &bodies_db, &blocks).unwrap();
+
+        state_db
+            .update(|tx| {
+                let code_hash = keccak256(&code);
+                let account =
+                    Account { nonce: 0, balance: U256::ZERO, bytecode_hash: Some(code_hash) };
+                tx.put::<tables::PlainAccountState>(contract, account)?;
+                tx.put::<tables::Bytecodes>(code_hash, code)?;
+                let account =
+                    Account { nonce: 0, balance: U256::from(1_000_000), bytecode_hash: None };
+                tx.put::<tables::PlainAccountState>(sender, account)
+            })
+            .unwrap()
+            .unwrap();
+
+        let mut sync = StateSync::new(headers_db, bodies_db, state_db, 10, test_chain_spec());
+        sync.run(1..=2).await.unwrap();
+
+        // The hash of the parent is read from the canonical headers.
+        assert_eq!(
+            storage_entries(&sync, contract),
+            vec![StorageEntry { key: H256::zero(), value: U256::from_be_bytes(first.hash().0) }]
+        );
+    }
+}
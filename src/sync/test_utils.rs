@@ -0,0 +1,178 @@
+use crate::database::test_utils::test_chain_spec;
+use futures::Stream;
+use reth_db::{database::Database, models::StoredBlockBody, tables, transaction::DbTxMut};
+use reth_interfaces::p2p::{
+    bodies::{downloader::BodyDownloader, response::BlockResponse},
+    error::DownloadResult,
+    headers::downloader::{HeaderDownloader, SyncTarget},
+};
+use reth_primitives::{
+    Address, BlockNumber, Header, SealedHeader, TransactionSigned, EMPTY_OMMER_ROOT, EMPTY_ROOT,
+    U256,
+};
+use reth_stages::stages::{BODIES, HEADERS};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    ops::Range,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// The beneficiary of the test blocks.
+pub(crate) fn test_beneficiary() -> Address {
+    Address::repeat_byte(0xbe)
+}
+
+/// The genesis header of the test chain.
+pub(crate) fn test_genesis() -> SealedHeader {
+    test_chain_spec().genesis_header().seal_slow()
+}
+
+/// Build `count` headers of empty blocks on top of the parent, ordered from the lowest.
+///
+/// The blocks are mined by [test_beneficiary] with the lowest difficulty and hold no
+/// transactions, so they pass the validation and execute without any prior state.
+pub(crate) fn test_headers(parent: &SealedHeader, count: u64) -> Vec<SealedHeader> {
+    build_headers(parent, count, 12)
+}
+
+/// Build `count` headers of empty blocks on top of the parent like [test_headers], but a second
+/// later each, so that they compete with the ones built by [test_headers] from the same parent.
+pub(crate) fn test_fork_headers(parent: &SealedHeader, count: u64) -> Vec<SealedHeader> {
+    build_headers(parent, count, 13)
+}
+
+fn build_headers(parent: &SealedHeader, count: u64, block_time: u64) -> Vec<SealedHeader> {
+    let mut parent = parent.clone();
+    let mut headers = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let header = Header {
+            parent_hash: parent.hash(),
+            number: parent.number + 1,
+            timestamp: parent.timestamp + block_time,
+            beneficiary: test_beneficiary(),
+            difficulty: U256::from(1),
+            gas_limit: parent.gas_limit,
+            ommers_hash: EMPTY_OMMER_ROOT,
+            transactions_root: EMPTY_ROOT,
+            receipts_root: EMPTY_ROOT,
+            ..Default::default()
+        }
+        .seal_slow();
+        headers.push(header.clone());
+        parent = header;
+    }
+    headers
+}
+
+/// Store the headers as the canonical chain along with their empty bodies, and save the progress
+/// of both stages.
+pub(crate) fn insert_empty_blocks<DB: Database>(
+    headers_db: &DB,
+    bodies_db: &DB,
+    headers: &[SealedHeader],
+) -> eyre::Result<()> {
+    let blocks = headers.iter().map(|header| (header.clone(), Vec::new())).collect::<Vec<_>>();
+    insert_blocks(headers_db, bodies_db, &blocks)
+}
+
+/// Store the headers as the canonical chain along with the bodies of their transactions, and save
+/// the progress of both stages.
+pub(crate) fn insert_blocks<DB: Database>(
+    headers_db: &DB,
+    bodies_db: &DB,
+    blocks: &[(SealedHeader, Vec<TransactionSigned>)],
+) -> eyre::Result<()> {
+    let Some(last) = blocks.last().map(|(header, _)| header.number) else { return Ok(()) };
+    headers_db.update(|tx| {
+        for (header, _) in blocks {
+            tx.put::<tables::CanonicalHeaders>(header.number, header.hash())?;
+            tx.put::<tables::Headers>(header.number, header.clone().unseal())?;
+        }
+        HEADERS.save_progress(tx, last)
+    })??;
+    bodies_db.update(|tx| {
+        let mut next_tx_id = 0;
+        for (header, transactions) in blocks {
+            let body =
+                StoredBlockBody { start_tx_id: next_tx_id, tx_count: transactions.len() as u64 };
+            tx.put::<tables::BlockBodies>(header.number, body)?;
+            for transaction in transactions {
+                tx.put::<tables::Transactions>(next_tx_id, transaction.clone())?;
+                next_tx_id += 1;
+            }
+        }
+        BODIES.save_progress(tx, last)
+    })??;
+    Ok(())
+}
+
+/// The header downloader that yields the canned batches in order, regardless of the sync gap.
+#[derive(Debug, Default)]
+pub(crate) struct TestHeaderDownloader {
+    batches: VecDeque<Vec<SealedHeader>>,
+}
+
+impl TestHeaderDownloader {
+    /// Create the downloader of the batches, each ordered from the highest header like the
+    /// reverse download.
+    pub(crate) fn new(batches: impl IntoIterator<Item = Vec<SealedHeader>>) -> Self {
+        Self { batches: batches.into_iter().collect() }
+    }
+}
+
+impl HeaderDownloader for TestHeaderDownloader {
+    fn update_local_head(&mut self, _head: SealedHeader) {}
+
+    fn update_sync_target(&mut self, _target: SyncTarget) {}
+
+    fn set_batch_size(&mut self, _limit: usize) {}
+}
+
+impl Stream for TestHeaderDownloader {
+    type Item = Vec<SealedHeader>;
+
+    fn poll_next(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Poll::Ready(self.batches.pop_front())
+    }
+}
+
+/// The body downloader that yields the canned responses of the download range in batches of
+/// `batch_size`. The stream ends at the first block without a response.
+#[derive(Debug)]
+pub(crate) struct TestBodyDownloader {
+    responses: BTreeMap<BlockNumber, BlockResponse>,
+    range: Range<BlockNumber>,
+    batch_size: u64,
+}
+
+impl TestBodyDownloader {
+    pub(crate) fn new(responses: impl IntoIterator<Item = BlockResponse>, batch_size: u64) -> Self {
+        let responses = responses.into_iter().map(|response| (response.block_number(), response));
+        Self { responses: responses.collect(), range: 0..0, batch_size }
+    }
+}
+
+impl BodyDownloader for TestBodyDownloader {
+    fn set_download_range(&mut self, range: Range<BlockNumber>) -> DownloadResult<()> {
+        self.range = range;
+        Ok(())
+    }
+}
+
+impl Stream for TestBodyDownloader {
+    type Item = DownloadResult<Vec<BlockResponse>>;
+
+    fn poll_next(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        let end = this.range.end.min(this.range.start + this.batch_size);
+        let batch = (this.range.start..end)
+            .map_while(|number| this.responses.get(&number).cloned())
+            .collect::<Vec<_>>();
+        if batch.is_empty() {
+            return Poll::Ready(None)
+        }
+        this.range.start += batch.len() as u64;
+        Poll::Ready(Some(Ok(batch)))
+    }
+}
@@ -0,0 +1,66 @@
+use reth_executor::execution_result::AccountInfoChangeSet;
+use reth_primitives::{Address, BlockNumber, H256, U256};
+use serde_json::json;
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+/// Writes the state changes of the executed transactions as JSON lines, one per changed account.
+///
+/// The block rewards are written with a `null` transaction index.
+pub struct ExecutionTrace {
+    writer: Mutex<BufWriter<File>>,
+}
+
+impl ExecutionTrace {
+    /// Create the trace file, truncating the existing one.
+    pub fn create(path: &Path) -> eyre::Result<Self> {
+        Ok(Self { writer: Mutex::new(BufWriter::new(File::create(path)?)) })
+    }
+
+    /// Record the changes of a single account.
+    pub fn record(
+        &self,
+        block: BlockNumber,
+        tx_index: Option<usize>,
+        address: Address,
+        account: &AccountInfoChangeSet,
+        wipe_storage: bool,
+        storage: &[(H256, U256, U256)],
+    ) -> eyre::Result<()> {
+        let account = match account {
+            AccountInfoChangeSet::Changed { old, new } => {
+                json!({ "kind": "changed", "old": old, "new": new })
+            }
+            AccountInfoChangeSet::Created { new } => json!({ "kind": "created", "new": new }),
+            AccountInfoChangeSet::Destroyed { old } => json!({ "kind": "destroyed", "old": old }),
+            AccountInfoChangeSet::NoChange => json!({ "kind": "unchanged" }),
+        };
+        let storage = storage
+            .iter()
+            .map(|(key, old, new)| json!({ "key": key, "old": old, "new": new }))
+            .collect::<Vec<_>>();
+        let line = json!({
+            "block": block,
+            "tx_index": tx_index,
+            "address": address,
+            "account": account,
+            "wipe_storage": wipe_storage,
+            "storage": storage,
+        });
+
+        let mut writer = self.writer.lock().expect("not poisoned");
+        serde_json::to_writer(&mut *writer, &line)?;
+        writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    /// Flush the buffered lines to the file.
+    pub fn flush(&self) -> eyre::Result<()> {
+        self.writer.lock().expect("not poisoned").flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,486 @@
+use crate::{
+    compression::{spawn_compress_file, CompressionFormat},
+    database::{
+        Checksum, ChecksumAlgorithm, HeadersDiffKey, Snapshot, SnapshotContents, SnapshotManifest,
+        Stage, StateDiffKey, MDBX_DAT,
+    },
+    remote::RemoteStore,
+};
+use flate2::Compression;
+use futures::{StreamExt, TryStreamExt};
+use itertools::Itertools;
+use reth_primitives::BlockNumber;
+use serde::{Deserialize, Serialize};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use tempfile::NamedTempFile;
+use tokio::{
+    sync::{mpsc, Semaphore},
+    task::{JoinHandle, JoinSet},
+};
+
+/// The number of compressed snapshots that can be queued while the maximum number of them is
+/// being uploaded. Once the queue is full, the sync waits for an upload to finish before
+/// compressing the next one.
+pub const UPLOAD_QUEUE_CAPACITY: usize = 1;
+
+/// The default number of snapshots uploaded at the same time.
+pub const DEFAULT_MAX_CONCURRENT_UPLOADS: usize = 2;
+
+/// The default number of previous snapshot entries deleted concurrently after an upload.
+pub const DEFAULT_CLEANUP_CONCURRENCY: usize = 8;
+
+/// The default gzip compression level of the database snapshots.
+pub const DEFAULT_COMPRESSION_LEVEL: u32 = 6;
+
+/// The default minimum age of the previous snapshot entries deleted after an upload.
+pub const DEFAULT_CLEANUP_GRACE_PERIOD: Duration = Duration::from_secs(60 * 60);
+
+/// How many of the state snapshots are kept in the remote store.
+///
+/// The state snapshots are uploaded at every interval, unlike the headers and bodies ones that
+/// replace the previous snapshot, so they pile up unless they are evicted.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetentionPolicy {
+    /// The number of the newest state snapshots kept. All are kept if not set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keep: Option<usize>,
+}
+
+impl RetentionPolicy {
+    /// Override the number of the kept state snapshots.
+    pub fn with_keep(mut self, keep: Option<usize>) -> Self {
+        self.keep = keep.or(self.keep);
+        self
+    }
+}
+
+/// A compressed snapshot waiting to be uploaded.
+struct UploadJob {
+    /// The remote key of the snapshot.
+    key: String,
+    /// The compressed database file. Removed from disk once the job is dropped.
+    compressed: NamedTempFile,
+    /// The stage whose other snapshots are removed after a successful upload.
+    cleanup_stage: Option<Stage>,
+    /// The entry counts published along with the manifest.
+    contents: SnapshotContents,
+    /// The checksum of the database file, if it is a database snapshot.
+    checksum: Option<Checksum>,
+    /// The format the file is compressed in.
+    format: CompressionFormat,
+    /// The number of the newest state snapshots kept once this one is uploaded and verified.
+    retain: Option<usize>,
+}
+
+/// Uploads snapshots on a background task, so that the sync can continue while the previous
+/// snapshot is being transferred.
+///
+/// The database file is compressed before the job is queued, since it keeps changing as soon as
+/// the sync resumes.
+pub struct SnapshotUploader {
+    remote: RemoteStore,
+    jobs: mpsc::Sender<UploadJob>,
+    handle: JoinHandle<eyre::Result<()>>,
+    checksum_algorithm: ChecksumAlgorithm,
+    compression: Compression,
+    compression_threads: usize,
+    temp_dir: Option<PathBuf>,
+    retention: RetentionPolicy,
+}
+
+impl SnapshotUploader {
+    /// Spawn the upload task. The snapshots are published along with the manifest.
+    ///
+    /// If the archive directory is set, a copy of every uploaded file is kept there under its
+    /// remote key. Up to `max_concurrent_uploads` snapshots of any stage are uploaded at a time,
+    /// the rest wait in the queue. Up to `cleanup_concurrency` previous snapshot entries are
+    /// deleted at a time.
+    /// The previous entries modified within the `cleanup_grace_period` are kept, since another
+    /// process might still be restoring from them. They are deleted by a later cleanup.
+    pub fn spawn(
+        remote: RemoteStore,
+        capacity: usize,
+        max_concurrent_uploads: usize,
+        manifest: SnapshotManifest,
+        archive_dir: Option<PathBuf>,
+        cleanup_concurrency: usize,
+        cleanup_grace_period: Duration,
+    ) -> Self {
+        let (jobs, rx) = mpsc::channel(capacity);
+        let uploads = Uploads {
+            remote: remote.clone(),
+            manifest,
+            archive_dir,
+            cleanup_concurrency: cleanup_concurrency.max(1),
+            cleanup_grace_period,
+        };
+        let handle = tokio::spawn(run_uploads(uploads, rx, max_concurrent_uploads.max(1)));
+        Self {
+            remote,
+            jobs,
+            handle,
+            checksum_algorithm: ChecksumAlgorithm::default(),
+            compression: Compression::new(DEFAULT_COMPRESSION_LEVEL),
+            compression_threads: 1,
+            temp_dir: None,
+            retention: RetentionPolicy::default(),
+        }
+    }
+
+    /// Set the gzip compression level of the database snapshots, from 0 to 9.
+    pub fn with_compression_level(mut self, level: u32) -> Self {
+        self.compression = Compression::new(level.min(9));
+        self
+    }
+
+    /// Set the hash function of the database checksums published along with the snapshots.
+    pub fn with_checksum_algorithm(mut self, checksum_algorithm: ChecksumAlgorithm) -> Self {
+        self.checksum_algorithm = checksum_algorithm;
+        self
+    }
+
+    /// Returns the remote store the snapshots are uploaded to.
+    pub fn remote(&self) -> &RemoteStore {
+        &self.remote
+    }
+
+    /// Compress the database snapshots with multiple threads, into the format that the restore
+    /// can decompress in parallel. With a single thread, the snapshots are compressed into a
+    /// single gzip member, which the previous releases can restore as well.
+    pub fn with_compression_threads(mut self, threads: usize) -> Self {
+        self.compression_threads = threads.max(1);
+        self
+    }
+
+    /// Set the directory the database snapshots are compressed into before the upload.
+    /// Defaults to the parent directory of the database, which is on the same filesystem.
+    pub fn with_temp_dir(mut self, temp_dir: Option<PathBuf>) -> Self {
+        self.temp_dir = temp_dir;
+        self
+    }
+
+    /// Evict the older state snapshots according to the policy. Every new state snapshot is
+    /// downloaded and verified against its checksum first, so that there is always a valid one.
+    pub fn with_retention(mut self, retention: RetentionPolicy) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    /// Returns the directory the snapshot of the database at `path` is compressed into.
+    pub fn temp_dir(&self, path: &Path) -> PathBuf {
+        self.temp_dir.clone().unwrap_or_else(|| path.parent().unwrap_or(path).to_owned())
+    }
+
+    /// Compress the database at `path` and queue it for upload as the snapshot.
+    pub async fn upload(
+        &mut self,
+        snapshot: Snapshot,
+        path: &Path,
+        contents: SnapshotContents,
+    ) -> eyre::Result<()> {
+        let retain = self.retention.keep.filter(|_| snapshot.stage == Stage::State);
+        self.queue(snapshot.key(), path, None, contents, retain).await
+    }
+
+    /// Compress the database at `path` and queue it for upload as the only snapshot of the
+    /// `stage`. Any previous snapshot entries are removed once the upload succeeds.
+    pub async fn upload_single(
+        &mut self,
+        stage: Stage,
+        path: &Path,
+        progress: BlockNumber,
+        contents: SnapshotContents,
+    ) -> eyre::Result<()> {
+        let key = Snapshot::new(stage, progress).key();
+        self.queue(key, path, Some(stage), contents, None).await
+    }
+
+    /// Queue an already compressed file for upload under `key`.
+    pub async fn upload_compressed(
+        &mut self,
+        key: String,
+        compressed: NamedTempFile,
+    ) -> eyre::Result<()> {
+        let contents = SnapshotContents::default();
+        self.send(UploadJob {
+            key,
+            compressed,
+            cleanup_stage: None,
+            contents,
+            checksum: None,
+            format: CompressionFormat::Gzip,
+            retain: None,
+        })
+        .await
+    }
+
+    /// Wait for all pending uploads to complete.
+    pub async fn finish(self) -> eyre::Result<()> {
+        drop(self.jobs);
+        self.handle.await?
+    }
+
+    async fn queue(
+        &mut self,
+        key: String,
+        path: &Path,
+        cleanup_stage: Option<Stage>,
+        contents: SnapshotContents,
+        retain: Option<usize>,
+    ) -> eyre::Result<()> {
+        // Snapshots are immutable once uploaded, skip the ones that have already been published.
+        if self.remote.exists(&key).await? {
+            tracing::info!(target: "sync::uploader", %key, "Snapshot already exists, skipping upload");
+            return Ok(())
+        }
+
+        // The database file is compressed off the runtime threads. The sync still waits for it,
+        // since the file must not change until it is compressed.
+        tracing::trace!(target: "sync::uploader", %key, "Compressing snapshot");
+        let database = path.join(MDBX_DAT);
+        let temp_dir = self.temp_dir(path);
+        let (compressed, format) = spawn_compress_file(
+            database.clone(),
+            temp_dir,
+            self.compression,
+            self.compression_threads,
+        )
+        .join()
+        .await?;
+        let algorithm = self.checksum_algorithm;
+        let checksum =
+            tokio::task::spawn_blocking(move || Checksum::compute(algorithm, &database)).await??;
+
+        self.send(UploadJob {
+            key,
+            compressed,
+            cleanup_stage,
+            contents,
+            checksum: Some(checksum),
+            format,
+            retain,
+        })
+        .await
+    }
+
+    async fn send(&mut self, job: UploadJob) -> eyre::Result<()> {
+        tracing::trace!(target: "sync::uploader", key = %job.key, "Queueing snapshot upload");
+        if self.jobs.send(job).await.is_err() {
+            // The upload task exits early only if one of the uploads has failed.
+            (&mut self.handle).await??;
+            eyre::bail!("snapshot upload task has exited")
+        }
+        Ok(())
+    }
+}
+
+/// Run the queued uploads, up to `max_concurrent_uploads` at a time.
+/// Exits on the first failed upload.
+async fn run_uploads(
+    uploads: Uploads,
+    mut jobs: mpsc::Receiver<UploadJob>,
+    max_concurrent_uploads: usize,
+) -> eyre::Result<()> {
+    let uploads = Arc::new(uploads);
+    let permits = Arc::new(Semaphore::new(max_concurrent_uploads));
+    let mut running = JoinSet::new();
+    loop {
+        // The next job is only taken off the queue once a permit is available, so that the sync
+        // waits for the uploads to catch up once the queue is full.
+        let permit = tokio::select! {
+            Some(result) = running.join_next() => {
+                result??;
+                continue
+            }
+            permit = Arc::clone(&permits).acquire_owned() => permit?,
+        };
+        let job = tokio::select! {
+            Some(result) = running.join_next() => {
+                result??;
+                continue
+            }
+            job = jobs.recv() => job,
+        };
+        let Some(job) = job else { break };
+
+        let uploads = Arc::clone(&uploads);
+        running.spawn(async move {
+            let result = uploads.upload(job).await;
+            drop(permit);
+            result
+        });
+    }
+
+    while let Some(result) = running.join_next().await {
+        result??;
+    }
+    Ok(())
+}
+
+/// The settings shared by the concurrent uploads.
+struct Uploads {
+    remote: RemoteStore,
+    manifest: SnapshotManifest,
+    archive_dir: Option<PathBuf>,
+    cleanup_concurrency: usize,
+    cleanup_grace_period: Duration,
+}
+
+impl Uploads {
+    async fn upload(&self, job: UploadJob) -> eyre::Result<()> {
+        let Self { remote, manifest, archive_dir, cleanup_concurrency, cleanup_grace_period } =
+            self;
+        let UploadJob { key, compressed, cleanup_stage, contents, checksum, format, retain } = job;
+
+        tracing::trace!(target: "sync::uploader", %key, %contents, "Uploading snapshot");
+        let mut metadata = manifest.to_metadata();
+        metadata.extend(contents.to_metadata());
+        metadata.extend(checksum.iter().flat_map(Checksum::to_metadata));
+        metadata.extend(format.to_metadata());
+        if remote.save(&key, compressed.path(), metadata).await? {
+            tracing::info!(target: "sync::uploader", %key, "Snapshot uploaded");
+        } else {
+            let size = compressed.as_file().metadata()?.len();
+            tracing::info!(target: "sync::uploader", %key, saved_bytes = size, "Snapshot unchanged, skipped upload");
+        }
+
+        if let Some(dir) = archive_dir {
+            let archived = dir.join(&key);
+            // The snapshot is copied off the runtime threads, so that the other uploads proceed.
+            let (source, destination) = (compressed.path().to_path_buf(), archived.clone());
+            tokio::task::spawn_blocking(move || -> std::io::Result<()> {
+                if let Some(parent) = destination.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                std::fs::copy(source, destination)?;
+                Ok(())
+            })
+            .await??;
+            tracing::trace!(target: "sync::uploader", %key, path = %archived.display(), "Snapshot archived");
+        }
+
+        if let Some(stage) = cleanup_stage {
+            // Clean up any previous snapshot entries
+            let now = SystemTime::now();
+            let stale = remote.list(Some(stage.prefix())).await?.into_iter().filter(|entry| {
+                let entry_key = entry.key.as_str();
+                // Skip any entries that are not snapshots of the same stage
+                let is_snapshot = Snapshot::parse_for(entry_key, stage).is_some();
+                // Headers diffs are relative to the previous base snapshot
+                let is_diff = HeadersDiffKey::parse(entry_key).is_some();
+                if !(is_snapshot || is_diff) || entry_key == key {
+                    return false
+                }
+                // Keep the recent entries another process might be restoring from
+                let age = entry.last_modified.and_then(|time| now.duration_since(time).ok());
+                if age.map_or(false, |age| age < *cleanup_grace_period) {
+                    tracing::debug!(target: "sync::uploader", key = %entry.key, ?age, "Keeping recent snapshot entry");
+                    return false
+                }
+                true
+            });
+            futures::stream::iter(stale)
+                .map(|entry| {
+                    let remote = &remote;
+                    async move { remote.delete(&entry.key).await }
+                })
+                .buffer_unordered(*cleanup_concurrency)
+                .try_collect::<()>()
+                .await?;
+        }
+
+        if let Some(keep) = retain {
+            match checksum {
+                Some(checksum) => {
+                    self.evict_state(&key, keep, &checksum, format, compressed.path()).await?
+                }
+                None => {
+                    tracing::warn!(target: "sync::uploader", %key, "Snapshot has no checksum, keeping the older state snapshots")
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Download the uploaded snapshot next to the compressed file and verify its checksum.
+    async fn verify_upload(
+        &self,
+        key: &str,
+        checksum: &Checksum,
+        format: CompressionFormat,
+        compressed: &Path,
+    ) -> eyre::Result<()> {
+        tracing::trace!(target: "sync::uploader", %key, "Verifying uploaded snapshot");
+        let dir = compressed.parent().unwrap_or(compressed);
+        let downloaded = NamedTempFile::new_in(dir)?;
+        let threads = if format.is_parallel() {
+            std::thread::available_parallelism().map_or(1, |threads| threads.get())
+        } else {
+            1
+        };
+        if !self.remote.retrieve_to(key, downloaded.path(), threads).await? {
+            eyre::bail!("Snapshot {key} was removed before it could be verified")
+        }
+        let (owned_key, checksum) = (key.to_owned(), checksum.clone());
+        tokio::task::spawn_blocking(move || checksum.verify(&owned_key, downloaded.path()))
+            .await??;
+        tracing::info!(target: "sync::uploader", %key, "Verified uploaded snapshot");
+        Ok(())
+    }
+
+    /// Delete the state snapshots older than the `keep - 1` ones preceding the uploaded snapshot,
+    /// along with the state diffs that only apply on top of them. The uploaded snapshot is
+    /// verified first, unless there is nothing to delete.
+    async fn evict_state(
+        &self,
+        key: &str,
+        keep: usize,
+        checksum: &Checksum,
+        format: CompressionFormat,
+        compressed: &Path,
+    ) -> eyre::Result<()> {
+        let Some(uploaded) = Snapshot::parse_for(key, Stage::State) else { return Ok(()) };
+        let entries = self.remote.list(Some(Stage::State.prefix())).await?;
+        let older = entries
+            .iter()
+            .filter_map(|entry| Some((entry, Snapshot::parse_for(&entry.key, Stage::State)?)))
+            .filter(|(_, snapshot)| snapshot.block < uploaded.block)
+            .sorted_by_key(|(_, snapshot)| std::cmp::Reverse(snapshot.block))
+            .collect::<Vec<_>>();
+        // The uploaded snapshot is one of the kept ones.
+        let (kept, evicted) = older.split_at(keep.saturating_sub(1).min(older.len()));
+        let oldest_kept = kept.last().map_or(uploaded.block, |(_, snapshot)| snapshot.block);
+
+        let now = SystemTime::now();
+        let stale = evicted
+            .iter()
+            .map(|(entry, _)| *entry)
+            .chain(entries.iter().filter(|entry| {
+                StateDiffKey::parse(&entry.key).map_or(false, |diff| diff.to <= oldest_kept)
+            }))
+            .filter(|entry| {
+                // Keep the recent entries another process might be restoring from
+                let age = entry.last_modified.and_then(|time| now.duration_since(time).ok());
+                age.map_or(true, |age| age >= self.cleanup_grace_period)
+            })
+            .collect::<Vec<_>>();
+        if stale.is_empty() {
+            return Ok(())
+        }
+
+        self.verify_upload(key, checksum, format, compressed).await?;
+        futures::stream::iter(stale)
+            .map(|entry| async move {
+                tracing::debug!(target: "sync::uploader", key = %entry.key, "Evicting state snapshot entry");
+                self.remote.delete(&entry.key).await
+            })
+            .buffer_unordered(self.cleanup_concurrency)
+            .try_collect::<()>()
+            .await
+    }
+}